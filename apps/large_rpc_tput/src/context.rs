@@ -116,8 +116,8 @@ impl Default for AppContext {
             stat_rx_bytes_tot: 0,
             stat_tx_bytes_tot: 0,
             req_ts: [0; K_APP_MAX_CONCURRENCY],
-            req_msgbuf: MaybeUninit::uninit_array(),
-            resp_msgbuf: MaybeUninit::uninit_array(),
+            req_msgbuf: std::array::from_fn(|_| MaybeUninit::uninit()),
+            resp_msgbuf: std::array::from_fn(|_| MaybeUninit::uninit()),
             msgbuf_nr: 0,
             args_req_size: 0,
             args_resp_size: 0,
@@ -1,8 +1,5 @@
 // Copyright (c) 2023, IOMesh Inc. All rights reserved.
 
-#![feature(sort_floats)]
-#![feature(maybe_uninit_uninit_array)]
-
 extern crate core_affinity;
 
 mod cli;
@@ -20,7 +17,7 @@ use std::{
     mem::MaybeUninit,
     sync::{
         atomic::{AtomicBool, Ordering},
-        Arc, Mutex,
+        Arc,
     },
     thread,
 };
@@ -99,7 +96,7 @@ extern "C" fn basic_sm_handler(
             sm_err_type_str(sm_err_type),
             unsafe { c.base.rpc.assume_init_mut() }.sec_since_creation()
         );
-        eprintln!("{}", verbose);
+        tracing::info!("{}", verbose);
     }
 }
 
@@ -190,7 +187,7 @@ fn send_req(c: &mut AppContext, idx: usize) {
 fn thread_func(
     thread_id: usize,
     args: Arc<Args>,
-    nexus: Arc<Mutex<Nexus>>,
+    nexus: Arc<Nexus>,
     ctrl_c_pressed: Arc<AtomicBool>,
 ) {
     let mut c = AppContext::default();
@@ -206,16 +203,13 @@ fn thread_func(
     }
     let phy_port = *ports.get(thread_id % ports.len()).unwrap();
 
-    let mut rpc = {
-        let mut nexus = nexus.lock().unwrap();
-        Rpc::new(
-            &mut nexus,
-            Some(&mut c as *mut AppContext as *mut c_void),
-            thread_id as u8,
-            Some(basic_sm_handler),
-            phy_port,
-        )
-    };
+    let mut rpc = Rpc::new(
+        &nexus,
+        Some(&mut c as *mut AppContext as *mut c_void),
+        thread_id as u8,
+        Some(basic_sm_handler),
+        phy_port,
+    );
     rpc.force_retry_connect_on_invalid_rpc_id();
 
     unsafe {
@@ -229,12 +223,12 @@ fn thread_func(
         CONNECT_SESSIONS_FUNC.assume_init()(&mut c, args.clone(), ctrl_c_pressed.clone());
     }
     if !c.base.session_num_vec.is_empty() {
-        println!(
+        tracing::info!(
             "large_rpc_tput: Thread {}: All sessions connected.\n",
             thread_id
         );
     } else {
-        println!(
+        tracing::info!(
             "large_rpc_tput: Thread {}: No sessions created.\n",
             thread_id,
         );
@@ -285,7 +279,7 @@ fn thread_func(
         c.app_stats.rtt_99_us = timely_0.get_rtt_perc(0.99);
 
         if !c.lat_vec.is_empty() {
-            c.lat_vec.sort_floats();
+            c.lat_vec.sort_unstable_by(f64::total_cmp);
             c.app_stats.rpc_50_us = c.lat_vec[(c.lat_vec.len() as f64 * 0.5).floor() as usize];
             c.app_stats.rpc_99_us = c.lat_vec[(c.lat_vec.len() as f64 * 0.99).floor() as usize];
             c.app_stats.rpc_999_us = c.lat_vec[(c.lat_vec.len() as f64 * 0.999).floor() as usize];
@@ -315,7 +309,7 @@ fn thread_func(
             timely_0.get_rate_gbps(),
             kSessionCredits
         );
-        println!("{}", stats);
+        tracing::info!("{}", stats);
 
         // Reset stats for next iteration
         c.stat_rx_bytes_tot = 0;
@@ -332,18 +326,17 @@ fn thread_func(
     }
 
     let mut wheel = unsafe { c.base.rpc.assume_init_mut().get_wheel() };
-    if !wheel.is_none() && !wheel.get_record_vec().is_empty() {
+    if let Some(mut records) = wheel.get_record_vec().filter(|r| !r.is_empty()) {
         let num_to_print = 200_usize;
-        let tot_entries = wheel.get_record_vec().len();
+        let tot_entries = records.len();
         let base_entry = (tot_entries as f64 * 0.9).floor() as usize;
 
-        println!("Printing up to 200 entries toward the end of wheel record");
+        tracing::info!("Printing up to 200 entries toward the end of wheel record");
         let mut num_printed = 0_usize;
 
-        let mut records = wheel.get_record_vec();
         for i in base_entry..tot_entries {
             let record = records.as_mut().index_mut(i).unwrap();
-            println!(
+            tracing::info!(
                 "wheel: {}",
                 record
                     .to_string(console_ref_tsc, unsafe {
@@ -357,17 +350,43 @@ fn thread_func(
                 break;
             }
         }
+
+        let rpc = unsafe { c.base.rpc.assume_init_mut() };
+        let report = PacingReport::collect(rpc, &c.base.session_num_vec);
+        tracing::info!(
+            "large_rpc_tput: Thread {}: Wheel: {} inserted, {} reaped, occupancy {} (max {}). \
+             TX gap {{{} 50th, {} 99th}} ns.",
+            c.base.thread_id,
+            report.inserted,
+            report.reaped,
+            report.occupancy,
+            report.max_occupancy,
+            report.tx_gap.quantile(0.5),
+            report.tx_gap.quantile(0.99),
+        );
+        for s in &report.sessions {
+            tracing::info!(
+                "large_rpc_tput: Thread {}: Session {}: Timely rate {:.1} Gbps ({:.1}% of link). \
+                 Retransmissions {}.",
+                c.base.thread_id,
+                s.session_num.0,
+                s.rate_gbps,
+                s.link_share * 100.0,
+                s.re_tx,
+            );
+        }
     }
 }
 
 fn main() -> Result<()> {
     let ctrl_c_pressed: Arc<AtomicBool> = Arc::new(AtomicBool::new(false));
     signal_hook::flag::register(SIGINT, Arc::clone(&ctrl_c_pressed)).unwrap();
+    tracing_subscriber::fmt::init();
     let args = parse_args();
 
     setup_profile(&args);
 
-    let mut nexus = Nexus::new(
+    let nexus = Nexus::new(
         (*get_uri_for_process(args.process_id)).to_str().unwrap(),
         args.numa_node,
     );
@@ -380,7 +399,7 @@ fn main() -> Result<()> {
     } else {
         args.num_proc_other_threads
     };
-    let nexus = Arc::new(Mutex::new(nexus));
+    let nexus = Arc::new(nexus);
     let args = Arc::new(args);
     let core_ids = core_affinity::get_core_ids().unwrap();
     let mut handles = Vec::with_capacity(num_threads);
@@ -25,7 +25,7 @@ pub fn connect_sessions_func_incast(
 
     c.base.session_num_vec.resize(1, c_int(0));
 
-    println!(
+    tracing::info!(
         "large_rpc_tput: Thread: {}: Creating 1 session to proc 0, thread: {}",
         c.base.thread_id, rem_tid
     );
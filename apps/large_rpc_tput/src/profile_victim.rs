@@ -35,7 +35,7 @@ pub fn connect_sessions_func_victim(
 
     c.base.session_num_vec.resize(1, c_int(0));
 
-    println!(
+    tracing::info!(
         "large_rpc_tput: Thread {}: Creating session to proc {}, thread {}.",
         c.base.thread_id, server_process_id, rem_tid
     );
@@ -49,6 +49,12 @@ pub struct Args {
     /// Fraction of fair share to throttle to.
     #[arg(short, long, default_value_t = 1.0)]
     pub throttle_fraction: f64,
+    /// Also write the per-thread stats to this file
+    #[arg(long)]
+    pub stats_file: Option<String>,
+    /// Format of the stats file, "jsonl" or "csv"
+    #[arg(long, default_value = "jsonl")]
+    pub stats_format: String,
 }
 
 pub fn parse_args() -> Args {
@@ -62,6 +68,9 @@ pub fn parse_args() -> Args {
     if args.process_id >= args.num_processes {
         panic!("Invalid process ID");
     }
+    if args.stats_format != "jsonl" && args.stats_format != "csv" {
+        panic!("Invalid stats format");
+    }
     if args.drop_prob >= 1.0 {
         panic!("Invalid drop prob");
     }
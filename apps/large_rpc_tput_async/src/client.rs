@@ -1,6 +1,6 @@
 // Copyright (c) 2023, IOMesh Inc. All rights reserved.
 
-use std::sync::Arc;
+use std::{fs::File, io::BufWriter, sync::Arc};
 
 use erpc_rs::prelude::*;
 use tokio::signal;
@@ -8,24 +8,13 @@ use tokio::signal;
 use crate::{
     cli::Args,
     common::*,
-    largerpctput::{BenchClient, BenchRequest, METHOD_BENCH_SEND_REQUEST},
+    largerpctput::{BenchClient, BenchRequest},
 };
 
 extern "C" fn cont_func(ctx: *mut c_void, tag: *mut c_void) {
     let ctx = unsafe { &mut *(ctx as *mut ClientRpcContext) };
     let tag = unsafe { Box::from_raw(tag as *mut Tag) };
-    let (_, resp) = ctx
-        .resp_msgbufs
-        .get_mut(METHOD_BENCH_SEND_REQUEST.id as usize)
-        .unwrap()
-        .remove_entry(&tag.idx)
-        .unwrap();
-    let usec = to_usec(
-        rdtsc() - ctx.bench_stat.req_ts[tag.idx as usize % 32],
-        ctx.rpc.as_ref().unwrap().get_freq_ghz(),
-    );
-    ctx.bench_stat.lat_vec.push(usec);
-    let msg_buffer_reader = unsafe { MsgBufferReader::new(resp.as_inner() as *const RawMsgBuffer) };
+    let msg_buffer_reader = unsafe { MsgBufferReader::new(tag.resp_msgbuf()) };
     tag.tx.send_blocking(msg_buffer_reader).unwrap();
     ctx.bench_stat.stat_rx_bytes_tot += ctx.bench_stat.args_resp_size;
 }
@@ -36,18 +25,29 @@ pub async fn client_main(args: Args) -> Result<()> {
     let threads_nr = args.num_proc_other_threads;
     let env = Arc::new(EnvBuilder::new(local_uri).chan_count(threads_nr).build());
 
+    let stats_sink = args.stats_file.as_ref().map(|path| {
+        let w = BufWriter::new(File::create(path).unwrap());
+        let sink: Arc<dyn StatsSink> = if args.stats_format == "csv" {
+            Arc::new(CsvSink::new(w).unwrap())
+        } else {
+            Arc::new(JsonLinesSink::new(w))
+        };
+        sink
+    });
+
     let mut chs = Vec::new();
 
     for _i in 0..threads_nr {
-        let ch = ChannelBuilder::new(env.clone(), args.phy_port)
+        let mut builder = ChannelBuilder::new(env.clone(), args.phy_port)
             .subchan_count(1)
             .timeout_ms(K_APP_EV_LOOP_MS)
             .req_size(args.req_size)
-            .resp_size(args.resp_size)
-            .connect(server_uri.clone())
-            .await
-            .unwrap();
-        let mut client = BenchClient::new(ch.clone());
+            .resp_size(args.resp_size);
+        if let Some(sink) = &stats_sink {
+            builder = builder.stats_sink(sink.clone());
+        }
+        let ch = builder.connect(server_uri.clone()).await.unwrap();
+        let client = BenchClient::new(ch.clone());
 
         let mut req_msgbufs = Vec::new();
         let mut resp_msgbufs = Vec::new();
@@ -57,12 +57,14 @@ pub async fn client_main(args: Args) -> Result<()> {
         req.buf[0] = K_APP_DATA_BYTE;
 
         for _j in 0..args.concurrency {
-            let req_msgbuf = Arc::new(client.alloc_msg_buffer(args.req_size + 10));
-            let resp_msgbuf = Arc::new(client.alloc_msg_buffer(args.resp_size + 10));
+            let req_msgbuf = client.alloc_msg_buffer(args.req_size + 10).await?;
+            let resp_msgbuf = client.alloc_msg_buffer(args.resp_size + 10).await?;
 
             if req_msgbuf.get_data_size() != args.req_size {
                 panic!("allocated req_msgbuf's data size not eq arg's req_size");
             }
+            let req_msgbuf = SharedMsgBuffer::new(req_msgbuf);
+            let resp_msgbuf = SharedMsgBuffer::new(resp_msgbuf);
 
             req_msgbufs.push(req_msgbuf.clone());
             resp_msgbufs.push(resp_msgbuf.clone());
@@ -89,7 +91,7 @@ pub async fn client_main(args: Args) -> Result<()> {
         chs.push(ch);
     }
     signal::ctrl_c().await.unwrap();
-    eprintln!("Ctrl-c received!");
+    tracing::info!("Ctrl-c received!");
     for mut ch in chs {
         ch.shutdown().await.unwrap();
     }
@@ -13,13 +13,16 @@ pub struct BenchResponse {
 pub const METHOD_BENCH_SEND_REQUEST: ::erpc_rs::prelude::Method<BenchRequest, BenchResponse> =
     ::erpc_rs::prelude::Method {
         id: 1,
+        name: "/largerpctput.Bench/SendRequest",
         req_mar: ::erpc_rs::prelude::Marshaller {
             ser: ::erpc_rs::prelude::pr_ser,
             de: ::erpc_rs::prelude::pr_de,
+            trace_trailer: true,
         },
         resp_mar: ::erpc_rs::prelude::Marshaller {
             ser: ::erpc_rs::prelude::pr_ser,
             de: ::erpc_rs::prelude::pr_de,
+            trace_trailer: true,
         },
     };
 #[derive(Clone)]
@@ -35,16 +38,19 @@ impl BenchClient {
     pub async fn send_request(
         &self,
         req: &BenchRequest,
-        req_msgbuf: std::sync::Arc<::erpc_rs::prelude::MsgBuffer>,
-        resp_msgbuf: std::sync::Arc<::erpc_rs::prelude::MsgBuffer>,
+        req_msgbuf: ::erpc_rs::prelude::SharedMsgBuffer,
+        resp_msgbuf: ::erpc_rs::prelude::SharedMsgBuffer,
         cb: ::erpc_rs::prelude::ContFunc,
     ) -> ::erpc_rs::prelude::Result<BenchResponse> {
         self.client
             .unary_call(&METHOD_BENCH_SEND_REQUEST, req, req_msgbuf, resp_msgbuf, cb)
             .await
     }
-    pub fn alloc_msg_buffer(&mut self, max_data_size: usize) -> ::erpc_rs::prelude::MsgBuffer {
-        self.client.alloc_msg_buffer(max_data_size)
+    pub async fn alloc_msg_buffer(
+        &self,
+        max_data_size: usize,
+    ) -> ::erpc_rs::prelude::Result<::erpc_rs::prelude::MsgBuffer> {
+        self.client.alloc_msg_buffer(max_data_size).await
     }
 }
 #[async_trait::async_trait]
@@ -57,7 +63,6 @@ pub trait Bench: Send + 'static {
     }
     async fn send_request_async(
         _req: ::erpc_rs::prelude::ReqHandle,
-        _rcp: std::sync::Arc<::erpc_rs::prelude::Rpc>,
         _tx: ::async_channel::Sender<::erpc_rs::prelude::RpcCall>,
         _codec: ::erpc_rs::prelude::Codec<BenchRequest, BenchResponse>,
     ) {
@@ -70,8 +75,9 @@ extern "C" fn send_request_wrapper<S: Bench>(
 ) {
     let result = std::panic::catch_unwind(|| {
         let req = erpc_rs::prelude::ReqHandle::from_inner_raw(req);
-        let ctx = unsafe { &mut *(ctx as *mut ::erpc_rs::prelude::ServerRpcContext) };
-        S::send_request(req, ctx);
+        unsafe {
+            ::erpc_rs::prelude::ServerRpcContext::run_handler(ctx, |ctx| S::send_request(req, ctx));
+        }
     });
     if result.is_err() {
         std::process::abort();
@@ -84,7 +90,7 @@ pub fn create_bench<S: Bench + Send + Clone + 'static>() -> ::erpc_rs::prelude::
     let mut builder = ::erpc_rs::prelude::ServiceBuilder::new();
     builder = builder.add_unary_handler(
         &METHOD_BENCH_SEND_REQUEST,
-        move |req, rpc, tx, codec| std::boxed::Box::pin(S::send_request_async(req, rpc, tx, codec)),
+        move |req, tx, codec| std::boxed::Box::pin(S::send_request_async(req, tx, codec)),
         unsafe { send_request_wrapper_into::<S>() },
     );
     builder.build()
@@ -1,7 +1,5 @@
 // Copyright (c) 2023, IOMesh Inc. All rights reserved.
 
-#![feature(get_mut_unchecked)]
-
 mod cli;
 mod client;
 mod common;
@@ -14,6 +12,7 @@ use server::server_main;
 
 #[tokio::main]
 async fn main() {
+    tracing_subscriber::fmt::init();
     let args = parse_args();
     if args.process_id == 0 {
         server_main(args).await.unwrap();
@@ -18,36 +18,35 @@ struct BenchService;
 #[async_trait::async_trait]
 impl Bench for BenchService {
     fn send_request(req: ReqHandle, ctx: &'static mut ServerRpcContext) {
-        let rpc = ctx.rpc.clone();
         let tx = ctx.tx.clone();
         let f = ctx
             .get_handler(METHOD_BENCH_SEND_REQUEST.id)
             .unwrap()
-            .handle(req, rpc, tx);
+            .handle(req, tx);
         ctx.spawn(f);
     }
 
     async fn send_request_async(
         mut req_handle: ReqHandle,
-        mut rpc: Arc<Rpc>,
         tx: Sender<RpcCall>,
         codec: Codec<BenchRequest, BenchResponse>,
     ) {
         let msg_buffer_reader = unsafe { MsgBufferReader::new(req_handle.get_req_msgbuf()) };
         let req = (codec.de)(msg_buffer_reader).unwrap();
         let resp_byte = req.buf[0];
-        let mut resp_msgbuf = unsafe {
-            let rpc = Arc::get_mut_unchecked(&mut rpc);
-            // FIXME: c++ mutex will be called, may become a performance bottleneck in actual use
-            rpc.alloc_msg_buffer(32 + 10)
-        };
+        let mut resp_msgbuf = MsgBufferAllocator::new(tx.clone())
+            .alloc(32 + 10)
+            .await
+            .unwrap();
         let mut resp = BenchResponse { buf: vec![0; 32] };
         resp.buf[0] = resp_byte;
         (codec.ser)(&resp, &mut resp_msgbuf).unwrap();
-        req_handle.init_dyn_resp_msgbuf_from_allocated(&mut resp_msgbuf);
-        tx.send(RpcCall::CallTag(CallTag { req_handle }))
-            .await
-            .unwrap();
+        tx.send(RpcCall::CallTag(CallTag::with_resp_msgbuf(
+            req_handle,
+            resp_msgbuf,
+        )))
+        .await
+        .unwrap();
     }
 }
 
@@ -61,7 +60,7 @@ pub async fn server_main(args: Args) -> Result<()> {
         .await
         .unwrap();
     signal::ctrl_c().await.unwrap();
-    eprintln!("Ctrl-c received!");
+    tracing::info!("Ctrl-c received!");
     server.shutdown().await.unwrap();
 
     Ok(())
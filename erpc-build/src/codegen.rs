@@ -1,6 +1,7 @@
 // Copyright (c) 2023, IOMesh Inc. All rights reserved.
 
 use std::{
+    collections::HashSet,
     io::{Error, ErrorKind, Read},
     path::Path,
     sync::atomic::{AtomicU8, Ordering},
@@ -18,11 +19,24 @@ static METHOD_ID: AtomicU8 = AtomicU8::new(1);
 
 /// Returns the names of all packages compiled.
 pub fn compile_protos<P>(protos: &[P], includes: &[P], out_dir: &str) -> io::Result<Vec<String>>
+where
+    P: AsRef<Path>,
+{
+    compile_protos_with(protos, includes, out_dir, Generator::default())
+}
+
+/// Like [`compile_protos()`], with a configured [`Generator`].
+pub fn compile_protos_with<P>(
+    protos: &[P],
+    includes: &[P],
+    out_dir: &str,
+    generator: Generator,
+) -> io::Result<Vec<String>>
 where
     P: AsRef<Path>,
 {
     let mut prost_config = Config::new();
-    prost_config.service_generator(Box::new(Generator));
+    prost_config.service_generator(Box::new(generator));
     prost_config.out_dir(out_dir);
 
     // Create a file descriptor set for the protocol files.
@@ -92,25 +106,43 @@ where
 ///
 /// fn main() -> Result<(), Box<dyn std::error::Error>> {
 ///     let mut config = Config::new();
-///     config.service_generator(Box::new(Generator));
+///     config.service_generator(Box::new(Generator::default()));
 ///     // Modify config as needed
 ///     config.compile_protos(&["src/frontend.proto", "src/backend.proto"], &["src"])?;
 ///     Ok(())
 /// }
 /// ```
-pub struct Generator;
+#[derive(Default)]
+pub struct Generator {
+    bg_methods: HashSet<String>,
+}
+
+impl Generator {
+    /// Run the handler of `method`, e.g. `/helloworld.Greeter/SayHello`, on
+    /// eRPC's background threads, see `ServiceBuilder::add_unary_bg_handler`.
+    pub fn bg_method<S: Into<String>>(mut self, method: S) -> Generator {
+        self.bg_methods.insert(method.into());
+        self
+    }
+}
 
 impl ServiceGenerator for Generator {
     fn generate(&mut self, service: Service, buf: &mut String) {
+        let bg: HashSet<_> = service
+            .methods
+            .iter()
+            .filter(|m| self.bg_methods.contains(&full_method_name(&service, m)))
+            .map(|m| m.name.clone())
+            .collect();
         generate_methods(&service, buf);
         generate_client(&service, buf);
-        generate_server(&service, buf);
+        generate_server(&service, &bg, buf);
     }
 }
 
 fn generate_methods(service: &Service, buf: &mut String) {
     for method in &service.methods {
-        generate_method(&service.name, method, buf);
+        generate_method(service, method, buf);
     }
 }
 
@@ -122,8 +154,20 @@ fn const_method_name(service_name: &str, method: &Method) -> String {
     )
 }
 
-fn generate_method(service_name: &str, method: &Method, buf: &mut String) {
-    let name = const_method_name(service_name, method);
+/// The full name of `method`, e.g. `/helloworld.Greeter/SayHello`.
+fn full_method_name(service: &Service, method: &Method) -> String {
+    if service.package.is_empty() {
+        format!("/{}/{}", service.proto_name, method.proto_name)
+    } else {
+        format!(
+            "/{}.{}/{}",
+            service.package, service.proto_name, method.proto_name
+        )
+    }
+}
+
+fn generate_method(service: &Service, method: &Method, buf: &mut String) {
+    let name = const_method_name(&service.name, method);
     let ty = format!(
         "{}<{}, {}>",
         fq_erpc("Method"),
@@ -136,12 +180,12 @@ fn generate_method(service_name: &str, method: &Method, buf: &mut String) {
     buf.push_str(": ");
     buf.push_str(&ty);
     buf.push_str(" = ");
-    generate_method_body(buf);
+    generate_method_body(&full_method_name(service, method), buf);
 }
 
-fn generate_method_body(buf: &mut String) {
+fn generate_method_body(full_name: &str, buf: &mut String) {
     let pr_mar = format!(
-        "{} {{ ser: {}, de: {} }}",
+        "{} {{ ser: {}, de: {}, trace_trailer: true }}",
         fq_erpc("Marshaller"),
         fq_erpc("pr_ser"),
         fq_erpc("pr_de")
@@ -151,6 +195,7 @@ fn generate_method_body(buf: &mut String) {
     buf.push_str(&fq_erpc("Method"));
     buf.push('{');
     generate_field_init("id", &id.to_string(), buf);
+    generate_field_init("name", &format!("{:?}", full_name), buf);
     generate_field_init("req_mar", &pr_mar, buf);
     generate_field_init("resp_mar", &pr_mar, buf);
     buf.push_str("};\n");
@@ -228,11 +273,11 @@ impl<'a> ClientMethod<'a> {
             buf.push_str(", req: &");
             buf.push_str(req);
         }
-        buf.push_str(", req_msgbuf: std::sync::Arc<");
-        buf.push_str(&fq_erpc("MsgBuffer"));
-        buf.push_str(">, resp_msgbuf: std::sync::Arc<");
-        buf.push_str(&fq_erpc("MsgBuffer"));
-        buf.push_str(">, cb: ");
+        buf.push_str(", req_msgbuf: ");
+        buf.push_str(&fq_erpc("SharedMsgBuffer"));
+        buf.push_str(", resp_msgbuf: ");
+        buf.push_str(&fq_erpc("SharedMsgBuffer"));
+        buf.push_str(", cb: ");
         buf.push_str(&fq_erpc("ContFunc"));
         buf.push_str(") -> ");
 
@@ -267,21 +312,24 @@ impl<'a> ClientMethod<'a> {
 }
 
 fn generate_client_alloc_msg_buffer(buf: &mut String) {
-    buf.push_str("pub fn alloc_msg_buffer(&mut self, max_data_size: usize) -> ");
+    buf.push_str("pub async fn alloc_msg_buffer(&self, max_data_size: usize) -> ");
+    buf.push_str(&fq_erpc("Result"));
+    buf.push('<');
     buf.push_str(&fq_erpc("MsgBuffer"));
-    buf.push_str("{\n");
-    buf.push_str("self.client.alloc_msg_buffer(max_data_size)\n");
+    buf.push_str("> {\n");
+    buf.push_str("self.client.alloc_msg_buffer(max_data_size).await\n");
     buf.push_str("}\n");
 }
 
-fn generate_server(service: &Service, buf: &mut String) {
+/// `bg` holds the names of the methods run on eRPC's background threads.
+fn generate_server(service: &Service, bg: &HashSet<String>, buf: &mut String) {
     buf.push_str("#[async_trait::async_trait]\n");
     buf.push_str("pub trait ");
     buf.push_str(&service.name);
     buf.push_str(": Send + 'static {\n");
     generate_server_methods(service, buf);
     buf.push_str("}\n");
-    generate_server_method_wrappers(service, buf);
+    generate_server_method_wrappers(service, bg, buf);
 
     buf.push_str("pub fn create_");
     buf.push_str(&to_snake_case(&service.name));
@@ -293,7 +341,7 @@ fn generate_server(service: &Service, buf: &mut String) {
     buf.push_str("let mut builder = ::erpc_rs::prelude::ServiceBuilder::new();\n");
 
     for method in &service.methods {
-        generate_method_bind(&service.name, method, buf);
+        generate_method_bind(&service.name, method, bg.contains(&method.name), buf);
     }
 
     buf.push_str("builder.build()\n");
@@ -323,9 +371,7 @@ fn generate_server_method(method: &Method, buf: &mut String, sync: bool) {
         buf.push_str(&fq_erpc("ServerRpcContext"));
     }
     if !sync {
-        buf.push_str(", _rcp: std::sync::Arc<");
-        buf.push_str(&fq_erpc("Rpc"));
-        buf.push_str(">, _tx: ::async_channel::Sender<");
+        buf.push_str(", _tx: ::async_channel::Sender<");
         buf.push_str(&fq_erpc("RpcCall"));
         buf.push_str(">, _codec: ");
         buf.push_str(&fq_erpc("Codec"));
@@ -335,13 +381,13 @@ fn generate_server_method(method: &Method, buf: &mut String, sync: bool) {
     buf.push_str(") { unimplemented!() }\n");
 }
 
-fn generate_server_method_wrappers(service: &Service, buf: &mut String) {
+fn generate_server_method_wrappers(service: &Service, bg: &HashSet<String>, buf: &mut String) {
     for method in &service.methods {
-        generate_server_method_wrapper(&service.name, method, buf);
+        generate_server_method_wrapper(&service.name, method, bg.contains(&method.name), buf);
     }
 }
 
-fn generate_server_method_wrapper(srv_name: &str, method: &Method, buf: &mut String) {
+fn generate_server_method_wrapper(srv_name: &str, method: &Method, bg: bool, buf: &mut String) {
     buf.push_str("extern \"C\" fn ");
     buf.push_str(&method.name);
     buf.push_str("_wrapper");
@@ -350,7 +396,11 @@ fn generate_server_method_wrapper(srv_name: &str, method: &Method, buf: &mut Str
     buf.push_str(">(req: *mut");
     buf.push_str(&fq_erpc("RawReqHandle"));
     buf.push_str(", ctx: *mut erpc_rs::prelude::c_void) {\n");
-    generate_wrapper_inner_body(method, buf);
+    if bg {
+        generate_bg_wrapper_inner_body(srv_name, method, buf);
+    } else {
+        generate_wrapper_inner_body(method, buf);
+    }
     buf.push_str("}\n");
     buf.push_str("unsafe fn ");
     buf.push_str(&method.name);
@@ -368,28 +418,47 @@ fn generate_server_method_wrapper(srv_name: &str, method: &Method, buf: &mut Str
 fn generate_wrapper_inner_body(method: &Method, buf: &mut String) {
     buf.push_str("let result = std::panic::catch_unwind(|| {\n");
     buf.push_str("let req = erpc_rs::prelude::ReqHandle::from_inner_raw(req);\n");
-    buf.push_str(
-        "let ctx = unsafe { &mut *(ctx as *mut ::erpc_rs::prelude::ServerRpcContext) };\n",
-    );
-    buf.push_str("S::");
+    buf.push_str("unsafe {\n");
+    buf.push_str("::erpc_rs::prelude::ServerRpcContext::run_handler(ctx, |ctx| S::");
     buf.push_str(&method.name);
-    buf.push_str("(req, ctx);\n");
+    buf.push_str("(req, ctx));\n");
+    buf.push_str("}\n");
+    buf.push_str("});\n");
+    buf.push_str("if result.is_err() {\n");
+    buf.push_str("std::process::abort();");
+    buf.push_str("}\n");
+}
+
+// The background thread is blocked on the async handler, which can't reach
+// the poll thread's context.
+fn generate_bg_wrapper_inner_body(srv_name: &str, method: &Method, buf: &mut String) {
+    buf.push_str("let result = std::panic::catch_unwind(|| {\n");
+    buf.push_str("let req = erpc_rs::prelude::ReqHandle::from_inner_raw(req);\n");
+    buf.push_str("unsafe {\n");
+    buf.push_str("::erpc_rs::prelude::ServerRpcContext::run_bg_handler(ctx, ");
+    buf.push_str(&const_method_name(srv_name, method));
+    buf.push_str(".id, req);\n");
+    buf.push_str("}\n");
     buf.push_str("});\n");
     buf.push_str("if result.is_err() {\n");
     buf.push_str("std::process::abort();");
     buf.push_str("}\n");
 }
 
-fn generate_method_bind(service_name: &str, method: &Method, buf: &mut String) {
-    let add_name = "add_unary_handler";
+fn generate_method_bind(service_name: &str, method: &Method, bg: bool, buf: &mut String) {
+    let add_name = if bg {
+        "add_unary_bg_handler"
+    } else {
+        "add_unary_handler"
+    };
 
     buf.push_str("builder = builder.");
     buf.push_str(add_name);
     buf.push_str("(&");
     buf.push_str(&const_method_name(service_name, method));
-    buf.push_str(", move |req, rpc, tx, codec| { std::boxed::Box::pin(S::");
+    buf.push_str(", move |req, tx, codec| { std::boxed::Box::pin(S::");
     buf.push_str(&method.name);
-    buf.push_str("_async(req, rpc, tx, codec))}");
+    buf.push_str("_async(req, tx, codec))}");
     buf.push_str(", unsafe {");
     buf.push_str(&method.name);
     buf.push_str("_wrapper_into::<S>() }, \n");
@@ -400,18 +469,22 @@ pub fn protoc_gen_erpc_rust_main() {
     let mut args = env::args();
     args.next();
     let (mut protos, mut includes, mut out_dir): (Vec<_>, Vec<_>, _) = Default::default();
+    let mut generator = Generator::default();
     for arg in args {
         if let Some(value) = arg.strip_prefix("--protos=") {
-            eprintln!("value: {}", value);
             protos.extend(value.split(',').map(|s| s.to_string()));
         } else if let Some(value) = arg.strip_prefix("--includes=") {
             includes.extend(value.split(',').map(|s| s.to_string()));
         } else if let Some(value) = arg.strip_prefix("--out-dir=") {
             out_dir = value.to_string();
+        } else if let Some(value) = arg.strip_prefix("--bg-methods=") {
+            for method in value.split(',') {
+                generator = generator.bg_method(method);
+            }
         }
     }
     if protos.is_empty() {
         panic!("should at least specify protos to generate");
     }
-    compile_protos(&protos, &includes, &out_dir).unwrap();
+    compile_protos_with(&protos, &includes, &out_dir, generator).unwrap();
 }
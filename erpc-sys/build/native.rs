@@ -0,0 +1,546 @@
+// Copyright (c) 2023, IOMesh Inc. All rights reserved.
+
+//! Builds eRPC and its native dependencies, or links preinstalled ones.
+
+use cmake::Config as CmakeConfig;
+use pkg_config::Config as PkgConfig;
+use regex::Regex;
+use std::collections::HashSet;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::path::Path;
+use std::{
+    env::{self, VarError},
+    fs, io,
+    path::PathBuf,
+    process::Command,
+};
+use walkdir::WalkDir;
+
+/// The eRPC transport, selected by the cargo feature of the same name.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Transport {
+    Dpdk,
+    Infiniband,
+    Raw,
+}
+
+impl Transport {
+    fn from_features() -> Transport {
+        let enabled: Vec<_> = [
+            ("CARGO_FEATURE_DPDK", Transport::Dpdk),
+            ("CARGO_FEATURE_INFINIBAND", Transport::Infiniband),
+            ("CARGO_FEATURE_RAW", Transport::Raw),
+        ]
+        .into_iter()
+        .filter(|(feature, _)| env::var_os(feature).is_some())
+        .map(|(_, transport)| transport)
+        .collect();
+        match enabled[..] {
+            [transport] => transport,
+            [] => panic!("enable one of the features dpdk, infiniband or raw"),
+            _ => panic!("the features dpdk, infiniband and raw are mutually exclusive"),
+        }
+    }
+
+    /// The value of eRPC's `TRANSPORT` CMake option.
+    fn cmake_name(self) -> &'static str {
+        match self {
+            Transport::Dpdk => "dpdk",
+            Transport::Infiniband => "infiniband",
+            Transport::Raw => "raw",
+        }
+    }
+
+    /// The macro eRPC's headers select the transport by.
+    fn define(self) -> &'static str {
+        match self {
+            Transport::Dpdk => "ERPC_DPDK",
+            Transport::Infiniband => "ERPC_INFINIBAND",
+            Transport::Raw => "ERPC_RAW",
+        }
+    }
+}
+
+fn update_submodules() {
+    let program = "git";
+    let dir = "../";
+    let args = ["submodule", "update", "--init", "--recursive"];
+    println!(
+        "Running command: \"{} {}\" in dir: {}",
+        program,
+        args.join(" "),
+        dir
+    );
+    let ret = Command::new(program).current_dir(dir).args(args).status();
+    match ret.map(|status| (status.success(), status.code())) {
+        Ok((true, _)) => (),
+        Ok((false, Some(c))) => panic!("Command failed with error code {}", c),
+        Ok((false, None)) => panic!("Command got killed"),
+        Err(e) => panic!("Command failed with error: {}", e),
+    }
+}
+
+fn erpc_include_dir() -> String {
+    PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap())
+        .join("eRPC")
+        .join("src")
+        .to_str()
+        .unwrap()
+        .to_string()
+}
+
+/// The headers of eRPC built from the submodule, its dependencies and the
+/// generated `config.h`.
+fn submodule_include_paths(transport: Transport, use_sys_dpdk: bool) -> Vec<PathBuf> {
+    let erpc_include_path = erpc_include_dir();
+    let asio_include_path =
+        PathBuf::from(erpc_include_path.clone() + "/../third_party/asio/include");
+    let erpc_include_path = PathBuf::from(erpc_include_path);
+    let erpc_config_include_path = PathBuf::from(env::var("OUT_DIR").unwrap())
+        .join("build")
+        .join("src");
+    let mut include_paths = vec![
+        asio_include_path,
+        erpc_include_path,
+        erpc_config_include_path,
+    ];
+    if transport == Transport::Dpdk {
+        include_paths.push(PathBuf::from(if use_sys_dpdk {
+            get_env("RTE_INCLUDE_PATH").unwrap()
+        } else {
+            format!(
+                "{}/dpdk/build/install/usr/local/include",
+                env::var("OUT_DIR").unwrap()
+            )
+        }));
+    }
+    include_paths
+}
+
+fn bindgen_erpc(transport: Transport, mut include_paths: Vec<PathBuf>) -> miette::Result<()> {
+    println!("cargo:rerun-if-changed=src/lib.rs");
+    include_paths.push(PathBuf::from("src"));
+    let define = format!("-D{}=true", transport.define());
+    let mut b = autocxx_build::Builder::new("src/lib.rs", include_paths)
+        .extra_clang_args(&[&define])
+        .build()?;
+    b.define(transport.define(), "true")
+        .flag_if_supported("--std=c++14")
+        .flag_if_supported("-Wno-unused-function")
+        .static_flag(true)
+        .compile("autocxx-erpc");
+    Ok(())
+}
+
+/// If cache is stale, remove it to avoid compilation failure.
+fn clean_up_stale_cache(cxx_compiler: String) {
+    // We don't know the cmake output path before it's configured.
+    let build_dir = format!("{}/build", env::var("OUT_DIR").unwrap());
+    let path = format!("{build_dir}/CMakeCache.txt");
+    let f = match std::fs::File::open(path) {
+        Ok(f) => BufReader::new(f),
+        // It may be an empty directory.
+        Err(_) => return,
+    };
+    let cache_stale = f.lines().any(|l| {
+        let l = l.unwrap();
+        trim_start(&l, "CMAKE_CXX_COMPILER:").map_or(false, |s| {
+            let mut splits = s.splitn(2, '=');
+            splits.next();
+            splits.next().map_or(false, |p| p != cxx_compiler)
+        })
+    });
+    // CMake can't handle compiler change well, it will invalidate cache without respecting command
+    // line settings and result in configuration failure.
+    // See https://gitlab.kitware.com/cmake/cmake/-/issues/18959.
+    if cache_stale {
+        let _ = fs::remove_dir_all(&build_dir);
+    }
+}
+
+fn is_directory_empty<P: AsRef<Path>>(p: P) -> Result<bool, io::Error> {
+    let mut entries = fs::read_dir(p)?;
+    Ok(entries.next().is_none())
+}
+
+fn trim_start<'a>(s: &'a str, prefix: &str) -> Option<&'a str> {
+    if s.starts_with(prefix) {
+        Some(s.trim_start_matches(prefix))
+    } else {
+        None
+    }
+}
+
+fn get_env(name: &str) -> Option<String> {
+    println!("cargo:rerun-if-env-changed={name}");
+    match env::var(name) {
+        Ok(s) => Some(s),
+        Err(VarError::NotPresent) => None,
+        Err(VarError::NotUnicode(s)) => {
+            panic!("unrecognize env var of {name}: {:?}", s.to_string_lossy());
+        }
+    }
+}
+
+fn prepare_module(module: &str) {
+    match is_directory_empty(module) {
+        Ok(is_empty) => {
+            if is_empty {
+                update_submodules();
+            }
+        }
+        Err(_) => {
+            panic!(
+                "Can't find module {}. You need to run `git submodule \
+                 update --init --recursive` first to build the project.",
+                module
+            );
+        }
+    }
+}
+
+fn build_rdma(cc: &mut cc::Build) {
+    prepare_module("rdma-core");
+
+    let dst = {
+        let mut config = CmakeConfig::new("rdma-core");
+        config.define("ENABLE_STATIC", "ON");
+        config.define("NO_PYVERBS", "1");
+        config.define("ENABLE_RESOLVE_NEIGH", "0");
+        config.define("CMAKE_BUILD_TYPE", "Release");
+        config.define(
+            "CMAKE_INSTALL_PREFIX",
+            format!("{}/r", env::var("OUT_DIR").unwrap()),
+        );
+
+        let cxx_compiler = if let Some(val) = get_env("CXX") {
+            config.define("CMAKE_CXX_COMPILER", val.clone());
+            val
+        } else {
+            format!("{}", cc.get_compiler().path().display())
+        };
+        clean_up_stale_cache(cxx_compiler);
+        config.uses_cxx11().build()
+    };
+    let build_dir = format!("{}/build", dst.display());
+    let lib_dir = build_dir + "/lib";
+    for e in WalkDir::new(&lib_dir) {
+        let e = e.unwrap();
+        if e.file_name().to_string_lossy().ends_with(".a") {
+            println!(
+                "cargo:rustc-link-search=native={}",
+                e.path().parent().unwrap().display()
+            );
+        }
+    }
+    cc.include("build_dir/include");
+}
+
+fn build_dpdk() {
+    prepare_module("dpdk");
+
+    let build_dir = format!("{}/build", env::var("OUT_DIR").unwrap());
+    let c_include_path = build_dir.clone() + "/../r/include";
+    let library_path = build_dir.clone() + "/../r/lib";
+    if fs::metadata("./dpdk/build/build.ninja").is_err() {
+        let program = "meson";
+        let args = [
+            "-Dlibdir=lib",
+            "-Dincludedir=include",
+            "-Dexamples=",
+            "-Denable_kmods=false",
+            "-Dtests=false",
+            "-Ddisable_drivers=raw/*,crypto/*,baseband/*,dma/*",
+            "build",
+        ];
+        let ret = Command::new(program)
+            .env("C_INCLUDE_PATH", &c_include_path)
+            .env("LIBRARY_PATH", &library_path)
+            .current_dir("./dpdk")
+            .args(args)
+            .status();
+        match ret.map(|status| (status.success(), status.code())) {
+            Ok((true, _)) => (),
+            Ok((false, Some(c))) => panic!("Command failed with error code {}", c),
+            Ok((false, None)) => panic!("Command got killed"),
+            Err(e) => panic!("Command failed with error: {}", e),
+        }
+    }
+
+    let program = "ninja";
+    let args = ["install"];
+    let ret = Command::new(program)
+        .env("C_INCLUDE_PATH", &c_include_path)
+        .env("LIBRARY_PATH", &library_path)
+        .env("DESTDIR", build_dir + "/../dpdk/build/install")
+        .current_dir("./dpdk/build")
+        .args(args)
+        .status();
+    match ret.map(|status| (status.success(), status.code())) {
+        Ok((true, _)) => (),
+        Ok((false, Some(c))) => panic!("Command failed with error code {}", c),
+        Ok((false, None)) => panic!("Command got killed"),
+        Err(e) => panic!("Command failed with error: {}", e),
+    }
+}
+
+/// Add `path` to the search path of pkg-config, and its parent to the
+/// library search path.
+fn use_pkg_config_dir(path: &str) {
+    env::set_var("PKG_CONFIG_PATH", path);
+    println!(
+        "cargo:rustc-link-search=native={}",
+        PathBuf::from(path).parent().unwrap().display()
+    );
+}
+
+/// Link libibverbs, found in `pkg_config_dir` or else pkg-config's default
+/// search path.
+fn link_with_rdma(pkg_config_dir: Option<&str>) {
+    if let Some(path) = pkg_config_dir {
+        use_pkg_config_dir(path);
+    }
+    let mut cfg = PkgConfig::new();
+    cfg.print_system_cflags(false)
+        .print_system_libs(false)
+        .env_metadata(false)
+        .cargo_metadata(false)
+        .statik(true);
+    let rdma = cfg.probe("libibverbs").unwrap();
+    let rdma_libs: HashSet<_> = rdma.libs.iter().cloned().collect();
+    for l in rdma_libs.iter() {
+        if l != "pthread" {
+            println!("cargo:rustc-link-lib=static:+whole-archive,-bundle={l}");
+        }
+    }
+}
+
+/// Link DPDK, found in `pkg_config_dir` or else pkg-config's default search
+/// path. Returns its include paths.
+fn link_with_dpdk(pkg_config_dir: Option<&str>) -> Vec<PathBuf> {
+    if let Some(path) = pkg_config_dir {
+        use_pkg_config_dir(path);
+    }
+    let mut cfg = PkgConfig::new();
+    cfg.print_system_cflags(false)
+        .print_system_libs(false)
+        .env_metadata(false)
+        .cargo_metadata(false)
+        .statik(true);
+    let dpdk = cfg.probe("libdpdk").unwrap();
+    let dpdk_libs: HashSet<_> = dpdk.libs.iter().cloned().collect();
+    let lib_name_format = Regex::new(r"lib(.*)\.(a)").unwrap();
+    let mut dpdk_link_names = vec![];
+    for l in dpdk_libs.iter() {
+        if let Some(l) = l.strip_prefix(':') {
+            if let Some(capture) = lib_name_format.captures(l) {
+                let link_name = &capture[1];
+                dpdk_link_names.push(link_name.to_string());
+            }
+        }
+    }
+    for l in dpdk_link_names {
+        println!("cargo:rustc-link-lib=static:+whole-archive,-bundle={l}");
+    }
+    dpdk.include_paths
+}
+
+/// Link the system libraries eRPC depends on.
+fn link_with_sys_libs() {
+    ["z", "jansson", "bsd", "numa", "pthread"].map(|lib| {
+        println!("cargo:rustc-link-lib=dylib={}", lib);
+    });
+    if let Ok(os_release) = std::fs::read_to_string("/etc/os-release") {
+        if os_release.contains("Ubuntu") {
+            println!("cargo:rustc-link-lib=dylib=atomic");
+        }
+    }
+}
+
+/// Link `liberpc` in `lib_dirs`, statically if there's an archive.
+fn link_with_erpc(lib_dirs: &[PathBuf]) {
+    for dir in lib_dirs {
+        println!("cargo:rustc-link-search=native={}", dir.display());
+    }
+    if lib_dirs.iter().any(|dir| dir.join("liberpc.a").exists()) {
+        println!("cargo:rustc-link-lib=static:+whole-archive,-bundle=erpc");
+    } else {
+        println!("cargo:rustc-link-lib=dylib=erpc");
+    }
+}
+
+/// Locate a preinstalled eRPC, built with the selected transport, by
+/// `ERPC_INCLUDE_PATH` and `ERPC_LIB_PATH`, or by `ERPC_PKG_CONFIG`, the
+/// directory of its `erpc.pc`. Links it and returns its include paths,
+/// which must cover eRPC's `config.h` and asio as well.
+fn probe_sys_erpc() -> Option<Vec<PathBuf>> {
+    if let Some(include_path) = get_env("ERPC_INCLUDE_PATH") {
+        let lib_path = get_env("ERPC_LIB_PATH")
+            .unwrap_or_else(|| panic!("ERPC_LIB_PATH must be set along with ERPC_INCLUDE_PATH"));
+        link_with_erpc(&env::split_paths(&lib_path).collect::<Vec<_>>());
+        return Some(env::split_paths(&include_path).collect());
+    }
+    let path = get_env("ERPC_PKG_CONFIG")?;
+    env::set_var("PKG_CONFIG_PATH", &path);
+    let mut cfg = PkgConfig::new();
+    cfg.print_system_cflags(false)
+        .print_system_libs(false)
+        .env_metadata(false)
+        .cargo_metadata(false);
+    let erpc = cfg.probe("erpc").unwrap();
+    link_with_erpc(&erpc.link_paths);
+    Some(erpc.include_paths)
+}
+
+fn build_erpc(cc: &mut cc::Build, transport: Transport, use_sys_rdma: bool, use_sys_dpdk: bool) {
+    prepare_module("eRPC");
+
+    let dst = {
+        let mut config = CmakeConfig::new("eRPC");
+        config.define("PERF", "ON");
+        config.define("TRANSPORT", transport.cmake_name());
+        if transport == Transport::Dpdk {
+            config.env(
+                "RTE_SDK",
+                if use_sys_dpdk {
+                    get_env("RTE_SDK").unwrap()
+                } else {
+                    format!("{}/dpdk", env::var("OUT_DIR").unwrap())
+                },
+            );
+        }
+
+        let cxx_compiler = if let Some(val) = get_env("CXX") {
+            config.define("CMAKE_CXX_COMPILER", val.clone());
+            val
+        } else {
+            format!("{}", cc.get_compiler().path().display())
+        };
+        clean_up_stale_cache(cxx_compiler);
+
+        if use_sys_rdma {
+            config.env("LIBRARY_PATH", get_env("RDMA_PKG_CONFIG").unwrap() + "/../");
+        } else {
+            let out_dir = env::var("OUT_DIR").unwrap().to_string();
+            let mut path = out_dir.clone() + "/r/lib";
+            if is_directory_empty(&path).is_err() {
+                path = out_dir.clone() + "/r/lib64";
+                if is_directory_empty(&path).is_err() || is_directory_empty(&path).unwrap() {
+                    panic!("rdma core's lib path {} not correct", path);
+                }
+            }
+            config.env("LIBRARY_PATH", path);
+        }
+
+        config.uses_cxx11().build()
+    };
+
+    let rdma_lib_dir = if use_sys_rdma {
+        get_env("RDMA_PKG_CONFIG").unwrap() + "/../"
+    } else {
+        format!("{}/build", dst.display())
+    };
+    for e in WalkDir::new(&rdma_lib_dir) {
+        let e = e.unwrap();
+        if e.file_name().to_string_lossy().ends_with(".a") {
+            println!(
+                "cargo:rustc-link-search=native={}",
+                e.path().parent().unwrap().display()
+            );
+        }
+    }
+    println!(
+        "cargo:rustc-link-search=native={}",
+        PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap())
+            .join("eRPC")
+            .join("build")
+            .to_str()
+            .unwrap()
+    );
+
+    // All transports need libibverbs: infiniband and raw run on it, DPDK's
+    // mlx drivers do.
+    let rdma_pkg_config = if use_sys_rdma {
+        get_env("RDMA_PKG_CONFIG").unwrap()
+    } else {
+        let mut path = format!("{}/r/lib/pkgconfig", dst.display());
+        if is_directory_empty(&path).is_err() {
+            path = format!("{}/r/lib64/pkgconfig", dst.display());
+            if is_directory_empty(&path).is_err() || is_directory_empty(&path).unwrap() {
+                panic!("r's pkgconfig path {} not correct", path);
+            }
+        }
+        path
+    };
+    link_with_rdma(Some(&rdma_pkg_config));
+    if transport == Transport::Dpdk {
+        let dpdk_pkg_config = if use_sys_dpdk {
+            get_env("RTE_PKG_CONFIG").unwrap()
+        } else {
+            let path = format!(
+                "{}/dpdk/build/install/usr/local/lib/pkgconfig",
+                dst.display()
+            );
+            if is_directory_empty(&path).is_err() || is_directory_empty(&path).unwrap() {
+                panic!("dpdk's pkgconfig path {} not correct", path);
+            }
+            path
+        };
+        link_with_dpdk(Some(&dpdk_pkg_config));
+    }
+
+    let libs = &["erpc"];
+    for l in libs {
+        println!("cargo:rustc-link-lib=static:+whole-archive,-bundle={}", l);
+    }
+    link_with_sys_libs();
+
+    cc.include("eRPC/src");
+}
+
+/// Builds or finds eRPC for the enabled transport, and generates its bindings.
+pub fn build() -> miette::Result<()> {
+    println!("cargo:rerun-if-changed=src/erpc_wrapper.h");
+
+    let transport = Transport::from_features();
+
+    // A preinstalled eRPC needs neither the submodules nor git. rdma-core and
+    // DPDK are then preinstalled as well.
+    if let Some(mut include_paths) = probe_sys_erpc() {
+        // infiniband and raw run on libibverbs. DPDK needs it only for its
+        // mlx drivers, so link it there only when RDMA_PKG_CONFIG is set.
+        let rdma_pkg_config = get_env("RDMA_PKG_CONFIG");
+        if transport != Transport::Dpdk || rdma_pkg_config.is_some() {
+            link_with_rdma(rdma_pkg_config.as_deref());
+        }
+        if transport == Transport::Dpdk {
+            include_paths.extend(link_with_dpdk(get_env("RTE_PKG_CONFIG").as_deref()));
+        }
+        link_with_sys_libs();
+        return bindgen_erpc(transport, include_paths);
+    }
+
+    println!("cargo:rerun-if-changed=eRPC");
+    let mut use_sys_rdma = false;
+    let mut use_sys_dpdk = false;
+    let mut cc = cc::Build::new();
+
+    if get_env("RDMA_PKG_CONFIG").is_none() {
+        build_rdma(&mut cc);
+    } else {
+        use_sys_rdma = true;
+    }
+    if transport == Transport::Dpdk {
+        if get_env("RTE_SDK").is_none() {
+            build_dpdk();
+        } else {
+            use_sys_dpdk = true;
+        }
+    }
+    build_erpc(&mut cc, transport, use_sys_rdma, use_sys_dpdk);
+
+    bindgen_erpc(transport, submodule_include_paths(transport, use_sys_dpdk))?;
+
+    Ok(())
+}
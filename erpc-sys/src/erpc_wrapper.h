@@ -2,7 +2,116 @@
 
 #pragma once
 
-// TODO: make it define by cc builder
-#define ERPC_DPDK true
+// The transport, e.g. ERPC_DPDK, is defined by build.rs after the cargo
+// feature.
+#if !defined(ERPC_DPDK) && !defined(ERPC_INFINIBAND) && !defined(ERPC_RAW)
+#error "no eRPC transport is defined"
+#endif
+
+#include <cstdint>
+#include <type_traits>
+#include <utility>
+#include <vector>
 
 #include "rpc.h"
+
+namespace erpc_wrapper {
+
+// Accessors of the fields of erpc::wheel_record_t, which autocxx can't reach
+// through the opaque type.
+
+inline bool wheel_record_is_insert(const erpc::wheel_record_t &record) {
+  return record.insert_;
+}
+
+inline size_t wheel_record_pkt_num(const erpc::wheel_record_t &record) {
+  return record.pkt_num_;
+}
+
+inline size_t wheel_record_abs_tx_tsc(const erpc::wheel_record_t &record) {
+  return record.abs_tx_tsc_;
+}
+
+// Accessors of erpc::Rpc which autocxx can't bind: static or constexpr
+// members, and state only reachable through the session vector.
+
+inline size_t rpc_max_msg_size(const erpc::Rpc &rpc) {
+  return rpc.get_max_msg_size();
+}
+
+inline size_t rpc_max_data_per_pkt(const erpc::Rpc &rpc) {
+  return rpc.get_max_data_per_pkt();
+}
+
+inline size_t rpc_mtu() { return erpc::CTransport::kMTU; }
+
+inline size_t rpc_stat_user_alloc_tot(erpc::Rpc &rpc) {
+  return rpc.get_stat_user_alloc_tot();
+}
+
+inline size_t rpc_num_active_sessions(erpc::Rpc &rpc) {
+  return rpc.num_active_sessions();
+}
+
+// eRPC has no accessors for the session vector, the session credits or the
+// background request queues, so the functions below read the fields behind
+// them. Check up front that they're still reachable with the types read, so
+// that an eRPC upgrade which renames, hides or retypes one fails here rather
+// than inside an accessor, or worse, reads the wrong thing.
+namespace internals {
+
+using RpcRef = const erpc::Rpc &;
+
+static_assert(std::is_same<decltype(std::declval<RpcRef>().session_vec_),
+                           std::vector<erpc::Session *>>::value,
+              "erpc::Rpc::session_vec_ isn't a vector of Session pointers");
+static_assert(
+    std::is_convertible<
+        decltype(std::declval<const erpc::Session &>().client_info_.credits_),
+        size_t>::value,
+    "erpc::Session::client_info_.credits_ isn't a count");
+static_assert(
+    std::is_convertible<decltype(std::declval<RpcRef>().nexus_->num_bg_threads_),
+                        size_t>::value,
+    "erpc::Nexus::num_bg_threads_ isn't a count");
+static_assert(
+    std::is_convertible<
+        decltype(std::declval<RpcRef>().nexus_hook_.bg_req_queue_arr_[0]->size_),
+        size_t>::value,
+    "the background request queues of erpc::Nexus::Hook have no size_");
+
+}  // namespace internals
+
+// Whether session_num is a client session which hasn't been destroyed, i.e.
+// buried.
+inline bool rpc_is_client_session(const erpc::Rpc &rpc, int session_num) {
+  if (session_num < 0 ||
+      static_cast<size_t>(session_num) >= rpc.session_vec_.size()) {
+    return false;
+  }
+  const erpc::Session *session =
+      rpc.session_vec_[static_cast<size_t>(session_num)];
+  return session != nullptr && session->is_client();
+}
+
+// The credits left to a client session, or -1 if session_num isn't one.
+inline int64_t rpc_session_credits(const erpc::Rpc &rpc, int session_num) {
+  if (!rpc_is_client_session(rpc, session_num)) {
+    return -1;
+  }
+  return static_cast<int64_t>(
+      rpc.session_vec_[static_cast<size_t>(session_num)]
+          ->client_info_.credits_);
+}
+
+// Requests queued for the background threads of the Rpc's Nexus, including
+// those of the Nexus' other Rpcs.
+inline size_t rpc_bg_queue_len(const erpc::Rpc &rpc) {
+  size_t len = 0;
+  for (size_t i = 0; i < rpc.nexus_->num_bg_threads_; i++) {
+    len += rpc.nexus_hook_.bg_req_queue_arr_[i]->size_;
+  }
+  return len;
+}
+
+}  // namespace erpc_wrapper
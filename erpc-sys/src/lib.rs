@@ -1,7 +1,9 @@
 // Copyright (c) 2023, IOMesh Inc. All rights reserved.
 
+#[cfg(not(any(feature = "loopback", feature = "stub")))]
 pub use autocxx::prelude::*;
 
+#[cfg(not(any(feature = "loopback", feature = "stub")))]
 include_cpp! {
     #include "erpc_wrapper.h"
     safety!(unsafe_ffi)
@@ -13,6 +15,7 @@ include_cpp! {
     generate!("EINVAL")
     generate!("EBUSY")
     generate!("EALREADY")
+    generate!("ETIMEDOUT")
     generate!("erpc::get_uri_for_process")
     generate!("erpc::kInvalidBgETid")
     generate!("erpc::kSessionCredits")
@@ -32,13 +35,40 @@ include_cpp! {
     generate!("erpc::sm_err_type_str")
     generate!("erpc::sm_event_type_str")
     generate!("erpc::wheel_record_t")
+    generate!("erpc_wrapper::wheel_record_is_insert")
+    generate!("erpc_wrapper::wheel_record_pkt_num")
+    generate!("erpc_wrapper::wheel_record_abs_tx_tsc")
+    generate!("erpc_wrapper::rpc_max_msg_size")
+    generate!("erpc_wrapper::rpc_max_data_per_pkt")
+    generate!("erpc_wrapper::rpc_mtu")
+    generate!("erpc_wrapper::rpc_stat_user_alloc_tot")
+    generate!("erpc_wrapper::rpc_num_active_sessions")
+    generate!("erpc_wrapper::rpc_is_client_session")
+    generate!("erpc_wrapper::rpc_session_credits")
+    generate!("erpc_wrapper::rpc_bg_queue_len")
     block!("erpc::HugeAlloc")
 }
 
+#[cfg(not(any(feature = "loopback", feature = "stub")))]
 pub use autocxx::moveit;
+#[cfg(not(any(feature = "loopback", feature = "stub")))]
 pub use cxx::CxxVector;
+#[cfg(not(any(feature = "loopback", feature = "stub")))]
 pub use ffi::*;
 
+#[cfg(any(feature = "loopback", feature = "stub"))]
+mod shim;
+
+#[cfg(feature = "loopback")]
+mod loopback;
+#[cfg(feature = "loopback")]
+pub use loopback::*;
+
+#[cfg(all(feature = "stub", not(feature = "loopback")))]
+mod stub;
+#[cfg(all(feature = "stub", not(feature = "loopback")))]
+pub use stub::*;
+
 unsafe impl Send for erpc::Nexus {}
 unsafe impl Sync for erpc::Nexus {}
 
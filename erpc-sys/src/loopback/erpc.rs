@@ -0,0 +1,1320 @@
+// Copyright (c) 2023, IOMesh Inc. All rights reserved.
+
+//! The loopback counterpart of eRPC's `erpc` namespace.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    mem,
+    pin::Pin,
+    ptr, slice,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex, Once, Weak,
+    },
+    thread::{self, JoinHandle},
+    time::Instant,
+};
+
+use super::sim;
+use super::{c_int, c_void, CxxString, CxxVector, UniquePtr, WithinUniquePtr};
+use super::{EBUSY, EEXIST, EINVAL, EPERM};
+
+pub const kInvalidBgETid: usize = 8;
+pub const kSessionCredits: usize = 32;
+pub const kSessionReqWindow: usize = 8;
+pub const kInvalidSessionNum: usize = u16::MAX as usize;
+
+/// The TSC ticks in nanoseconds.
+const FREQ_GHZ: f64 = 1.0;
+/// The nominal link bandwidth in bytes per second, i.e. 100 Gbps.
+const BANDWIDTH: usize = 12_500_000_000;
+/// The MTU of the simulated NIC.
+pub const MTU: usize = 1040;
+/// The size of eRPC's packet header.
+const PKTHDR_SIZE: usize = 16;
+const MAX_DATA_PER_PKT: usize = MTU - PKTHDR_SIZE;
+/// eRPC's max message size.
+const MAX_MSG_SIZE: usize = 8 << 20;
+/// eRPC sizes the preallocated response to the payload of one packet.
+const PRE_RESP_MSGBUF_SIZE: usize = MAX_DATA_PER_PKT;
+/// The round trip times kept by a [`Timely`] until it's reset.
+const MAX_RTT_SAMPLES: usize = 1 << 16;
+
+type SmHandler = extern "C" fn(c_int, SmEventType, SmErrType, *mut c_void);
+type ReqFunc = extern "C" fn(*mut ReqHandle, *mut c_void);
+type ContFunc = extern "C" fn(*mut c_void, *mut c_void);
+
+static EPOCH_INIT: Once = Once::new();
+static mut EPOCH: Option<Instant> = None;
+
+/// Returns the nanoseconds since the first call, or the virtual clock of
+/// the installed [`sim::Simulation`].
+pub fn rdtsc() -> usize {
+    if let Some(now) = sim::now_tsc() {
+        return now;
+    }
+    EPOCH_INIT.call_once(|| unsafe { EPOCH = Some(Instant::now()) });
+    // Only written once, before any read.
+    let epoch = unsafe { ptr::addr_of!(EPOCH).read() }.unwrap();
+    epoch.elapsed().as_nanos() as usize + sim::clock_offset()
+}
+
+pub fn to_usec(cycles: usize, freq_ghz: f64) -> f64 {
+    cycles as f64 / (freq_ghz * 1000.0)
+}
+
+pub fn ms_to_cycles(ms: f64, freq_ghz: f64) -> usize {
+    (ms * 1_000_000.0 * freq_ghz) as usize
+}
+
+pub fn get_uri_for_process(process_id: usize) -> UniquePtr<CxxString> {
+    CxxString(format!("localhost:{}", 31850 + process_id)).within_unique_ptr()
+}
+
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SmErrType {
+    kNoError,
+    kSrvDisconnected,
+    kRingExhausted,
+    kOutOfMemory,
+    kRoutingResolutionFailure,
+    kInvalidRemoteRpcId,
+    kInvalidTransport,
+}
+
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SmEventType {
+    kConnected,
+    kConnectFailed,
+    kDisconnected,
+    kDisconnectFailed,
+}
+
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ReqFuncType {
+    kForeground,
+    kBackground,
+}
+
+pub fn sm_err_type_str(err: SmErrType) -> UniquePtr<CxxString> {
+    let s = match err {
+        SmErrType::kNoError => "[No error]",
+        SmErrType::kSrvDisconnected => "[Server disconnected]",
+        SmErrType::kRingExhausted => "[Ring buffers exhausted]",
+        SmErrType::kOutOfMemory => "[Out of memory]",
+        SmErrType::kRoutingResolutionFailure => "[Routing resolution failure]",
+        SmErrType::kInvalidRemoteRpcId => "[Invalid remote Rpc ID]",
+        SmErrType::kInvalidTransport => "[Invalid transport]",
+    };
+    CxxString(s.to_owned()).within_unique_ptr()
+}
+
+pub fn sm_event_type_str(event: SmEventType) -> UniquePtr<CxxString> {
+    let s = match event {
+        SmEventType::kConnected => "[Connected]",
+        SmEventType::kConnectFailed => "[Connect failed]",
+        SmEventType::kDisconnected => "[Disconnected]",
+        SmEventType::kDisconnectFailed => "[Disconnect failed]",
+    };
+    CxxString(s.to_owned()).within_unique_ptr()
+}
+
+pub struct ChronoTimer {
+    start: Instant,
+}
+
+impl ChronoTimer {
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> ChronoTimer {
+        ChronoTimer {
+            start: Instant::now(),
+        }
+    }
+
+    pub fn reset(self: Pin<&mut Self>) {
+        self.get_mut().start = Instant::now();
+    }
+
+    pub fn get_ns(&self) -> usize {
+        self.start.elapsed().as_nanos() as usize
+    }
+
+    pub fn get_us(&self) -> usize {
+        self.start.elapsed().as_micros() as usize
+    }
+
+    pub fn get_sec(&self) -> f64 {
+        self.start.elapsed().as_secs_f64()
+    }
+}
+
+/// eRPC's linear congruential generator.
+pub struct FastRand {
+    seed: u64,
+}
+
+impl FastRand {
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> FastRand {
+        FastRand {
+            seed: rdtsc() as u64,
+        }
+    }
+
+    pub fn next_u32(self: Pin<&mut Self>) -> u32 {
+        let this = self.get_mut();
+        this.seed = this.seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+        (this.seed >> 32) as u32
+    }
+}
+
+/// A message buffer. Like eRPC's, copies share the memory, which is only
+/// released by `Rpc::free_msg_buffer`.
+pub struct MsgBuffer {
+    buf: *mut u8,
+    max_data_size: usize,
+    data_size: usize,
+}
+
+impl MsgBuffer {
+    fn alloc(max_data_size: usize) -> MsgBuffer {
+        let buf = Box::into_raw(vec![0u8; max_data_size].into_boxed_slice()) as *mut u8;
+        MsgBuffer {
+            buf,
+            max_data_size,
+            data_size: max_data_size,
+        }
+    }
+
+    fn invalid() -> MsgBuffer {
+        MsgBuffer {
+            buf: ptr::null_mut(),
+            max_data_size: 0,
+            data_size: 0,
+        }
+    }
+
+    fn copy(&self) -> MsgBuffer {
+        MsgBuffer {
+            buf: self.buf,
+            max_data_size: self.max_data_size,
+            data_size: self.data_size,
+        }
+    }
+
+    fn from_slice(data: &[u8]) -> MsgBuffer {
+        let msgbuf = MsgBuffer::alloc(data.len());
+        unsafe { ptr::copy_nonoverlapping(data.as_ptr(), msgbuf.buf, data.len()) };
+        msgbuf
+    }
+
+    fn data(&self) -> &[u8] {
+        if self.buf.is_null() {
+            return &[];
+        }
+        unsafe { slice::from_raw_parts(self.buf, self.data_size) }
+    }
+
+    /// # Safety
+    ///
+    /// No other copy may be used afterwards.
+    unsafe fn free(&self) {
+        if !self.buf.is_null() {
+            drop(Box::from_raw(ptr::slice_from_raw_parts_mut(
+                self.buf,
+                self.max_data_size,
+            )));
+        }
+    }
+
+    pub fn get_inner_buf(&self) -> *mut u8 {
+        self.buf
+    }
+
+    pub fn get_data_size(&self) -> usize {
+        self.data_size
+    }
+
+    pub fn get_max_data_size(&self) -> usize {
+        self.max_data_size
+    }
+
+    pub fn is_valid(&self) -> bool {
+        !self.buf.is_null()
+    }
+}
+
+/// A request received by a server `Rpc`, valid until its response is
+/// enqueued.
+pub struct ReqHandle {
+    req_msgbuf: MsgBuffer,
+    pre_resp_msgbuf: MsgBuffer,
+    dyn_resp_msgbuf: MsgBuffer,
+    peer: Weak<Mailbox>,
+    client_session: u16,
+    req_num: u64,
+}
+
+impl ReqHandle {
+    /// Returns the handle of a request that arrived on no session, so its
+    /// response goes nowhere. Loopback only, e.g. to unit test handlers.
+    pub fn detached(req: &[u8]) -> ReqHandle {
+        ReqHandle {
+            req_msgbuf: MsgBuffer::from_slice(req),
+            pre_resp_msgbuf: MsgBuffer::alloc(PRE_RESP_MSGBUF_SIZE),
+            dyn_resp_msgbuf: MsgBuffer::invalid(),
+            peer: Weak::new(),
+            client_session: 0,
+            req_num: 0,
+        }
+    }
+
+    pub fn get_req_msgbuf(self: Pin<&mut Self>) -> *const MsgBuffer {
+        &self.req_msgbuf
+    }
+
+    pub fn get_pre_resp_msgbuf(self: Pin<&mut Self>) -> MsgBuffer {
+        self.pre_resp_msgbuf.copy()
+    }
+
+    pub fn get_dyn_resp_msgbuf(self: Pin<&mut Self>) -> MsgBuffer {
+        self.dyn_resp_msgbuf.copy()
+    }
+
+    /// Take ownership of `msgbuf`, which is freed once the response is sent.
+    pub fn init_dyn_resp_msgbuf_from_allocated(
+        self: Pin<&mut Self>,
+        msgbuf: Pin<&mut MsgBuffer>,
+    ) -> MsgBuffer {
+        let this = unsafe { self.get_unchecked_mut() };
+        this.dyn_resp_msgbuf = msgbuf.copy();
+        this.dyn_resp_msgbuf.copy()
+    }
+}
+
+enum Packet {
+    Connect {
+        from: Weak<Mailbox>,
+        client_session: u16,
+    },
+    ConnectResp {
+        from: Weak<Mailbox>,
+        client_session: u16,
+        res: Result<u16, SmErrType>,
+    },
+    Request {
+        server_session: u16,
+        req_num: u64,
+        req_type: u8,
+        data: Vec<u8>,
+    },
+    Response {
+        client_session: u16,
+        req_num: u64,
+        data: Vec<u8>,
+        /// Retransmissions of the response, set by [`Mailbox::send`].
+        re_tx: u32,
+    },
+    Disconnect {
+        server_session: u16,
+    },
+    /// A client session went down.
+    Disconnected {
+        client_session: u16,
+        err: SmErrType,
+    },
+    /// Reset a server session, or all sessions if `None`. Only injected by
+    /// the simulation.
+    Reset {
+        server_session: Option<u16>,
+    },
+}
+
+struct Mailbox {
+    /// `uri/rpc_id`, names the `Rpc` in the simulation.
+    addr: String,
+    /// Packets by delivery time, then arrival.
+    packets: Mutex<BTreeMap<(usize, u64), Packet>>,
+    seq: AtomicU64,
+    stall_until: AtomicUsize,
+}
+
+impl Mailbox {
+    fn new(uri: &str, rpc_id: u8) -> Mailbox {
+        Mailbox {
+            addr: format!("{uri}/{rpc_id}"),
+            packets: Mutex::new(BTreeMap::new()),
+            seq: AtomicU64::new(0),
+            stall_until: AtomicUsize::new(0),
+        }
+    }
+
+    /// Deliver `packet` to this `Rpc` itself, right away.
+    fn push(&self, packet: Packet) {
+        self.push_at(0, packet);
+    }
+
+    fn push_at(&self, deliver_at: usize, packet: Packet) {
+        let seq = self.seq.fetch_add(1, Ordering::Relaxed);
+        self.packets
+            .lock()
+            .unwrap()
+            .insert((deliver_at, seq), packet);
+    }
+
+    /// Send `packet` to another `Rpc`, through the simulation if installed.
+    /// Returns how often it was retransmitted.
+    fn send(&self, to: &Mailbox, mut packet: Packet) -> u32 {
+        let request = matches!(packet, Packet::Request { .. });
+        let route = match sim::route(&self.addr, &to.addr, request) {
+            Some(route) => route,
+            None => {
+                to.push(packet);
+                return 0;
+            }
+        };
+        if let Some(stall_until) = route.stall_until {
+            to.stall(stall_until);
+        }
+        if let Packet::Response { re_tx, .. } = &mut packet {
+            *re_tx = route.re_tx;
+        }
+        match packet {
+            Packet::Request { server_session, .. } if route.reset => to.push_at(
+                route.deliver_at,
+                Packet::Reset {
+                    server_session: Some(server_session),
+                },
+            ),
+            packet => to.push_at(route.deliver_at, packet),
+        }
+        route.re_tx
+    }
+
+    fn stall(&self, until: usize) {
+        self.stall_until.fetch_max(until, Ordering::AcqRel);
+    }
+
+    /// Take the packets due at `now`, none while stalled.
+    fn drain(&self, now: usize) -> Vec<Packet> {
+        if now < self.stall_until.load(Ordering::Acquire) {
+            return Vec::new();
+        }
+        let mut packets = self.packets.lock().unwrap();
+        let later = packets.split_off(&(now + 1, 0));
+        mem::replace(&mut *packets, later).into_values().collect()
+    }
+
+    /// Returns when the next packet can be handled.
+    fn next_delivery(&self) -> Option<usize> {
+        let packets = self.packets.lock().unwrap();
+        let (&(deliver_at, _), _) = packets.iter().next()?;
+        Some(deliver_at.max(self.stall_until.load(Ordering::Acquire)))
+    }
+}
+
+/// A request handed to a background thread.
+struct BgJob {
+    req_func: ReqFunc,
+    req_handle: *mut ReqHandle,
+    context: *mut c_void,
+}
+
+// The request handle and context are only used by the background thread
+// until the handler returns, like eRPC's.
+unsafe impl Send for BgJob {}
+
+/// The process-wide state behind a [`Nexus`].
+struct Host {
+    uri: String,
+    num_bg_threads: usize,
+    req_funcs: Mutex<HashMap<u8, (ReqFunc, ReqFuncType)>>,
+    rpcs: Mutex<HashMap<u8, Weak<Mailbox>>>,
+    /// The queue of the background threads, closed when the `Nexus` drops.
+    bg_jobs: Mutex<Option<Sender<BgJob>>>,
+    /// Jobs queued or being handled. eRPC only counts the queued ones.
+    bg_queued: Arc<AtomicUsize>,
+}
+
+fn bg_thread(jobs: Arc<Mutex<Receiver<BgJob>>>, queued: Arc<AtomicUsize>) {
+    loop {
+        let job = jobs.lock().unwrap().recv();
+        match job {
+            Ok(job) => {
+                (job.req_func)(job.req_handle, job.context);
+                queued.fetch_sub(1, Ordering::AcqRel);
+            }
+            Err(_) => return,
+        }
+    }
+}
+
+static HOSTS: Mutex<Vec<Weak<Host>>> = Mutex::new(Vec::new());
+
+fn lookup_host(uri: &str) -> Option<Arc<Host>> {
+    HOSTS
+        .lock()
+        .unwrap()
+        .iter()
+        .filter_map(Weak::upgrade)
+        .find(|h| h.uri == uri)
+}
+
+/// Returns the mailboxes of the live `Rpc`s, of `uri` only if given.
+fn mailboxes(uri: Option<&str>) -> Vec<Arc<Mailbox>> {
+    let hosts: Vec<_> = HOSTS
+        .lock()
+        .unwrap()
+        .iter()
+        .filter_map(Weak::upgrade)
+        .filter(|h| !matches!(uri, Some(uri) if h.uri != uri))
+        .collect();
+    hosts
+        .iter()
+        .flat_map(|h| {
+            let rpcs = h.rpcs.lock().unwrap();
+            rpcs.values().filter_map(Weak::upgrade).collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// Returns when the next packet of any `Rpc` can be handled.
+pub(super) fn next_delivery() -> Option<usize> {
+    mailboxes(None)
+        .iter()
+        .filter_map(|m| m.next_delivery())
+        .min()
+}
+
+pub(super) fn reset_sessions(uri: &str) {
+    for mailbox in mailboxes(Some(uri)) {
+        mailbox.push(Packet::Reset {
+            server_session: None,
+        });
+    }
+}
+
+pub(super) fn stall(uri: &str, until: usize) {
+    for mailbox in mailboxes(Some(uri)) {
+        mailbox.stall(until);
+    }
+}
+
+pub struct Nexus {
+    host: Arc<Host>,
+    bg_threads: Vec<JoinHandle<()>>,
+}
+
+impl Nexus {
+    /// # Panics
+    ///
+    /// Panics if another live `Nexus` of this process uses `local_uri`, like
+    /// eRPC fails to bind its management port.
+    pub fn new(local_uri: &str, _numa_node: usize, num_bg_threads: usize) -> Nexus {
+        let mut hosts = HOSTS.lock().unwrap();
+        hosts.retain(|h| h.strong_count() > 0);
+        assert!(
+            !hosts
+                .iter()
+                .filter_map(Weak::upgrade)
+                .any(|h| h.uri == local_uri),
+            "loopback: {local_uri} is already in use"
+        );
+        let (tx, rx) = mpsc::channel();
+        let rx = Arc::new(Mutex::new(rx));
+        let bg_queued = Arc::new(AtomicUsize::new(0));
+        let bg_threads = (0..num_bg_threads)
+            .map(|_| {
+                let (rx, queued) = (rx.clone(), bg_queued.clone());
+                thread::spawn(move || bg_thread(rx, queued))
+            })
+            .collect();
+        let host = Arc::new(Host {
+            uri: local_uri.to_owned(),
+            num_bg_threads,
+            req_funcs: Mutex::new(HashMap::new()),
+            rpcs: Mutex::new(HashMap::new()),
+            bg_jobs: Mutex::new(Some(tx)),
+            bg_queued,
+        });
+        hosts.push(Arc::downgrade(&host));
+        Nexus { host, bg_threads }
+    }
+
+    /// # Safety
+    ///
+    /// `req_func` must be an `extern "C" fn(*mut ReqHandle, *mut c_void)`.
+    pub unsafe fn register_req_func(
+        self: Pin<&mut Self>,
+        req_type: u8,
+        req_func: *mut c_void,
+        req_func_type: ReqFuncType,
+    ) -> c_int {
+        // Like eRPC, handlers must be registered before any `Rpc` is created.
+        if !self.host.rpcs.lock().unwrap().is_empty()
+            || (req_func_type == ReqFuncType::kBackground && self.host.num_bg_threads == 0)
+        {
+            return c_int(-(EPERM as i32));
+        }
+        if req_func.is_null() {
+            return c_int(-(EINVAL as i32));
+        }
+        let mut req_funcs = self.host.req_funcs.lock().unwrap();
+        if req_funcs.contains_key(&req_type) {
+            return c_int(-(EEXIST as i32));
+        }
+        req_funcs.insert(
+            req_type,
+            (
+                mem::transmute::<*mut c_void, ReqFunc>(req_func),
+                req_func_type,
+            ),
+        );
+        c_int(0)
+    }
+}
+
+impl Drop for Nexus {
+    /// Waits for the background threads to finish their requests.
+    fn drop(&mut self) {
+        self.host.bg_jobs.lock().unwrap().take();
+        for handle in self.bg_threads.drain(..) {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// eRPC's congestion control state of a session. The loopback never
+/// throttles, it only samples the round trip times.
+pub struct Timely {
+    rate_gbps: f64,
+    rtts_us: Vec<f64>,
+}
+
+impl Timely {
+    pub fn set_rate(self: Pin<&mut Self>, rate: f64) {
+        self.get_mut().rate_gbps = rate;
+    }
+
+    pub fn get_rtt_perc(self: Pin<&mut Self>, perc: f64) -> f64 {
+        let this = self.get_mut();
+        if this.rtts_us.is_empty() {
+            return 0.0;
+        }
+        this.rtts_us.sort_unstable_by(f64::total_cmp);
+        let idx = ((this.rtts_us.len() as f64 * perc) as usize).min(this.rtts_us.len() - 1);
+        this.rtts_us[idx]
+    }
+
+    pub fn reset_rtt_stats(self: Pin<&mut Self>) {
+        self.get_mut().rtts_us.clear();
+    }
+
+    pub fn get_rate_gbps(&self) -> f64 {
+        self.rate_gbps
+    }
+}
+
+pub struct wheel_record_t {
+    pub(super) insert_: bool,
+    pub(super) pkt_num_: usize,
+    pub(super) abs_tx_tsc_: usize,
+}
+
+impl wheel_record_t {
+    pub fn to_string(
+        self: Pin<&mut Self>,
+        console_ref_tsc: usize,
+        freq_ghz: f64,
+    ) -> UniquePtr<CxxString> {
+        let s = if self.insert_ {
+            format!(
+                "[Insert pkt {}, desired TX {:.2} us]",
+                self.pkt_num_,
+                to_usec(self.abs_tx_tsc_.saturating_sub(console_ref_tsc), freq_ghz)
+            )
+        } else {
+            format!("[Reap pkt {}]", self.pkt_num_)
+        };
+        CxxString(s).within_unique_ptr()
+    }
+}
+
+/// The loopback never paces, `Rpc::get_wheel` returns null.
+pub struct TimingWheel {
+    record_vec: CxxVector<wheel_record_t>,
+}
+
+impl TimingWheel {
+    pub fn get_record_vec(self: Pin<&mut Self>) -> Pin<&mut CxxVector<wheel_record_t>> {
+        unsafe { self.map_unchecked_mut(|w| &mut w.record_vec) }
+    }
+}
+
+struct Pending {
+    resp_msgbuf: *mut MsgBuffer,
+    cont_func: ContFunc,
+    tag: *mut c_void,
+    start_tsc: usize,
+}
+
+struct ClientSession {
+    remote_uri: String,
+    rem_rpc_id: u8,
+    /// The server's mailbox and session number, once connected.
+    peer: Option<(Weak<Mailbox>, u16)>,
+    connected: bool,
+    next_req_num: u64,
+    pending: HashMap<u64, Pending>,
+    /// Retransmissions injected by the simulation since the last reset.
+    num_re_tx: usize,
+    timely: Box<Timely>,
+}
+
+struct ServerSession {
+    peer: Weak<Mailbox>,
+    client_session: u16,
+}
+
+enum Session {
+    Client(ClientSession),
+    Server(ServerSession),
+}
+
+pub struct Rpc {
+    host: Arc<Host>,
+    rpc_id: u8,
+    context: *mut c_void,
+    sm_handler: Option<SmHandler>,
+    mailbox: Arc<Mailbox>,
+    sessions: Vec<Option<Session>>,
+    /// Client sessions waiting for their remote `Rpc` to be created.
+    connect_retries: Vec<u16>,
+    retry_connect_on_invalid_rpc_id: bool,
+    creation_tsc: usize,
+    ev_loop_tsc: usize,
+    /// Bytes allocated by the user and not freed yet. Background threads
+    /// allocate and respond as well.
+    user_alloc_tot: AtomicUsize,
+}
+
+impl Rpc {
+    /// # Safety
+    ///
+    /// `nexus` must outlive the `Rpc`, and `sm_handler` must be null or an
+    /// `extern "C" fn(c_int, SmEventType, SmErrType, *mut c_void)`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the nexus already has an `Rpc` with `rpc_id`.
+    pub unsafe fn new(
+        nexus: *mut Nexus,
+        context: *mut c_void,
+        rpc_id: u8,
+        sm_handler: *mut c_void,
+        _phy_port: u8,
+    ) -> Rpc {
+        let host = (*nexus).host.clone();
+        let mailbox = Arc::new(Mailbox::new(&host.uri, rpc_id));
+        {
+            let mut rpcs = host.rpcs.lock().unwrap();
+            rpcs.retain(|_, m| m.strong_count() > 0);
+            assert!(
+                !rpcs.contains_key(&rpc_id),
+                "loopback: Rpc {rpc_id} already exists on {}",
+                host.uri
+            );
+            rpcs.insert(rpc_id, Arc::downgrade(&mailbox));
+        }
+        let now = rdtsc();
+        Rpc {
+            host,
+            rpc_id,
+            context,
+            sm_handler: (!sm_handler.is_null())
+                .then(|| mem::transmute::<*mut c_void, SmHandler>(sm_handler)),
+            mailbox,
+            sessions: Vec::new(),
+            connect_retries: Vec::new(),
+            retry_connect_on_invalid_rpc_id: false,
+            creation_tsc: now,
+            ev_loop_tsc: now,
+            user_alloc_tot: AtomicUsize::new(0),
+        }
+    }
+
+    /// # Safety
+    ///
+    /// `msgbuf` must point to a valid buffer.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `new_data_size` exceeds the buffer's max data size.
+    pub unsafe fn resize_msg_buffer(msgbuf: *mut MsgBuffer, new_data_size: usize) {
+        let msgbuf = &mut *msgbuf;
+        assert!(
+            new_data_size <= msgbuf.max_data_size,
+            "loopback: resize to {new_data_size} exceeds max data size {}",
+            msgbuf.max_data_size
+        );
+        msgbuf.data_size = new_data_size;
+    }
+
+    pub fn create_session(self: Pin<&mut Self>, remote_uri: &str, rem_rpc_id: u8) -> c_int {
+        if !remote_uri.contains(':') {
+            return c_int(-(EINVAL as i32));
+        }
+        let this = unsafe { self.get_unchecked_mut() };
+        let sid = this.sessions.len() as u16;
+        this.sessions.push(Some(Session::Client(ClientSession {
+            remote_uri: remote_uri.to_owned(),
+            rem_rpc_id,
+            peer: None,
+            connected: false,
+            next_req_num: 0,
+            pending: HashMap::new(),
+            num_re_tx: 0,
+            timely: Box::new(Timely {
+                rate_gbps: BANDWIDTH as f64 * 8.0 / 1e9,
+                rtts_us: Vec::new(),
+            }),
+        })));
+        this.connect(sid);
+        c_int(sid as i32)
+    }
+
+    /// Send the connect request of client session `sid`. Failures are
+    /// reported to the session management handler by the event loop.
+    fn connect(&mut self, sid: u16) {
+        let (remote_uri, rem_rpc_id) = match self.client_session(sid) {
+            Some(s) => (s.remote_uri.clone(), s.rem_rpc_id),
+            None => return,
+        };
+        let err = match lookup_host(&remote_uri) {
+            None => SmErrType::kRoutingResolutionFailure,
+            Some(host) => {
+                let remote = host.rpcs.lock().unwrap().get(&rem_rpc_id).cloned();
+                match remote.and_then(|m| m.upgrade()) {
+                    Some(remote) => {
+                        self.mailbox.send(
+                            &remote,
+                            Packet::Connect {
+                                from: Arc::downgrade(&self.mailbox),
+                                client_session: sid,
+                            },
+                        );
+                        return;
+                    }
+                    None if self.retry_connect_on_invalid_rpc_id => {
+                        self.connect_retries.push(sid);
+                        return;
+                    }
+                    None => SmErrType::kInvalidRemoteRpcId,
+                }
+            }
+        };
+        self.mailbox.push(Packet::ConnectResp {
+            from: Weak::new(),
+            client_session: sid,
+            res: Err(err),
+        });
+    }
+
+    pub fn destroy_session(self: Pin<&mut Self>, session_num: c_int) -> c_int {
+        let this = unsafe { self.get_unchecked_mut() };
+        let mailbox = this.mailbox.clone();
+        let sid = session_num.0 as u16;
+        let session = match this.client_session(sid) {
+            Some(s) => s,
+            None => return c_int(-(EINVAL as i32)),
+        };
+        if !session.connected {
+            return c_int(-(EPERM as i32));
+        }
+        if !session.pending.is_empty() {
+            return c_int(-(EBUSY as i32));
+        }
+        if let Some((peer, server_session)) = session.peer.as_ref() {
+            if let Some(peer) = peer.upgrade() {
+                mailbox.send(
+                    &peer,
+                    Packet::Disconnect {
+                        server_session: *server_session,
+                    },
+                );
+            }
+        }
+        session.connected = false;
+        mailbox.push(Packet::Disconnected {
+            client_session: sid,
+            err: SmErrType::kNoError,
+        });
+        c_int(0)
+    }
+
+    pub fn is_connected(&self, session_num: c_int) -> bool {
+        match self.sessions.get(session_num.0 as usize) {
+            Some(Some(Session::Client(s))) => s.connected,
+            _ => false,
+        }
+    }
+
+    pub fn run_event_loop_once(self: Pin<&mut Self>) {
+        let this = unsafe { self.get_unchecked_mut() } as *mut Rpc;
+        unsafe {
+            let now = rdtsc();
+            (*this).ev_loop_tsc = now;
+            for sid in mem::take(&mut (*this).connect_retries) {
+                (*this).connect(sid);
+            }
+            for packet in (*this).mailbox.drain(now) {
+                Rpc::handle(this, packet);
+            }
+        }
+    }
+
+    pub fn run_event_loop(mut self: Pin<&mut Self>, timeout_ms: usize) {
+        let timeout_tsc = ms_to_cycles(timeout_ms as f64, FREQ_GHZ);
+        let start_tsc = rdtsc();
+        while rdtsc() - start_tsc < timeout_tsc {
+            self.as_mut().run_event_loop_once();
+        }
+    }
+
+    /// Handle one packet. Callbacks may reenter the `Rpc` through their
+    /// context, so no borrow of it is held while they run.
+    unsafe fn handle(this: *mut Rpc, packet: Packet) {
+        match packet {
+            Packet::Connect {
+                from,
+                client_session,
+            } => {
+                let rpc = &mut *this;
+                let sid = rpc.sessions.len() as u16;
+                rpc.sessions.push(Some(Session::Server(ServerSession {
+                    peer: from.clone(),
+                    client_session,
+                })));
+                if let Some(from) = from.upgrade() {
+                    rpc.mailbox.send(
+                        &from,
+                        Packet::ConnectResp {
+                            from: Arc::downgrade(&rpc.mailbox),
+                            client_session,
+                            res: Ok(sid),
+                        },
+                    );
+                }
+            }
+            Packet::ConnectResp {
+                from,
+                client_session,
+                res,
+            } => {
+                let (event, err) = {
+                    let rpc = &mut *this;
+                    let session = match rpc.client_session(client_session) {
+                        Some(s) => s,
+                        None => return,
+                    };
+                    match res {
+                        Ok(server_session) => {
+                            session.peer = Some((from, server_session));
+                            session.connected = true;
+                            (SmEventType::kConnected, SmErrType::kNoError)
+                        }
+                        Err(err) => {
+                            // Like eRPC, the failed session is buried.
+                            rpc.sessions[client_session as usize] = None;
+                            (SmEventType::kConnectFailed, err)
+                        }
+                    }
+                };
+                Rpc::sm_event(this, client_session, event, err);
+            }
+            Packet::Request {
+                server_session,
+                req_num,
+                req_type,
+                data,
+            } => {
+                let ((req_func, req_func_type), req_handle, context) = {
+                    let rpc = &mut *this;
+                    let (peer, client_session) = match rpc.sessions.get(server_session as usize) {
+                        Some(Some(Session::Server(s))) => (s.peer.clone(), s.client_session),
+                        _ => return,
+                    };
+                    // eRPC drops requests of unregistered types as well.
+                    let req_func = match rpc.host.req_funcs.lock().unwrap().get(&req_type) {
+                        Some(f) => *f,
+                        None => return,
+                    };
+                    let req_handle = Box::into_raw(Box::new(ReqHandle {
+                        req_msgbuf: MsgBuffer::from_slice(&data),
+                        pre_resp_msgbuf: MsgBuffer::alloc(PRE_RESP_MSGBUF_SIZE),
+                        dyn_resp_msgbuf: MsgBuffer::invalid(),
+                        peer,
+                        client_session,
+                        req_num,
+                    }));
+                    (req_func, req_handle, rpc.context)
+                };
+                match req_func_type {
+                    ReqFuncType::kForeground => req_func(req_handle, context),
+                    ReqFuncType::kBackground => {
+                        let rpc = &*this;
+                        if let Some(bg_jobs) = rpc.host.bg_jobs.lock().unwrap().as_ref() {
+                            rpc.host.bg_queued.fetch_add(1, Ordering::AcqRel);
+                            let _ = bg_jobs.send(BgJob {
+                                req_func,
+                                req_handle,
+                                context,
+                            });
+                        }
+                    }
+                }
+            }
+            Packet::Response {
+                client_session,
+                req_num,
+                data,
+                re_tx,
+            } => {
+                let (pending, context) = {
+                    let rpc = &mut *this;
+                    let session = match rpc.client_session(client_session) {
+                        Some(s) => s,
+                        None => return,
+                    };
+                    // The client retransmits the request when the response
+                    // is lost.
+                    session.num_re_tx += re_tx as usize;
+                    let pending = match session.pending.remove(&req_num) {
+                        Some(p) => p,
+                        None => return,
+                    };
+                    let rtts_us = &mut session.timely.rtts_us;
+                    if rtts_us.len() < MAX_RTT_SAMPLES {
+                        rtts_us.push(to_usec(rdtsc() - pending.start_tsc, FREQ_GHZ));
+                    }
+                    (pending, rpc.context)
+                };
+                let resp_msgbuf = &mut *pending.resp_msgbuf;
+                Rpc::resize_msg_buffer(resp_msgbuf, data.len());
+                ptr::copy_nonoverlapping(data.as_ptr(), resp_msgbuf.buf, data.len());
+                (pending.cont_func)(context, pending.tag);
+            }
+            Packet::Disconnect { server_session } => {
+                let rpc = &mut *this;
+                if let Some(slot) = rpc.sessions.get_mut(server_session as usize) {
+                    *slot = None;
+                }
+            }
+            Packet::Disconnected {
+                client_session,
+                err,
+            } => {
+                {
+                    let rpc = &mut *this;
+                    // Destroyed sessions are buried, failed ones are kept
+                    // with their pending requests.
+                    if err == SmErrType::kNoError {
+                        rpc.sessions[client_session as usize] = None;
+                    } else {
+                        match rpc.client_session(client_session) {
+                            Some(s) => s.connected = false,
+                            None => return,
+                        }
+                    }
+                }
+                Rpc::sm_event(this, client_session, SmEventType::kDisconnected, err);
+            }
+            Packet::Reset { server_session } => {
+                let Rpc {
+                    sessions, mailbox, ..
+                } = &mut *this;
+                for (sid, slot) in sessions.iter_mut().enumerate() {
+                    if matches!(server_session, Some(s) if s as usize != sid) {
+                        continue;
+                    }
+                    match slot {
+                        Some(Session::Server(s)) => {
+                            if let Some(peer) = s.peer.upgrade() {
+                                mailbox.send(
+                                    &peer,
+                                    Packet::Disconnected {
+                                        client_session: s.client_session,
+                                        err: SmErrType::kSrvDisconnected,
+                                    },
+                                );
+                            }
+                            *slot = None;
+                        }
+                        Some(Session::Client(s)) if s.connected && server_session.is_none() => {
+                            if let Some((peer, server_session)) = s.peer.as_ref() {
+                                if let Some(peer) = peer.upgrade() {
+                                    mailbox.send(
+                                        &peer,
+                                        Packet::Disconnect {
+                                            server_session: *server_session,
+                                        },
+                                    );
+                                }
+                            }
+                            mailbox.push(Packet::Disconnected {
+                                client_session: sid as u16,
+                                err: SmErrType::kSrvDisconnected,
+                            });
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+
+    unsafe fn sm_event(this: *mut Rpc, sid: u16, event: SmEventType, err: SmErrType) {
+        let (sm_handler, context) = ((*this).sm_handler, (*this).context);
+        if let Some(sm_handler) = sm_handler {
+            sm_handler(c_int(sid as i32), event, err, context);
+        }
+    }
+
+    fn client_session(&mut self, sid: u16) -> Option<&mut ClientSession> {
+        match self.sessions.get_mut(sid as usize) {
+            Some(Some(Session::Client(s))) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn get_ev_loop_tsc(&self) -> usize {
+        self.ev_loop_tsc
+    }
+
+    /// # Safety
+    ///
+    /// `context` is passed to the handlers and continuations as is.
+    pub unsafe fn set_context(self: Pin<&mut Self>, context: *mut c_void) {
+        self.get_unchecked_mut().context = context;
+    }
+
+    pub fn alloc_msg_buffer_or_die(self: Pin<&mut Self>, max_data_size: usize) -> MsgBuffer {
+        self.alloc_msg_buffer(max_data_size)
+    }
+
+    pub fn alloc_msg_buffer(self: Pin<&mut Self>, max_data_size: usize) -> MsgBuffer {
+        self.user_alloc_tot
+            .fetch_add(max_data_size, Ordering::Relaxed);
+        MsgBuffer::alloc(max_data_size)
+    }
+
+    pub fn free_msg_buffer(self: Pin<&mut Self>, msgbuf: &MsgBuffer) {
+        self.user_alloc_tot
+            .fetch_sub(msgbuf.max_data_size, Ordering::Relaxed);
+        unsafe { msgbuf.free() };
+    }
+
+    /// # Safety
+    ///
+    /// Both buffers must stay valid until `cont_func` runs, and `cont_func`
+    /// must be an `extern "C" fn(*mut c_void, *mut c_void)`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `session_num` isn't a connected client session.
+    #[allow(clippy::too_many_arguments)]
+    pub unsafe fn enqueue_request(
+        self: Pin<&mut Self>,
+        session_num: c_int,
+        req_type: u8,
+        req_msgbuf: *mut MsgBuffer,
+        resp_msgbuf: *mut MsgBuffer,
+        cont_func: *mut c_void,
+        tag: *mut c_void,
+        _bg_etid: usize,
+    ) {
+        let this = self.get_unchecked_mut();
+        let mailbox = this.mailbox.clone();
+        let sid = session_num.0 as u16;
+        let session = match this.client_session(sid) {
+            Some(s) if s.connected => s,
+            _ => panic!("loopback: session {sid} is not a connected client session"),
+        };
+        let req_num = session.next_req_num;
+        session.next_req_num += 1;
+        session.pending.insert(
+            req_num,
+            Pending {
+                resp_msgbuf,
+                cont_func: mem::transmute::<*mut c_void, ContFunc>(cont_func),
+                tag,
+                start_tsc: rdtsc(),
+            },
+        );
+        let (peer, server_session) = session.peer.clone().unwrap();
+        match peer.upgrade() {
+            Some(peer) => {
+                session.num_re_tx += mailbox.send(
+                    &peer,
+                    Packet::Request {
+                        server_session,
+                        req_num,
+                        req_type,
+                        data: (*req_msgbuf).data().to_vec(),
+                    },
+                ) as usize
+            }
+            None => {
+                // The server is gone, its requests stay pending.
+                session.connected = false;
+                mailbox.push(Packet::Disconnected {
+                    client_session: sid,
+                    err: SmErrType::kSrvDisconnected,
+                });
+            }
+        }
+    }
+
+    /// # Safety
+    ///
+    /// `req_handle` must come from a request handler of this `Rpc` and not
+    /// be used afterwards, `resp_msgbuf` must point to a valid buffer.
+    pub unsafe fn enqueue_response(
+        self: Pin<&mut Self>,
+        req_handle: *mut ReqHandle,
+        resp_msgbuf: *mut MsgBuffer,
+    ) {
+        let req_handle = Box::from_raw(req_handle);
+        if let Some(peer) = req_handle.peer.upgrade() {
+            self.mailbox.send(
+                &peer,
+                Packet::Response {
+                    client_session: req_handle.client_session,
+                    req_num: req_handle.req_num,
+                    data: (*resp_msgbuf).data().to_vec(),
+                    re_tx: 0,
+                },
+            );
+        }
+        req_handle.req_msgbuf.free();
+        req_handle.pre_resp_msgbuf.free();
+        // The dynamic response was allocated by the user.
+        if req_handle.dyn_resp_msgbuf.is_valid() {
+            self.user_alloc_tot
+                .fetch_sub(req_handle.dyn_resp_msgbuf.max_data_size, Ordering::Relaxed);
+            req_handle.dyn_resp_msgbuf.free();
+        }
+    }
+
+    /// # Panics
+    ///
+    /// Panics if `session_num` isn't a client session.
+    pub fn get_timely(self: Pin<&mut Self>, session_num: c_int) -> *mut Timely {
+        let this = unsafe { self.get_unchecked_mut() };
+        match this.client_session(session_num.0 as u16) {
+            Some(s) => &mut *s.timely,
+            None => panic!(
+                "loopback: session {} is not a client session",
+                session_num.0
+            ),
+        }
+    }
+
+    pub fn get_bandwidth(&self) -> usize {
+        BANDWIDTH
+    }
+
+    pub fn get_freq_ghz(&self) -> f64 {
+        FREQ_GHZ
+    }
+
+    pub fn get_rpc_id(&self) -> u8 {
+        self.rpc_id
+    }
+
+    pub fn get_wheel(self: Pin<&mut Self>) -> *mut TimingWheel {
+        ptr::null_mut()
+    }
+
+    /// Retransmissions of lost packets, which only the simulation injects.
+    pub fn get_num_re_tx(&self, session_num: c_int) -> usize {
+        match self.sessions.get(session_num.0 as usize) {
+            Some(Some(Session::Client(s))) => s.num_re_tx,
+            _ => 0,
+        }
+    }
+
+    pub fn reset_num_re_tx(self: Pin<&mut Self>, session_num: c_int) {
+        if let Some(s) = unsafe { self.get_unchecked_mut() }.client_session(session_num.0 as u16) {
+            s.num_re_tx = 0;
+        }
+    }
+
+    pub fn sec_since_creation(self: Pin<&mut Self>) -> f64 {
+        to_usec(rdtsc() - self.creation_tsc, FREQ_GHZ) / 1e6
+    }
+
+    pub fn force_retry_connect_on_invalid_rpc_id(self: Pin<&mut Self>) {
+        unsafe { self.get_unchecked_mut() }.retry_connect_on_invalid_rpc_id = true;
+    }
+
+    pub fn get_max_msg_size(&self) -> usize {
+        MAX_MSG_SIZE
+    }
+
+    pub fn get_max_data_per_pkt(&self) -> usize {
+        MAX_DATA_PER_PKT
+    }
+
+    pub fn get_stat_user_alloc_tot(self: Pin<&mut Self>) -> usize {
+        self.user_alloc_tot.load(Ordering::Relaxed)
+    }
+
+    /// Connected client and server sessions.
+    pub fn num_active_sessions(self: Pin<&mut Self>) -> usize {
+        self.sessions
+            .iter()
+            .filter(|s| match s {
+                Some(Session::Client(s)) => s.connected,
+                Some(Session::Server(_)) => true,
+                None => false,
+            })
+            .count()
+    }
+
+    /// Requests queued for the background threads of the `Nexus`, or being
+    /// handled by them.
+    pub fn bg_queue_len(&self) -> usize {
+        self.host.bg_queued.load(Ordering::Acquire)
+    }
+
+    pub fn is_client_session(&self, session_num: c_int) -> bool {
+        matches!(
+            self.sessions.get(session_num.0 as usize),
+            Some(Some(Session::Client(_)))
+        )
+    }
+
+    /// One credit per request in flight, eRPC's are per packet. Returns
+    /// `None` if `session_num` isn't a client session.
+    pub fn session_credits(&self, session_num: c_int) -> Option<usize> {
+        match self.sessions.get(session_num.0 as usize) {
+            Some(Some(Session::Client(s))) => Some(kSessionCredits.saturating_sub(s.pending.len())),
+            _ => None,
+        }
+    }
+}
+
+impl Drop for Rpc {
+    fn drop(&mut self) {
+        let mut rpcs = self.host.rpcs.lock().unwrap();
+        let mailbox = Arc::downgrade(&self.mailbox);
+        if matches!(rpcs.get(&self.rpc_id), Some(m) if m.ptr_eq(&mailbox)) {
+            rpcs.remove(&self.rpc_id);
+        }
+    }
+}
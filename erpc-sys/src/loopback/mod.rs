@@ -0,0 +1,81 @@
+// Copyright (c) 2023, IOMesh Inc. All rights reserved.
+
+//! A pure-Rust, in-process stand-in for the eRPC bindings, enabled by the
+//! `loopback` feature.
+//!
+//! It exposes the same Rust-visible types and functions as the generated
+//! bindings, so crates built on top of them run unchanged. Nexuses are
+//! registered by URI in a process-wide table and messages are copied between
+//! the `Rpc`s' mailboxes, which are drained by `run_event_loop_once`. There's
+//! no packetization, congestion control or retransmission: messages are
+//! delivered in order and never lost, unless a [`sim::Simulation`] injects
+//! faults.
+
+#![allow(non_camel_case_types, non_upper_case_globals)]
+
+pub mod erpc;
+pub mod sim;
+
+pub use crate::shim::*;
+
+pub mod erpc_wrapper {
+    use std::pin::Pin;
+
+    use super::c_int;
+    use super::erpc::{self, wheel_record_t, Rpc};
+
+    #[inline]
+    pub fn wheel_record_is_insert(record: &wheel_record_t) -> bool {
+        record.insert_
+    }
+
+    #[inline]
+    pub fn wheel_record_pkt_num(record: &wheel_record_t) -> usize {
+        record.pkt_num_
+    }
+
+    #[inline]
+    pub fn wheel_record_abs_tx_tsc(record: &wheel_record_t) -> usize {
+        record.abs_tx_tsc_
+    }
+
+    #[inline]
+    pub fn rpc_max_msg_size(rpc: &Rpc) -> usize {
+        rpc.get_max_msg_size()
+    }
+
+    #[inline]
+    pub fn rpc_max_data_per_pkt(rpc: &Rpc) -> usize {
+        rpc.get_max_data_per_pkt()
+    }
+
+    #[inline]
+    pub fn rpc_mtu() -> usize {
+        erpc::MTU
+    }
+
+    #[inline]
+    pub fn rpc_stat_user_alloc_tot(rpc: Pin<&mut Rpc>) -> usize {
+        rpc.get_stat_user_alloc_tot()
+    }
+
+    #[inline]
+    pub fn rpc_num_active_sessions(rpc: Pin<&mut Rpc>) -> usize {
+        rpc.num_active_sessions()
+    }
+
+    #[inline]
+    pub fn rpc_is_client_session(rpc: &Rpc, session_num: c_int) -> bool {
+        rpc.is_client_session(session_num)
+    }
+
+    #[inline]
+    pub fn rpc_session_credits(rpc: &Rpc, session_num: c_int) -> i64 {
+        rpc.session_credits(session_num).map_or(-1, |c| c as i64)
+    }
+
+    #[inline]
+    pub fn rpc_bg_queue_len(rpc: &Rpc) -> usize {
+        rpc.bg_queue_len()
+    }
+}
@@ -0,0 +1,413 @@
+// Copyright (c) 2023, IOMesh Inc. All rights reserved.
+
+//! A seeded network simulation on top of the loopback.
+//!
+//! While a [`Simulation`] is installed, `rdtsc` reads a virtual clock and
+//! every packet between two `Rpc`s gets a delivery time on it, so latency,
+//! loss, reordering, session resets and server stalls can be injected.
+//! Faults are drawn from the seed, the link and the packet's index on the
+//! link rather than from a shared generator, so they don't depend on how
+//! the poll threads interleave: a scenario replays from its seed as long as
+//! every `Rpc` sends the same packets in the same order.
+//!
+//! Install the simulation before creating any `Nexus`, and drop it once
+//! they're gone.
+//!
+//! ```no_run
+//! use std::time::Duration;
+//!
+//! use erpc_sys::sim::SimBuilder;
+//!
+//! let sim = SimBuilder::new(42)
+//!     .latency(Duration::from_micros(5), Duration::from_micros(2))
+//!     .loss(0.01)
+//!     .auto_advance(Duration::from_micros(1))
+//!     .install();
+//! // ... run clients and servers, then on failure:
+//! for fault in sim.faults() {
+//!     println!("{fault:?}");
+//! }
+//! ```
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use super::erpc;
+
+/// eRPC's default retransmission timeout.
+const DEFAULT_RTO: Duration = Duration::from_millis(5);
+/// A packet is retransmitted at most this many times, then it goes through.
+const MAX_RE_TX: u32 = 8;
+/// How often the auto-advancing clock looks for work, in real time.
+const DRIVER_INTERVAL: Duration = Duration::from_micros(10);
+
+static INSTALLED: AtomicBool = AtomicBool::new(false);
+static STATE: Mutex<Option<Arc<State>>> = Mutex::new(None);
+/// The virtual TSC while a simulation is installed.
+static NOW: AtomicUsize = AtomicUsize::new(0);
+/// Keeps `rdtsc` monotonic once a simulation that ran ahead is dropped.
+static OFFSET: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns the virtual TSC if a simulation is installed.
+pub(super) fn now_tsc() -> Option<usize> {
+    INSTALLED
+        .load(Ordering::Acquire)
+        .then(|| NOW.load(Ordering::Acquire))
+}
+
+pub(super) fn clock_offset() -> usize {
+    OFFSET.load(Ordering::Relaxed)
+}
+
+fn to_tsc(d: Duration) -> usize {
+    d.as_nanos() as usize
+}
+
+/// The fault that hit a packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FaultKind {
+    /// The packet was lost and retransmitted after the RTO this many times.
+    Loss { re_tx: u32 },
+    /// The packet was held back, letting later ones overtake it.
+    Reorder,
+    /// A request reset its session instead of reaching the server.
+    SessionReset,
+    /// A request stalled the server's event loop.
+    ServerStall { duration: Duration },
+}
+
+/// A fault injected by the simulation.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Fault {
+    /// The virtual time the packet was sent at, since installation. With
+    /// an auto-advancing clock it varies with thread scheduling.
+    pub at: Duration,
+    /// The sending `Rpc`, as `uri/rpc_id`.
+    pub from: String,
+    /// The receiving `Rpc`, as `uri/rpc_id`.
+    pub to: String,
+    /// The packet's index on the link from `from` to `to`.
+    pub index: u64,
+    pub kind: FaultKind,
+}
+
+/// Where and when a packet is delivered.
+pub(super) struct Route {
+    pub(super) deliver_at: usize,
+    /// How often the packet was retransmitted.
+    pub(super) re_tx: u32,
+    /// Reset the session instead of delivering the request.
+    pub(super) reset: bool,
+    /// Stall the receiver until then.
+    pub(super) stall_until: Option<usize>,
+}
+
+/// Configures and installs a [`Simulation`]. All faults are off by default.
+#[derive(Debug, Clone)]
+pub struct SimBuilder {
+    seed: u64,
+    latency: Duration,
+    jitter: Duration,
+    loss: f64,
+    rto: Duration,
+    reorder: f64,
+    reorder_delay: Duration,
+    session_reset: f64,
+    server_stall: f64,
+    stall_duration: Duration,
+    auto_advance: Option<Duration>,
+}
+
+impl SimBuilder {
+    pub fn new(seed: u64) -> SimBuilder {
+        SimBuilder {
+            seed,
+            latency: Duration::ZERO,
+            jitter: Duration::ZERO,
+            loss: 0.0,
+            rto: DEFAULT_RTO,
+            reorder: 0.0,
+            reorder_delay: Duration::ZERO,
+            session_reset: 0.0,
+            server_stall: 0.0,
+            stall_duration: Duration::ZERO,
+            auto_advance: None,
+        }
+    }
+
+    /// Delay every packet by `base` plus up to `jitter`.
+    pub fn latency(mut self, base: Duration, jitter: Duration) -> SimBuilder {
+        self.latency = base;
+        self.jitter = jitter;
+        self
+    }
+
+    /// Lose packets with `probability`. Like eRPC, a lost packet is
+    /// retransmitted after the RTO, so loss shows up as latency and in the
+    /// client session's `get_num_re_tx`.
+    pub fn loss(mut self, probability: f64) -> SimBuilder {
+        self.loss = probability;
+        self
+    }
+
+    /// Set the retransmission timeout, 5 ms by default.
+    pub fn rto(mut self, rto: Duration) -> SimBuilder {
+        self.rto = rto;
+        self
+    }
+
+    /// Hold back packets by `delay` with `probability`.
+    pub fn reorder(mut self, probability: f64, delay: Duration) -> SimBuilder {
+        self.reorder = probability;
+        self.reorder_delay = delay;
+        self
+    }
+
+    /// Reset the session of a request with `probability`. The server drops
+    /// the session and the client gets `kDisconnected` with
+    /// `kSrvDisconnected`, its pending requests are left unanswered.
+    pub fn session_reset(mut self, probability: f64) -> SimBuilder {
+        self.session_reset = probability;
+        self
+    }
+
+    /// Stall the server's event loop for `duration` on arrival of a request
+    /// with `probability`.
+    pub fn server_stall(mut self, probability: f64, duration: Duration) -> SimBuilder {
+        self.server_stall = probability;
+        self.stall_duration = duration;
+        self
+    }
+
+    /// Advance the virtual clock from a background thread whenever no
+    /// packet is ready: straight to the next delivery, by at most `step`.
+    /// Without it the clock only moves through [`Simulation::advance`].
+    pub fn auto_advance(mut self, step: Duration) -> SimBuilder {
+        self.auto_advance = Some(step);
+        self
+    }
+
+    /// # Panics
+    ///
+    /// Panics if another simulation is installed.
+    pub fn install(self) -> Simulation {
+        let mut installed = STATE.lock().unwrap();
+        assert!(installed.is_none(), "loopback: a simulation is installed");
+        let start_tsc = erpc::rdtsc();
+        NOW.store(start_tsc, Ordering::Release);
+        INSTALLED.store(true, Ordering::Release);
+        let state = Arc::new(State {
+            config: self,
+            start_tsc,
+            links: Mutex::new(HashMap::new()),
+            faults: Mutex::new(Vec::new()),
+            stopped: AtomicBool::new(false),
+        });
+        *installed = Some(state.clone());
+        let driver = state.config.auto_advance.map(|step| {
+            let state = state.clone();
+            thread::spawn(move || drive(&state, to_tsc(step)))
+        });
+        Simulation { state, driver }
+    }
+}
+
+struct State {
+    config: SimBuilder,
+    start_tsc: usize,
+    /// The packets sent so far per link.
+    links: Mutex<HashMap<(String, String), u64>>,
+    faults: Mutex<Vec<Fault>>,
+    stopped: AtomicBool,
+}
+
+impl State {
+    fn route(&self, from: &str, to: &str, request: bool) -> Route {
+        let index = {
+            let mut links = self.links.lock().unwrap();
+            let count = links.entry((from.to_owned(), to.to_owned())).or_insert(0);
+            *count += 1;
+            *count - 1
+        };
+        let c = &self.config;
+        let mut rng = Rng::new(c.seed, from, to, index);
+        let now = NOW.load(Ordering::Acquire);
+        let mut faults = Vec::new();
+
+        let mut delay = to_tsc(c.latency) + rng.below(to_tsc(c.jitter) as u64 + 1) as usize;
+        let mut re_tx = 0;
+        while re_tx < MAX_RE_TX && rng.chance(c.loss) {
+            re_tx += 1;
+        }
+        if re_tx > 0 {
+            delay += to_tsc(c.rto) * re_tx as usize;
+            faults.push(FaultKind::Loss { re_tx });
+        }
+        if rng.chance(c.reorder) {
+            delay += to_tsc(c.reorder_delay);
+            faults.push(FaultKind::Reorder);
+        }
+        let deliver_at = now + delay;
+        let mut route = Route {
+            deliver_at,
+            re_tx,
+            reset: false,
+            stall_until: None,
+        };
+        if request && rng.chance(c.session_reset) {
+            route.reset = true;
+            faults.push(FaultKind::SessionReset);
+        } else if request && rng.chance(c.server_stall) {
+            route.stall_until = Some(deliver_at + to_tsc(c.stall_duration));
+            faults.push(FaultKind::ServerStall {
+                duration: c.stall_duration,
+            });
+        }
+
+        if !faults.is_empty() {
+            let at = Duration::from_nanos((now - self.start_tsc) as u64);
+            self.faults
+                .lock()
+                .unwrap()
+                .extend(faults.into_iter().map(|kind| Fault {
+                    at,
+                    from: from.to_owned(),
+                    to: to.to_owned(),
+                    index,
+                    kind,
+                }));
+        }
+        route
+    }
+}
+
+/// Route a packet from `from` to `to`, both `uri/rpc_id`. Returns `None`
+/// if no simulation is installed.
+pub(super) fn route(from: &str, to: &str, request: bool) -> Option<Route> {
+    if !INSTALLED.load(Ordering::Acquire) {
+        return None;
+    }
+    let state = STATE.lock().unwrap().clone()?;
+    Some(state.route(from, to, request))
+}
+
+fn drive(state: &State, step: usize) {
+    while !state.stopped.load(Ordering::Acquire) {
+        let now = NOW.load(Ordering::Acquire);
+        match erpc::next_delivery() {
+            // The poll threads have work to do.
+            Some(at) if at <= now => {}
+            Some(at) => NOW.store(at.min(now + step), Ordering::Release),
+            None => NOW.store(now + step, Ordering::Release),
+        }
+        thread::sleep(DRIVER_INTERVAL);
+    }
+}
+
+/// An installed network simulation, uninstalled on drop.
+pub struct Simulation {
+    state: Arc<State>,
+    driver: Option<JoinHandle<()>>,
+}
+
+impl Simulation {
+    pub fn seed(&self) -> u64 {
+        self.state.config.seed
+    }
+
+    /// Returns the virtual time since installation.
+    pub fn now(&self) -> Duration {
+        Duration::from_nanos((NOW.load(Ordering::Acquire) - self.state.start_tsc) as u64)
+    }
+
+    /// Move the virtual clock forward by `d`.
+    pub fn advance(&self, d: Duration) {
+        NOW.fetch_add(to_tsc(d), Ordering::AcqRel);
+    }
+
+    /// Move the virtual clock to the next packet delivery or stall end, if
+    /// it's in the future. Returns false if nothing is in flight.
+    pub fn advance_to_next_event(&self) -> bool {
+        match erpc::next_delivery() {
+            Some(at) => {
+                NOW.fetch_max(at, Ordering::AcqRel);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Reset every session from and to the `Rpc`s of `uri`.
+    pub fn reset_sessions(&self, uri: &str) {
+        erpc::reset_sessions(uri);
+    }
+
+    /// Stall the event loops of the `Rpc`s of `uri` for `duration`.
+    pub fn stall(&self, uri: &str, duration: Duration) {
+        erpc::stall(uri, NOW.load(Ordering::Acquire) + to_tsc(duration));
+    }
+
+    /// Returns the faults injected so far, in the order they were drawn.
+    pub fn faults(&self) -> Vec<Fault> {
+        self.state.faults.lock().unwrap().clone()
+    }
+}
+
+impl Drop for Simulation {
+    fn drop(&mut self) {
+        self.state.stopped.store(true, Ordering::Release);
+        if let Some(driver) = self.driver.take() {
+            let _ = driver.join();
+        }
+        let mut installed = STATE.lock().unwrap();
+        INSTALLED.store(false, Ordering::Release);
+        let now = NOW.load(Ordering::Acquire);
+        OFFSET.store(
+            now.saturating_sub(erpc::rdtsc() - clock_offset()),
+            Ordering::Relaxed,
+        );
+        *installed = None;
+    }
+}
+
+/// SplitMix64, seeded per packet.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64, from: &str, to: &str, index: u64) -> Rng {
+        let mut rng = Rng(seed ^ fnv1a(from) ^ fnv1a(to).rotate_left(32));
+        rng.0 ^= rng.next_u64() ^ index;
+        rng
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next_u64() % n
+    }
+
+    /// Always draws, so the faults after a disabled one keep their values.
+    fn chance(&mut self, p: f64) -> bool {
+        let x = (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64;
+        x < p
+    }
+}
+
+fn fnv1a(s: &str) -> u64 {
+    s.bytes().fold(0xcbf2_9ce4_8422_2325, |h, b| {
+        (h ^ b as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
@@ -0,0 +1,208 @@
+// Copyright (c) 2023, IOMesh Inc. All rights reserved.
+
+//! Pure-Rust stand-ins for the `cxx` and `autocxx` types in the bindings,
+//! shared by the loopback and the stub.
+
+#![allow(non_camel_case_types)]
+
+use std::{
+    borrow::Cow,
+    fmt,
+    ops::{Deref, DerefMut},
+    pin::Pin,
+    ptr, str,
+};
+
+pub use std::ffi::c_void;
+
+/// No C++ object is ever moved without eRPC, so there's nothing to
+/// re-export from `moveit`.
+pub mod moveit {}
+
+#[repr(transparent)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct c_int(pub i32);
+
+impl From<i32> for c_int {
+    #[inline]
+    fn from(v: i32) -> c_int {
+        c_int(v)
+    }
+}
+
+impl From<c_int> for i32 {
+    #[inline]
+    fn from(v: c_int) -> i32 {
+        v.0
+    }
+}
+
+pub const EPERM: u32 = 1;
+pub const ENOMEM: u32 = 12;
+pub const EBUSY: u32 = 16;
+pub const EEXIST: u32 = 17;
+pub const EINVAL: u32 = 22;
+pub const EALREADY: u32 = 114;
+pub const ETIMEDOUT: u32 = 110;
+
+/// An owned, heap-allocated object, like `cxx::UniquePtr`.
+pub struct UniquePtr<T>(Option<Box<T>>);
+
+impl<T> UniquePtr<T> {
+    #[inline]
+    pub fn null() -> Self {
+        UniquePtr(None)
+    }
+
+    #[inline]
+    pub fn is_null(&self) -> bool {
+        self.0.is_none()
+    }
+
+    #[inline]
+    pub fn as_ref(&self) -> Option<&T> {
+        self.0.as_deref()
+    }
+
+    #[inline]
+    pub fn as_mut(&mut self) -> Option<Pin<&mut T>> {
+        self.0
+            .as_deref_mut()
+            .map(|r| unsafe { Pin::new_unchecked(r) })
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the pointer is null.
+    #[inline]
+    pub fn pin_mut(&mut self) -> Pin<&mut T> {
+        self.as_mut().expect("called pin_mut on a null UniquePtr")
+    }
+
+    #[inline]
+    pub fn into_raw(self) -> *mut T {
+        self.0.map_or(ptr::null_mut(), Box::into_raw)
+    }
+
+    /// # Safety
+    ///
+    /// `raw` must be null or come from [`UniquePtr::into_raw`], and nothing
+    /// else may own it.
+    #[inline]
+    pub unsafe fn from_raw(raw: *mut T) -> Self {
+        if raw.is_null() {
+            UniquePtr(None)
+        } else {
+            UniquePtr(Some(Box::from_raw(raw)))
+        }
+    }
+}
+
+impl<T> Deref for UniquePtr<T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        self.0.as_deref().expect("dereferenced a null UniquePtr")
+    }
+}
+
+impl<T> DerefMut for UniquePtr<T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut T {
+        self.0
+            .as_deref_mut()
+            .expect("dereferenced a null UniquePtr")
+    }
+}
+
+impl<T: fmt::Display> fmt::Display for UniquePtr<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.as_ref() {
+            Some(t) => t.fmt(f),
+            None => f.write_str("nullptr"),
+        }
+    }
+}
+
+/// Moves a value into a [`UniquePtr`], like `autocxx::WithinUniquePtr`.
+pub trait WithinUniquePtr {
+    type Inner;
+    fn within_unique_ptr(self) -> UniquePtr<Self::Inner>;
+}
+
+impl<T> WithinUniquePtr for T {
+    type Inner = T;
+
+    #[inline]
+    fn within_unique_ptr(self) -> UniquePtr<T> {
+        UniquePtr(Some(Box::new(self)))
+    }
+}
+
+/// A string returned by value, like `cxx::CxxString`.
+pub struct CxxString(pub(crate) String);
+
+impl CxxString {
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8] {
+        self.0.as_bytes()
+    }
+
+    #[inline]
+    pub fn to_str(&self) -> Result<&str, str::Utf8Error> {
+        Ok(&self.0)
+    }
+
+    #[inline]
+    pub fn to_string_lossy(&self) -> Cow<'_, str> {
+        Cow::Borrowed(&self.0)
+    }
+}
+
+impl fmt::Display for CxxString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl fmt::Debug for CxxString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.0, f)
+    }
+}
+
+/// A vector owned by an eRPC object, like `cxx::CxxVector`.
+pub struct CxxVector<T>(Vec<T>);
+
+impl<T> CxxVector<T> {
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    #[inline]
+    pub fn get(&self, pos: usize) -> Option<&T> {
+        self.0.get(pos)
+    }
+
+    #[inline]
+    pub fn index_mut(self: Pin<&mut Self>, pos: usize) -> Option<Pin<&mut T>> {
+        unsafe {
+            self.get_unchecked_mut()
+                .0
+                .get_mut(pos)
+                .map(|r| Pin::new_unchecked(r))
+        }
+    }
+
+    #[inline]
+    pub fn iter(&self) -> std::slice::Iter<'_, T> {
+        self.0.iter()
+    }
+}
@@ -0,0 +1,423 @@
+// Copyright (c) 2023, IOMesh Inc. All rights reserved.
+
+//! The eRPC bindings without eRPC, enabled by the `stub` feature.
+//!
+//! It declares the same Rust-visible types and functions as the generated
+//! bindings, so crates built on top of them type-check and document without
+//! a C++ toolchain or any native library. There's nothing to call into:
+//! every eRPC function panics. Use the `loopback` feature to run.
+
+#![allow(non_camel_case_types, non_upper_case_globals)]
+
+pub use crate::shim::*;
+
+/// Panics, there's no eRPC to call into.
+macro_rules! no_erpc {
+    () => {
+        unimplemented!("erpc-sys is built with the stub feature, eRPC isn't available")
+    };
+}
+
+pub mod erpc {
+    use std::pin::Pin;
+
+    use super::{c_int, c_void, CxxString, CxxVector, UniquePtr};
+
+    pub const kInvalidBgETid: usize = 8;
+    pub const kSessionCredits: usize = 32;
+    pub const kSessionReqWindow: usize = 8;
+    pub const kInvalidSessionNum: usize = u16::MAX as usize;
+
+    pub fn rdtsc() -> usize {
+        no_erpc!()
+    }
+
+    pub fn to_usec(_cycles: usize, _freq_ghz: f64) -> f64 {
+        no_erpc!()
+    }
+
+    pub fn ms_to_cycles(_ms: f64, _freq_ghz: f64) -> usize {
+        no_erpc!()
+    }
+
+    pub fn get_uri_for_process(_process_id: usize) -> UniquePtr<CxxString> {
+        no_erpc!()
+    }
+
+    #[repr(u32)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub enum SmErrType {
+        kNoError,
+        kSrvDisconnected,
+        kRingExhausted,
+        kOutOfMemory,
+        kRoutingResolutionFailure,
+        kInvalidRemoteRpcId,
+        kInvalidTransport,
+    }
+
+    #[repr(u32)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub enum SmEventType {
+        kConnected,
+        kConnectFailed,
+        kDisconnected,
+        kDisconnectFailed,
+    }
+
+    #[repr(u32)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub enum ReqFuncType {
+        kForeground,
+        kBackground,
+    }
+
+    pub fn sm_err_type_str(_err: SmErrType) -> UniquePtr<CxxString> {
+        no_erpc!()
+    }
+
+    pub fn sm_event_type_str(_event: SmEventType) -> UniquePtr<CxxString> {
+        no_erpc!()
+    }
+
+    pub struct ChronoTimer {
+        _private: (),
+    }
+
+    impl ChronoTimer {
+        #[allow(clippy::new_without_default)]
+        pub fn new() -> ChronoTimer {
+            no_erpc!()
+        }
+
+        pub fn reset(self: Pin<&mut Self>) {
+            no_erpc!()
+        }
+
+        pub fn get_ns(&self) -> usize {
+            no_erpc!()
+        }
+
+        pub fn get_us(&self) -> usize {
+            no_erpc!()
+        }
+
+        pub fn get_sec(&self) -> f64 {
+            no_erpc!()
+        }
+    }
+
+    pub struct FastRand {
+        _private: (),
+    }
+
+    impl FastRand {
+        #[allow(clippy::new_without_default)]
+        pub fn new() -> FastRand {
+            no_erpc!()
+        }
+
+        pub fn next_u32(self: Pin<&mut Self>) -> u32 {
+            no_erpc!()
+        }
+    }
+
+    pub struct MsgBuffer {
+        _private: (),
+    }
+
+    impl MsgBuffer {
+        pub fn get_inner_buf(&self) -> *mut u8 {
+            no_erpc!()
+        }
+
+        pub fn get_data_size(&self) -> usize {
+            no_erpc!()
+        }
+
+        pub fn get_max_data_size(&self) -> usize {
+            no_erpc!()
+        }
+
+        pub fn is_valid(&self) -> bool {
+            no_erpc!()
+        }
+    }
+
+    pub struct ReqHandle {
+        _private: (),
+    }
+
+    impl ReqHandle {
+        pub fn get_req_msgbuf(self: Pin<&mut Self>) -> *const MsgBuffer {
+            no_erpc!()
+        }
+
+        pub fn get_pre_resp_msgbuf(self: Pin<&mut Self>) -> MsgBuffer {
+            no_erpc!()
+        }
+
+        pub fn get_dyn_resp_msgbuf(self: Pin<&mut Self>) -> MsgBuffer {
+            no_erpc!()
+        }
+
+        pub fn init_dyn_resp_msgbuf_from_allocated(
+            self: Pin<&mut Self>,
+            _msgbuf: Pin<&mut MsgBuffer>,
+        ) -> MsgBuffer {
+            no_erpc!()
+        }
+    }
+
+    pub struct Nexus {
+        _private: (),
+    }
+
+    impl Nexus {
+        pub fn new(_local_uri: &str, _numa_node: usize, _num_bg_threads: usize) -> Nexus {
+            no_erpc!()
+        }
+
+        /// # Safety
+        ///
+        /// Never called, it panics.
+        pub unsafe fn register_req_func(
+            self: Pin<&mut Self>,
+            _req_type: u8,
+            _req_func: *mut c_void,
+            _req_func_type: ReqFuncType,
+        ) -> c_int {
+            no_erpc!()
+        }
+    }
+
+    pub struct Timely {
+        _private: (),
+    }
+
+    impl Timely {
+        pub fn set_rate(self: Pin<&mut Self>, _rate: f64) {
+            no_erpc!()
+        }
+
+        pub fn get_rtt_perc(self: Pin<&mut Self>, _perc: f64) -> f64 {
+            no_erpc!()
+        }
+
+        pub fn reset_rtt_stats(self: Pin<&mut Self>) {
+            no_erpc!()
+        }
+
+        pub fn get_rate_gbps(&self) -> f64 {
+            no_erpc!()
+        }
+    }
+
+    pub struct wheel_record_t {
+        _private: (),
+    }
+
+    impl wheel_record_t {
+        pub fn to_string(
+            self: Pin<&mut Self>,
+            _console_ref_tsc: usize,
+            _freq_ghz: f64,
+        ) -> UniquePtr<CxxString> {
+            no_erpc!()
+        }
+    }
+
+    pub struct TimingWheel {
+        _private: (),
+    }
+
+    impl TimingWheel {
+        pub fn get_record_vec(self: Pin<&mut Self>) -> Pin<&mut CxxVector<wheel_record_t>> {
+            no_erpc!()
+        }
+    }
+
+    pub struct Rpc {
+        _private: (),
+    }
+
+    impl Rpc {
+        /// # Safety
+        ///
+        /// Never called, it panics.
+        pub unsafe fn new(
+            _nexus: *mut Nexus,
+            _context: *mut c_void,
+            _rpc_id: u8,
+            _sm_handler: *mut c_void,
+            _phy_port: u8,
+        ) -> Rpc {
+            no_erpc!()
+        }
+
+        /// # Safety
+        ///
+        /// Never called, it panics.
+        pub unsafe fn resize_msg_buffer(_msgbuf: *mut MsgBuffer, _new_data_size: usize) {
+            no_erpc!()
+        }
+
+        pub fn create_session(self: Pin<&mut Self>, _remote_uri: &str, _rem_rpc_id: u8) -> c_int {
+            no_erpc!()
+        }
+
+        pub fn destroy_session(self: Pin<&mut Self>, _session_num: c_int) -> c_int {
+            no_erpc!()
+        }
+
+        pub fn is_connected(&self, _session_num: c_int) -> bool {
+            no_erpc!()
+        }
+
+        pub fn run_event_loop_once(self: Pin<&mut Self>) {
+            no_erpc!()
+        }
+
+        pub fn run_event_loop(self: Pin<&mut Self>, _timeout_ms: usize) {
+            no_erpc!()
+        }
+
+        pub fn get_ev_loop_tsc(&self) -> usize {
+            no_erpc!()
+        }
+
+        /// # Safety
+        ///
+        /// Never called, it panics.
+        pub unsafe fn set_context(self: Pin<&mut Self>, _context: *mut c_void) {
+            no_erpc!()
+        }
+
+        pub fn alloc_msg_buffer_or_die(self: Pin<&mut Self>, _max_data_size: usize) -> MsgBuffer {
+            no_erpc!()
+        }
+
+        pub fn alloc_msg_buffer(self: Pin<&mut Self>, _max_data_size: usize) -> MsgBuffer {
+            no_erpc!()
+        }
+
+        pub fn free_msg_buffer(self: Pin<&mut Self>, _msgbuf: &MsgBuffer) {
+            no_erpc!()
+        }
+
+        /// # Safety
+        ///
+        /// Never called, it panics.
+        #[allow(clippy::too_many_arguments)]
+        pub unsafe fn enqueue_request(
+            self: Pin<&mut Self>,
+            _session_num: c_int,
+            _req_type: u8,
+            _req_msgbuf: *mut MsgBuffer,
+            _resp_msgbuf: *mut MsgBuffer,
+            _cont_func: *mut c_void,
+            _tag: *mut c_void,
+            _bg_etid: usize,
+        ) {
+            no_erpc!()
+        }
+
+        /// # Safety
+        ///
+        /// Never called, it panics.
+        pub unsafe fn enqueue_response(
+            self: Pin<&mut Self>,
+            _req_handle: *mut ReqHandle,
+            _resp_msgbuf: *mut MsgBuffer,
+        ) {
+            no_erpc!()
+        }
+
+        pub fn get_timely(self: Pin<&mut Self>, _session_num: c_int) -> *mut Timely {
+            no_erpc!()
+        }
+
+        pub fn get_bandwidth(&self) -> usize {
+            no_erpc!()
+        }
+
+        pub fn get_freq_ghz(&self) -> f64 {
+            no_erpc!()
+        }
+
+        pub fn get_rpc_id(&self) -> u8 {
+            no_erpc!()
+        }
+
+        pub fn get_wheel(self: Pin<&mut Self>) -> *mut TimingWheel {
+            no_erpc!()
+        }
+
+        pub fn get_num_re_tx(&self, _session_num: c_int) -> usize {
+            no_erpc!()
+        }
+
+        pub fn reset_num_re_tx(self: Pin<&mut Self>, _session_num: c_int) {
+            no_erpc!()
+        }
+
+        pub fn sec_since_creation(self: Pin<&mut Self>) -> f64 {
+            no_erpc!()
+        }
+
+        pub fn force_retry_connect_on_invalid_rpc_id(self: Pin<&mut Self>) {
+            no_erpc!()
+        }
+    }
+}
+
+pub mod erpc_wrapper {
+    use std::pin::Pin;
+
+    use super::c_int;
+    use super::erpc::{wheel_record_t, Rpc};
+
+    pub fn wheel_record_is_insert(_record: &wheel_record_t) -> bool {
+        no_erpc!()
+    }
+
+    pub fn wheel_record_pkt_num(_record: &wheel_record_t) -> usize {
+        no_erpc!()
+    }
+
+    pub fn wheel_record_abs_tx_tsc(_record: &wheel_record_t) -> usize {
+        no_erpc!()
+    }
+
+    pub fn rpc_max_msg_size(_rpc: &Rpc) -> usize {
+        no_erpc!()
+    }
+
+    pub fn rpc_max_data_per_pkt(_rpc: &Rpc) -> usize {
+        no_erpc!()
+    }
+
+    pub fn rpc_mtu() -> usize {
+        no_erpc!()
+    }
+
+    pub fn rpc_stat_user_alloc_tot(_rpc: Pin<&mut Rpc>) -> usize {
+        no_erpc!()
+    }
+
+    pub fn rpc_num_active_sessions(_rpc: Pin<&mut Rpc>) -> usize {
+        no_erpc!()
+    }
+
+    pub fn rpc_is_client_session(_rpc: &Rpc, _session_num: c_int) -> bool {
+        no_erpc!()
+    }
+
+    pub fn rpc_session_credits(_rpc: &Rpc, _session_num: c_int) -> i64 {
+        no_erpc!()
+    }
+
+    pub fn rpc_bg_queue_len(_rpc: &Rpc) -> usize {
+        no_erpc!()
+    }
+}
@@ -24,8 +24,8 @@ extern "C" fn sm_handler(_: c_int, _: SmEventType, _: SmErrType, _: *mut c_void)
 
 fn main() {
     let client_uri = K_CLIENT_HOST_NAME.to_owned() + ":" + K_UDP_PORT;
-    let mut nexus = Nexus::new(&client_uri, 0);
-    let mut rpc = Rpc::new(&mut nexus, None, 0, Some(sm_handler), 0);
+    let nexus = Nexus::new(&client_uri, 0);
+    let mut rpc = Rpc::new(&nexus, None, 0, Some(sm_handler), 0);
     let server_uri = K_SERVER_HOST_NAME.to_owned() + ":" + K_UDP_PORT;
     let session_num = rpc.create_session(&server_uri, 0).unwrap();
     loop {
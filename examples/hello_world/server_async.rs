@@ -7,7 +7,7 @@ use common::*;
 use crossbeam::channel;
 use crossbeam_channel::{unbounded, TryRecvError};
 use erpc_rs::prelude::*;
-use std::{mem::MaybeUninit, ptr, thread};
+use std::{mem::MaybeUninit, thread};
 use tokio::{fs, runtime::Handle, sync::mpsc};
 
 extern "C" fn req_handler(req_handle: *mut RawReqHandle, context: *mut c_void) {
@@ -67,10 +67,10 @@ async fn main() -> Result<()> {
             rx: rx1,
             rt,
         };
-        let mut nexus = Nexus::new(&server_uri, 0);
+        let nexus = Nexus::new(&server_uri, 0);
         nexus.register_req_func(K_REQ_TYPE, req_handler).unwrap();
         let rpc = Rpc::new(
-            &mut nexus,
+            &nexus,
             Some(&mut c as *mut AppContext as *mut c_void),
             0,
             None,
@@ -90,11 +90,7 @@ async fn main() -> Result<()> {
                             .assume_init_mut()
                             .alloc_msg_buffer_or_die(resp.data.len()),
                     );
-                    ptr::copy(
-                        resp.data.as_ptr(),
-                        resp_msgbuf.get_inner_buf(),
-                        resp.data.len(),
-                    );
+                    resp_msgbuf.as_mut_slice().copy_from_slice(&resp.data);
                     c.rpc
                         .assume_init_mut()
                         .enqueue_response(&mut resp.req_handle, &mut resp_msgbuf);
@@ -7,7 +7,7 @@ use common::*;
 use crossbeam::channel;
 use crossbeam_channel::{unbounded, TryRecvError};
 use erpc_rs::prelude::*;
-use std::{mem::MaybeUninit, slice, thread};
+use std::{mem::MaybeUninit, thread};
 use tokio::{fs::File, io::AsyncReadExt, runtime::Handle, sync::mpsc};
 
 extern "C" fn req_handler(req_handle: *mut RawReqHandle, context: *mut c_void) {
@@ -69,10 +69,10 @@ async fn main() -> Result<()> {
             rx: rx1,
             rt,
         };
-        let mut nexus = Nexus::new(&server_uri, 0);
+        let nexus = Nexus::new(&server_uri, 0);
         nexus.register_req_func(K_REQ_TYPE, req_handler).unwrap();
         let rpc = Rpc::new(
-            &mut nexus,
+            &nexus,
             Some(&mut c as *mut AppContext as *mut c_void),
             0,
             None,
@@ -109,10 +109,8 @@ async fn main() -> Result<()> {
             let mut file = File::open(req.file_name).await.unwrap();
             let metadata = file.metadata().await.unwrap();
             let resp_size = metadata.len() as usize;
-            let msg_buf = req.rpc.alloc_msg_buffer_or_die(resp_size);
-            let msg_buf_slice =
-                unsafe { slice::from_raw_parts_mut(msg_buf.get_inner_buf(), resp_size) };
-            file.read_exact(msg_buf_slice).await.unwrap();
+            let mut msg_buf = req.rpc.alloc_msg_buffer_or_die(resp_size);
+            file.read_exact(msg_buf.as_mut_slice()).await.unwrap();
             tx1.send(Resp {
                 req_handle: req.req_handle,
                 msg_buf,
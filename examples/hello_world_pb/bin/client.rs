@@ -7,21 +7,14 @@ use erpc_rs::prelude::*;
 
 use hello_world_pb::{
     common::*,
-    helloworld::{GreeterClient, HelloRequest, METHOD_GREETER_SAY_HELLO},
+    helloworld::{GreeterClient, HelloRequest},
 };
 
 // TODO: make sure if there's no other user-related logic, this function can be
 // automatically generated and invisible to the user.
-extern "C" fn cont_func(ctx: *mut c_void, tag: *mut c_void) {
-    let ctx = unsafe { &mut *(ctx as *mut ClientRpcContext) };
+extern "C" fn cont_func(_ctx: *mut c_void, tag: *mut c_void) {
     let tag = unsafe { Box::from_raw(tag as *mut Tag) };
-    let (_, resp) = ctx
-        .resp_msgbufs
-        .get_mut(METHOD_GREETER_SAY_HELLO.id as usize)
-        .unwrap()
-        .remove_entry(&tag.idx)
-        .unwrap();
-    let msg_buffer_reader = unsafe { MsgBufferReader::new(resp.as_inner() as *const RawMsgBuffer) };
+    let msg_buffer_reader = unsafe { MsgBufferReader::new(tag.resp_msgbuf()) };
     tag.tx.send_blocking(msg_buffer_reader).unwrap();
 }
 
@@ -36,13 +29,13 @@ async fn main() -> Result<()> {
         .connect(&server_uri)
         .await
         .unwrap();
-    let mut client = GreeterClient::new(ch.clone());
+    let client = GreeterClient::new(ch.clone());
     let req = HelloRequest {
         name: "world".to_owned(),
     };
 
-    let req_msgbuf = Arc::new(client.alloc_msg_buffer(K_MSG_SIZE));
-    let resp_msgbuf = Arc::new(client.alloc_msg_buffer(K_MSG_SIZE));
+    let req_msgbuf = SharedMsgBuffer::new(client.alloc_msg_buffer(K_MSG_SIZE).await?);
+    let resp_msgbuf = SharedMsgBuffer::new(client.alloc_msg_buffer(K_MSG_SIZE).await?);
 
     let reply = client
         .say_hello(&req, req_msgbuf.clone(), resp_msgbuf.clone(), cont_func)
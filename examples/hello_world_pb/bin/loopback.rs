@@ -0,0 +1,123 @@
+// Copyright (c) 2023, IOMesh Inc. All rights reserved.
+
+//! Runs the greeter server and client in one process on the loopback
+//! backend, no NIC or hugepages needed:
+//! `cargo run --features loopback --bin hello_loopback`.
+//!
+//! Given a seed, e.g. `hello_loopback 42`, the calls run through a lossy,
+//! reordering network simulation, and a stalled server makes a connect
+//! time out. The same seed injects the same faults.
+
+use std::{sync::Arc, time::Duration};
+
+use anyhow::Result;
+use async_channel::Sender;
+use erpc_rs::{prelude::*, sim::SimBuilder};
+
+use hello_world_pb::{
+    common::*,
+    helloworld::{
+        create_greeter, Greeter, GreeterClient, HelloReply, HelloRequest, METHOD_GREETER_SAY_HELLO,
+    },
+};
+
+#[derive(Clone)]
+struct GreeterService;
+
+#[async_trait::async_trait]
+impl Greeter for GreeterService {
+    fn say_hello(req: ReqHandle, ctx: &'static mut ServerRpcContext) {
+        let tx = ctx.tx.clone();
+        let f = ctx
+            .get_handler(METHOD_GREETER_SAY_HELLO.id)
+            .unwrap()
+            .handle(req, tx);
+        ctx.spawn(f);
+    }
+    async fn say_hello_async(
+        mut req_handle: ReqHandle,
+        tx: Sender<RpcCall>,
+        codec: Codec<HelloRequest, HelloReply>,
+    ) {
+        let msg_buffer_reader = unsafe { MsgBufferReader::new(req_handle.get_req_msgbuf()) };
+        let req = (codec.de)(msg_buffer_reader).unwrap();
+        let resp = HelloReply {
+            message: format!("Hello {}", req.name),
+        };
+        let mut resp_msgbuf = MsgBufferAllocator::new(tx.clone())
+            .alloc(K_MSG_SIZE)
+            .await
+            .unwrap();
+        (codec.ser)(&resp, &mut resp_msgbuf).unwrap();
+        tx.send(RpcCall::CallTag(CallTag::with_resp_msgbuf(
+            req_handle,
+            resp_msgbuf,
+        )))
+        .await
+        .unwrap();
+    }
+}
+
+extern "C" fn cont_func(_ctx: *mut c_void, tag: *mut c_void) {
+    let tag = unsafe { Box::from_raw(tag as *mut Tag) };
+    let msg_buffer_reader = unsafe { MsgBufferReader::new(tag.resp_msgbuf()) };
+    tag.tx.send_blocking(msg_buffer_reader).unwrap();
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let sim = std::env::args().nth(1).map(|seed| {
+        SimBuilder::new(seed.parse().expect("seed"))
+            .latency(Duration::from_micros(5), Duration::from_micros(5))
+            .loss(0.2)
+            .rto(Duration::from_millis(1))
+            .reorder(0.2, Duration::from_micros(50))
+            .server_stall(0.1, Duration::from_millis(2))
+            .auto_advance(Duration::from_micros(100))
+            .install()
+    });
+
+    let server_uri = "localhost:".to_owned() + K_UDP_PORT;
+    let server_env = Arc::new(EnvBuilder::new(&server_uri).chan_count(1).build());
+    let mut server = ServerBuilder::new(server_env, PHY_PORT, 0)
+        .register_service(create_greeter::<GreeterService>())
+        .build_and_start()
+        .await?;
+
+    let client_env = Arc::new(EnvBuilder::new("localhost:31851").chan_count(1).build());
+    let mut ch = ChannelBuilder::new(client_env, PHY_PORT)
+        .subchan_count(1)
+        .connect(&server_uri)
+        .await?;
+    let client = GreeterClient::new(ch.clone());
+    let req_msgbuf = SharedMsgBuffer::new(client.alloc_msg_buffer(K_MSG_SIZE).await?);
+    let resp_msgbuf = SharedMsgBuffer::new(client.alloc_msg_buffer(K_MSG_SIZE).await?);
+    for name in ["world", "loopback", "simulation", "seed"] {
+        let req = HelloRequest {
+            name: name.to_owned(),
+        };
+        let reply = client
+            .say_hello(&req, req_msgbuf.clone(), resp_msgbuf.clone(), cont_func)
+            .await?;
+        println!("Greeter received: {}", reply.message);
+    }
+
+    if let Some(sim) = sim.as_ref() {
+        sim.stall(&server_uri, Duration::from_secs(1));
+        let client_env = Arc::new(EnvBuilder::new("localhost:31852").chan_count(1).build());
+        let res = ChannelBuilder::new(client_env, PHY_PORT)
+            .subchan_count(1)
+            .connect_timeout_ms(100)
+            .connect(&server_uri)
+            .await;
+        println!("Connect to the stalled server: {:?}", res.err());
+        println!("Faults injected by seed {} in {:?}:", sim.seed(), sim.now());
+        for fault in sim.faults() {
+            println!("  {fault:?}");
+        }
+    }
+
+    ch.shutdown().await?;
+    server.shutdown().await?;
+    Ok(())
+}
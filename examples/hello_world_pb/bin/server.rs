@@ -1,7 +1,5 @@
 // Copyright (c) 2023, IOMesh Inc. All rights reserved.
 
-#![feature(get_mut_unchecked)]
-
 use std::sync::Arc;
 
 use anyhow::Result;
@@ -20,17 +18,15 @@ struct GreeterService;
 #[async_trait::async_trait]
 impl Greeter for GreeterService {
     fn say_hello(req: ReqHandle, ctx: &'static mut ServerRpcContext) {
-        let rpc = ctx.rpc.clone();
         let tx = ctx.tx.clone();
         let f = ctx
             .get_handler(METHOD_GREETER_SAY_HELLO.id)
             .unwrap()
-            .handle(req, rpc, tx);
+            .handle(req, tx);
         ctx.spawn(f);
     }
     async fn say_hello_async(
         mut req_handle: ReqHandle,
-        mut rpc: Arc<Rpc>,
         tx: Sender<RpcCall>,
         codec: Codec<HelloRequest, HelloReply>,
     ) {
@@ -38,16 +34,17 @@ impl Greeter for GreeterService {
         let req = (codec.de)(msg_buffer_reader).unwrap();
         let msg = format!("Hello {}", req.name);
         let resp = HelloReply { message: msg };
-        let mut resp_msgbuf = unsafe {
-            let rpc = Arc::get_mut_unchecked(&mut rpc);
-            // FIXME: c++ mutex will be called, may become a performance bottleneck in actual use
-            rpc.alloc_msg_buffer(K_MSG_SIZE)
-        };
-        (codec.ser)(&resp, &mut resp_msgbuf).unwrap();
-        req_handle.init_dyn_resp_msgbuf_from_allocated(&mut resp_msgbuf);
-        tx.send(RpcCall::CallTag(CallTag { req_handle }))
+        let mut resp_msgbuf = MsgBufferAllocator::new(tx.clone())
+            .alloc(K_MSG_SIZE)
             .await
             .unwrap();
+        (codec.ser)(&resp, &mut resp_msgbuf).unwrap();
+        tx.send(RpcCall::CallTag(CallTag::with_resp_msgbuf(
+            req_handle,
+            resp_msgbuf,
+        )))
+        .await
+        .unwrap();
     }
 }
 
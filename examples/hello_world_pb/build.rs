@@ -5,7 +5,7 @@ use std::io::Result;
 fn main() -> Result<()> {
     println!("cargo:rerun-if-changed=proto/helloworld.proto");
     Config::new()
-        .service_generator(Box::new(Generator))
+        .service_generator(Box::new(Generator::default()))
         .out_dir("src")
         .compile_protos(&["proto/helloworld.proto"], &["proto"])?;
     Ok(())
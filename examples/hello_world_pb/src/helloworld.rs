@@ -17,13 +17,16 @@ pub const METHOD_GREETER_SAY_HELLO: ::erpc_rs::prelude::Method<
     HelloReply,
 > = ::erpc_rs::prelude::Method {
     id: 1,
+    name: "/helloworld.Greeter/SayHello",
     req_mar: ::erpc_rs::prelude::Marshaller {
         ser: ::erpc_rs::prelude::pr_ser,
         de: ::erpc_rs::prelude::pr_de,
+        trace_trailer: true,
     },
     resp_mar: ::erpc_rs::prelude::Marshaller {
         ser: ::erpc_rs::prelude::pr_ser,
         de: ::erpc_rs::prelude::pr_de,
+        trace_trailer: true,
     },
 };
 #[derive(Clone)]
@@ -39,19 +42,19 @@ impl GreeterClient {
     pub async fn say_hello(
         &self,
         req: &HelloRequest,
-        req_msgbuf: std::sync::Arc<::erpc_rs::prelude::MsgBuffer>,
-        resp_msgbuf: std::sync::Arc<::erpc_rs::prelude::MsgBuffer>,
+        req_msgbuf: ::erpc_rs::prelude::SharedMsgBuffer,
+        resp_msgbuf: ::erpc_rs::prelude::SharedMsgBuffer,
         cb: ::erpc_rs::prelude::ContFunc,
     ) -> ::erpc_rs::prelude::Result<HelloReply> {
         self.client
             .unary_call(&METHOD_GREETER_SAY_HELLO, req, req_msgbuf, resp_msgbuf, cb)
             .await
     }
-    pub fn alloc_msg_buffer(
-        &mut self,
+    pub async fn alloc_msg_buffer(
+        &self,
         max_data_size: usize,
-    ) -> ::erpc_rs::prelude::MsgBuffer {
-        self.client.alloc_msg_buffer(max_data_size)
+    ) -> ::erpc_rs::prelude::Result<::erpc_rs::prelude::MsgBuffer> {
+        self.client.alloc_msg_buffer(max_data_size).await
     }
 }
 #[async_trait::async_trait]
@@ -64,7 +67,6 @@ pub trait Greeter: Send + 'static {
     }
     async fn say_hello_async(
         _req: ::erpc_rs::prelude::ReqHandle,
-        _rcp: std::sync::Arc<::erpc_rs::prelude::Rpc>,
         _tx: ::async_channel::Sender<::erpc_rs::prelude::RpcCall>,
         _codec: ::erpc_rs::prelude::Codec<HelloRequest, HelloReply>,
     ) {
@@ -77,8 +79,12 @@ extern "C" fn say_hello_wrapper<S: Greeter>(
 ) {
     let result = std::panic::catch_unwind(|| {
         let req = erpc_rs::prelude::ReqHandle::from_inner_raw(req);
-        let ctx = unsafe { &mut *(ctx as *mut ::erpc_rs::prelude::ServerRpcContext) };
-        S::say_hello(req, ctx);
+        unsafe {
+            ::erpc_rs::prelude::ServerRpcContext::run_handler(
+                ctx,
+                |ctx| S::say_hello(req, ctx),
+            );
+        }
     });
     if result.is_err() {
         std::process::abort();
@@ -92,8 +98,8 @@ pub fn create_greeter<S: Greeter + Send + Clone + 'static>() -> ::erpc_rs::prelu
     builder = builder
         .add_unary_handler(
             &METHOD_GREETER_SAY_HELLO,
-            move |req, rpc, tx, codec| {
-                std::boxed::Box::pin(S::say_hello_async(req, rpc, tx, codec))
+            move |req, tx, codec| {
+                std::boxed::Box::pin(S::say_hello_async(req, tx, codec))
             },
             unsafe { say_hello_wrapper_into::<S>() },
         );
@@ -0,0 +1,401 @@
+// Copyright (c) 2023, IOMesh Inc. All rights reserved.
+
+use std::{
+    fmt::Write as _,
+    io::{self, BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, Weak,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use erpc_sys::erpc::{ms_to_cycles, rdtsc};
+
+const DEFAULT_PUBLISH_INTERVAL_MS: usize = 1000;
+const ACCEPT_INTERVAL: Duration = Duration::from_millis(100);
+/// Connections are served one at a time, so a stalled client can't hold the
+/// server for longer than this.
+const IO_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// What a poll thread is serving.
+pub(crate) enum Role {
+    Channel {
+        remote_uri: String,
+        sessions: Vec<SessionState>,
+        /// Calls waiting for a response, by request type.
+        in_flight: Vec<(u8, usize)>,
+    },
+    Server {
+        handlers: Vec<u8>,
+    },
+}
+
+/// A session of a channel as seen by its poll thread.
+pub(crate) struct SessionState {
+    pub(crate) id: i32,
+    pub(crate) connected: bool,
+    pub(crate) outstanding: usize,
+}
+
+/// The state of one poll thread, published by the thread itself.
+pub(crate) struct ThreadSnapshot {
+    pub(crate) role: Role,
+    /// `RpcCall`s waiting in the thread's queue.
+    pub(crate) queued_calls: usize,
+    pub(crate) updated: SystemTime,
+}
+
+/// The introspection state of one [`Environment`](crate::prelude::Environment).
+pub(crate) struct EnvState {
+    local_uri: String,
+    busy: Vec<Arc<AtomicBool>>,
+    threads: Vec<Mutex<Option<ThreadSnapshot>>>,
+    publish_interval_ms: usize,
+}
+
+/// Collects the state of the environments it's attached to, for the admin
+/// endpoint.
+///
+/// Attach it with [`EnvBuilder::admin`](crate::prelude::EnvBuilder::admin)
+/// and serve it with [`AdminRegistry::serve`], then
+/// `curl http://127.0.0.1:<port>/` dumps the environments, their poll
+/// threads, sessions and in-flight calls as JSON.
+#[derive(Clone)]
+pub struct AdminRegistry {
+    envs: Arc<Mutex<Vec<Weak<EnvState>>>>,
+    publish_interval_ms: usize,
+}
+
+impl Default for AdminRegistry {
+    fn default() -> Self {
+        AdminRegistry::new(DEFAULT_PUBLISH_INTERVAL_MS)
+    }
+}
+
+impl AdminRegistry {
+    /// Initialize a new [`AdminRegistry`], poll threads publish their state
+    /// every `publish_interval_ms`.
+    pub fn new(publish_interval_ms: usize) -> Self {
+        AdminRegistry {
+            envs: Arc::new(Mutex::new(Vec::new())),
+            publish_interval_ms,
+        }
+    }
+
+    pub(crate) fn register(&self, local_uri: String, busy: Vec<Arc<AtomicBool>>) -> Arc<EnvState> {
+        let state = Arc::new(EnvState {
+            local_uri,
+            threads: busy.iter().map(|_| Mutex::new(None)).collect(),
+            busy,
+            publish_interval_ms: self.publish_interval_ms,
+        });
+        let mut envs = self.envs.lock().unwrap();
+        envs.retain(|e| e.strong_count() > 0);
+        envs.push(Arc::downgrade(&state));
+        state
+    }
+
+    /// Render the state of all live environments as JSON.
+    pub fn render(&self) -> String {
+        let envs: Vec<_> = self
+            .envs
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(Weak::upgrade)
+            .collect();
+        let mut out = String::from("{\"environments\":[");
+        for (i, env) in envs.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            env.render(&mut out);
+        }
+        out.push_str("]}");
+        out
+    }
+
+    /// Serve [`AdminRegistry::render`] over HTTP on `addr` from a background
+    /// thread. The endpoint has no authentication, bind it to a local address.
+    pub fn serve<A: ToSocketAddrs>(&self, addr: A) -> io::Result<AdminServer> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let registry = self.clone();
+        let thread_stop = stop.clone();
+        let handle = thread::Builder::new()
+            .name("erpc-admin".into())
+            .spawn(move || {
+                while !thread_stop.load(Ordering::Relaxed) {
+                    match listener.accept() {
+                        Ok((stream, _)) => {
+                            if let Err(e) = respond(stream, &registry) {
+                                tracing::debug!(error = %e, "admin request failed");
+                            }
+                        }
+                        Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                            thread::sleep(ACCEPT_INTERVAL)
+                        }
+                        Err(e) => tracing::warn!(error = %e, "admin accept failed"),
+                    }
+                }
+            })?;
+        Ok(AdminServer {
+            stop,
+            handle: Some(handle),
+        })
+    }
+}
+
+fn respond(stream: TcpStream, registry: &AdminRegistry) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(IO_TIMEOUT))?;
+    stream.set_write_timeout(Some(IO_TIMEOUT))?;
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // Drain the headers.
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 2 {
+        line.clear();
+    }
+    let mut stream = reader.into_inner();
+    let (status, body) = if request_line.starts_with("GET ") {
+        ("200 OK", registry.render())
+    } else {
+        ("405 Method Not Allowed", String::new())
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+         Connection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+    stream.flush()
+}
+
+/// The admin endpoint started by [`AdminRegistry::serve`], it stops when
+/// dropped.
+pub struct AdminServer {
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Drop for AdminServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl EnvState {
+    fn render(&self, out: &mut String) {
+        let _ = write!(
+            out,
+            "{{\"local_uri\":\"{}\",\"threads\":[",
+            escape(&self.local_uri)
+        );
+        for (id, (busy, snapshot)) in self.busy.iter().zip(&self.threads).enumerate() {
+            if id > 0 {
+                out.push(',');
+            }
+            let _ = write!(
+                out,
+                "{{\"id\":{id},\"busy\":{}",
+                busy.load(Ordering::Relaxed)
+            );
+            if let Some(s) = snapshot.lock().unwrap().as_ref() {
+                out.push(',');
+                s.render(out);
+            }
+            out.push('}');
+        }
+        out.push_str("]}");
+    }
+}
+
+impl ThreadSnapshot {
+    fn render(&self, out: &mut String) {
+        let updated_ms = self
+            .updated
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis());
+        let _ = write!(
+            out,
+            "\"updated_ms\":{updated_ms},\"queued_calls\":{},",
+            self.queued_calls
+        );
+        match &self.role {
+            Role::Channel {
+                remote_uri,
+                sessions,
+                in_flight,
+            } => {
+                let _ = write!(
+                    out,
+                    "\"role\":\"channel\",\"remote_uri\":\"{}\",\"sessions\":[",
+                    escape(remote_uri)
+                );
+                for (i, s) in sessions.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    let _ = write!(
+                        out,
+                        "{{\"id\":{},\"connected\":{},\"outstanding\":{}}}",
+                        s.id, s.connected, s.outstanding
+                    );
+                }
+                out.push_str("],\"in_flight\":[");
+                for (i, (req_type, count)) in in_flight.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    let _ = write!(out, "{{\"req_type\":{req_type},\"count\":{count}}}");
+                }
+                out.push(']');
+            }
+            // eRPC doesn't list the sessions of a server.
+            Role::Server { handlers } => {
+                let _ = write!(out, "\"role\":\"server\",\"handlers\":{handlers:?}");
+            }
+        }
+    }
+}
+
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if c.is_control() => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Publishes the state of one poll thread, owned by the thread.
+pub(crate) struct ThreadPublisher {
+    state: Arc<EnvState>,
+    thread_id: u8,
+    interval_tsc: usize,
+    last_tsc: usize,
+}
+
+impl ThreadPublisher {
+    pub(crate) fn new(state: Arc<EnvState>, thread_id: u8, freq_ghz: f64) -> Self {
+        let interval_tsc = ms_to_cycles(state.publish_interval_ms as f64, freq_ghz);
+        ThreadPublisher {
+            state,
+            thread_id,
+            interval_tsc,
+            last_tsc: 0,
+        }
+    }
+
+    /// Whether the interval has elapsed since the last publish.
+    pub(crate) fn due(&mut self) -> bool {
+        let now = rdtsc();
+        if now - self.last_tsc < self.interval_tsc {
+            return false;
+        }
+        self.last_tsc = now;
+        true
+    }
+
+    pub(crate) fn publish(&self, snapshot: ThreadSnapshot) {
+        *self.state.threads[self.thread_id as usize].lock().unwrap() = Some(snapshot);
+    }
+
+    /// Drop the published state, e.g. when the thread goes idle.
+    pub(crate) fn clear(&self) {
+        *self.state.threads[self.thread_id as usize].lock().unwrap() = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape("localhost:31850"), "localhost:31850");
+        assert_eq!(escape(r#"a "quoted" \path\"#), r#"a \"quoted\" \\path\\"#);
+        assert_eq!(
+            escape("tab\tnl\ncr\r\u{0}\u{1f}\u{7f}"),
+            r"tab\u0009nl\u000acr\u000d\u0000\u001f\u007f"
+        );
+        // Everything else is valid in JSON strings as is.
+        assert_eq!(escape("héllo, 世界 🦀"), "héllo, 世界 🦀");
+        assert_eq!(escape("\u{85}"), r"\u0085");
+    }
+
+    #[test]
+    fn test_render() {
+        let registry = AdminRegistry::new(10);
+        assert_eq!(registry.render(), r#"{"environments":[]}"#);
+
+        let busy = vec![
+            Arc::new(AtomicBool::new(true)),
+            Arc::new(AtomicBool::new(false)),
+        ];
+        let state = registry.register("host\"1\n".into(), busy);
+        assert_eq!(
+            registry.render(),
+            r#"{"environments":[{"local_uri":"host\"1\u000a","threads":[{"id":0,"busy":true},{"id":1,"busy":false}]}]}"#
+        );
+
+        let updated = UNIX_EPOCH + Duration::from_millis(1500);
+        *state.threads[0].lock().unwrap() = Some(ThreadSnapshot {
+            role: Role::Channel {
+                remote_uri: "ünï\\côde".into(),
+                sessions: vec![
+                    SessionState {
+                        id: 0,
+                        connected: true,
+                        outstanding: 2,
+                    },
+                    SessionState {
+                        id: 1,
+                        connected: false,
+                        outstanding: 0,
+                    },
+                ],
+                in_flight: vec![(1, 2)],
+            },
+            queued_calls: 3,
+            updated,
+        });
+        *state.threads[1].lock().unwrap() = Some(ThreadSnapshot {
+            role: Role::Server {
+                handlers: vec![1, 2],
+            },
+            queued_calls: 0,
+            updated,
+        });
+        assert_eq!(
+            registry.render(),
+            concat!(
+                r#"{"environments":[{"local_uri":"host\"1\u000a","threads":["#,
+                r#"{"id":0,"busy":true,"updated_ms":1500,"queued_calls":3,"role":"channel","#,
+                r#""remote_uri":"ünï\\côde","sessions":[{"id":0,"connected":true,"outstanding":2},"#,
+                r#"{"id":1,"connected":false,"outstanding":0}],"in_flight":[{"req_type":1,"count":2}]},"#,
+                r#"{"id":1,"busy":false,"updated_ms":1500,"queued_calls":0,"role":"server","handlers":[1, 2]}"#,
+                r#"]}]}"#
+            )
+        );
+
+        // Dropped environments aren't rendered.
+        drop(state);
+        assert_eq!(registry.render(), r#"{"environments":[]}"#);
+    }
+}
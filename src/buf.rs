@@ -2,8 +2,63 @@
 
 use std::io::{self, BufRead, Read};
 
+use bytes::BytesMut;
 use erpc_sys::erpc::MsgBuffer as RawMsgBuffer;
 
+use crate::msg_buffer::MsgBuffer;
+
+/// A buffer messages are serialized into, e.g. a [`MsgBuffer`] or, to use
+/// a codec without eRPC, a `Vec<u8>`.
+pub trait WriteBuf {
+    /// Returns the most bytes the buffer can hold.
+    fn max_len(&self) -> usize;
+
+    /// Set the buffer's content to `len` bytes and return them to be
+    /// overwritten. `len` must not exceed [`max_len`](WriteBuf::max_len).
+    fn reset(&mut self, len: usize) -> &mut [u8];
+}
+
+impl WriteBuf for MsgBuffer {
+    #[inline]
+    fn max_len(&self) -> usize {
+        self.get_max_data_size()
+    }
+
+    #[inline]
+    fn reset(&mut self, len: usize) -> &mut [u8] {
+        self.resize(len);
+        self.as_mut_slice()
+    }
+}
+
+impl WriteBuf for Vec<u8> {
+    #[inline]
+    fn max_len(&self) -> usize {
+        isize::MAX as usize
+    }
+
+    #[inline]
+    fn reset(&mut self, len: usize) -> &mut [u8] {
+        self.clear();
+        self.resize(len, 0);
+        self
+    }
+}
+
+impl WriteBuf for BytesMut {
+    #[inline]
+    fn max_len(&self) -> usize {
+        isize::MAX as usize
+    }
+
+    #[inline]
+    fn reset(&mut self, len: usize) -> &mut [u8] {
+        self.clear();
+        self.resize(len, 0);
+        self
+    }
+}
+
 #[repr(C)]
 pub struct MsgBufferReader {
     buf: *const RawMsgBuffer,
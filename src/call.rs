@@ -1,34 +1,98 @@
 // Copyright (c) 2023, IOMesh Inc. All rights reserved.
 
-use std::sync::Arc;
+use std::{
+    sync::{atomic::Ordering, Arc},
+    time::Instant,
+};
 
 use async_channel::{bounded, Sender};
-use erpc_sys::{c_int, c_void};
-
-#[cfg(feature = "bench_stat")]
-use erpc_sys::erpc::rdtsc;
+use erpc_sys::{
+    c_int, c_void,
+    erpc::{rdtsc, MsgBuffer as RawMsgBuffer, SmErrType},
+};
+use tracing::{debug, debug_span, field::Empty, trace, warn, Instrument, Span};
 
 use crate::{
     buf::MsgBufferReader,
     channel::{ClientRpcContext, SubChannel},
     codec::{DeserializeFn, SerializeFn},
-    error::Result,
+    error::{Error, ErrorCode, Result},
+    histogram::LatencySnapshot,
     method::Method,
-    msg_buffer::MsgBuffer,
+    metrics::SessionStats,
+    msg_buffer::{MsgBufferLease, SharedMsgBuffer},
+    pool::{MsgBufferPool, PooledMsgBuffer},
     req_handle::ReqHandle,
     rpc::{ContFunc, Rpc},
+    server::ServerRpcContext,
+    stage::{CallStamps, SERVER_STAGE_RESPOND},
+    trace::{TraceContext, TRAILER_LEN},
 };
 
 pub enum RpcCall {
     Call(Call),
     CallTag(CallTag),
+    Alloc(AllocReq),
+    Stats(StatsReq),
+    Latency(LatencyReq),
+    Settle(SettleReq),
+}
+
+/// The kind of poll thread a call is resolved on, which decides the type of
+/// its context.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum PollRole {
+    Client,
+    Server,
 }
 
 impl RpcCall {
-    pub fn resolve(self, rpc: &mut Rpc, ctx: *mut c_void) {
+    /// Resolve the call on a poll thread of `role`, whose context is `ctx`.
+    /// Calls meant for the other role are dropped, so that whoever holds a
+    /// sender can't make the thread misread its context.
+    pub(crate) fn resolve(
+        self,
+        role: PollRole,
+        rpc: &mut Rpc,
+        ctx: *mut c_void,
+        pool: &mut MsgBufferPool,
+    ) {
+        match (self, role) {
+            (RpcCall::Call(call), PollRole::Client) => call.resolve(rpc, ctx),
+            (RpcCall::CallTag(tag), PollRole::Server) => {
+                let created_tsc = tag.created_tsc;
+                let id = tag.req_handle.id();
+                let resp_size = tag.resolve(rpc);
+                let ctx = unsafe { &*(ctx as *const ServerRpcContext) };
+                if let Some(method_stats) = ctx.method_stats.as_ref() {
+                    method_stats.respond(id, resp_size);
+                }
+                if let Some(stages) = ctx.stages.as_ref() {
+                    stages.record(SERVER_STAGE_RESPOND, &[rdtsc().saturating_sub(created_tsc)]);
+                }
+            }
+            (RpcCall::Alloc(req), _) => req.resolve(rpc, pool),
+            (RpcCall::Stats(req), PollRole::Client) => req.resolve(rpc, ctx),
+            (RpcCall::Latency(req), PollRole::Client) => req.resolve(ctx),
+            (RpcCall::Settle(req), PollRole::Server) => req.resolve(ctx),
+            (call, role) => {
+                warn!(
+                    call = call.kind(),
+                    ?role,
+                    "dropped a call sent to the wrong poll thread"
+                );
+            }
+        }
+    }
+
+    fn kind(&self) -> &'static str {
         match self {
-            RpcCall::Call(call) => call.resolve(rpc, ctx),
-            RpcCall::CallTag(tag) => tag.resolve(rpc),
+            RpcCall::Call(_) => "call",
+            RpcCall::CallTag(_) => "response",
+            RpcCall::Alloc(_) => "alloc",
+            RpcCall::Stats(_) => "stats",
+            RpcCall::Latency(_) => "latency",
+            RpcCall::Settle(_) => "settle",
         }
     }
 }
@@ -36,16 +100,72 @@ impl RpcCall {
 pub struct Tag {
     pub tx: Sender<MsgBufferReader>,
     pub idx: u16,
+    pub req_type: u8,
+    /// The session the request was enqueued on.
+    pub sid: c_int,
+    /// The TSC when the request was enqueued.
+    pub start_tsc: usize,
+    /// The user's continuation, called once the latency is recorded.
+    pub(crate) cb: ContFunc,
+    pub(crate) stamps: Option<Arc<CallStamps>>,
+    /// Held until the continuation fires.
+    pub(crate) leases: Option<Arc<CallLeases>>,
+    resp_msgbuf: *const RawMsgBuffer,
+}
+
+impl Tag {
+    /// Returns the response buffer, e.g. to read it in the continuation with
+    /// [`MsgBufferReader::new`]. It's leased by the call until the caller has
+    /// read the response.
+    #[inline]
+    pub fn resp_msgbuf(&self) -> *const RawMsgBuffer {
+        self.resp_msgbuf
+    }
+}
+
+/// The leases of a call's buffers. eRPC owns both buffers until the
+/// continuation fires, and the caller reads the response after that, so both
+/// hold the leases.
+pub(crate) struct CallLeases {
+    req: MsgBufferLease,
+    resp: MsgBufferLease,
+}
+
+/// Records the latency of the call, then hands the tag to the user's
+/// continuation.
+extern "C" fn record_latency(ctx: *mut c_void, tag: *mut c_void) {
+    let now = rdtsc();
+    let (req_type, sid, start_tsc, cb, leases) = {
+        let tag = unsafe { &mut *(tag as *mut Tag) };
+        if let Some(stamps) = tag.stamps.as_ref() {
+            stamps.fired.store(now, Ordering::Relaxed);
+        }
+        // The caller releases the leases once it has read the response, so
+        // the buffers are free as soon as the call returns. If it's gone,
+        // they're kept until the user's continuation returns.
+        let leases = tag
+            .leases
+            .take()
+            .and_then(|leases| Arc::try_unwrap(leases).ok());
+        (tag.req_type, tag.sid, tag.start_tsc, tag.cb, leases)
+    };
+    let c = unsafe { &mut *(ctx as *mut ClientRpcContext) };
+    c.record_latency(req_type, now - start_tsc);
+    c.finish_request(sid, req_type);
+    cb(ctx, tag);
+    drop(leases);
+    let c = unsafe { &mut *(ctx as *mut ClientRpcContext) };
+    c.record_continuation(rdtsc() - now);
 }
 
 /// A Call represents an RPC.
 pub struct Call {
     pub sid: c_int,
     pub req_type: u8,
-    pub req_msgbuf: Arc<MsgBuffer>,
-    pub resp_msgbuf: Arc<MsgBuffer>,
     pub cb: ContFunc,
     pub tx: Sender<MsgBufferReader>,
+    pub(crate) stamps: Option<Arc<CallStamps>>,
+    pub(crate) leases: Arc<CallLeases>,
 }
 
 unsafe impl Send for Call {}
@@ -55,56 +175,165 @@ impl Call {
         subchan: &SubChannel,
         method: &Method<Req, Resp>,
         req: &Req,
-        mut req_msgbuf: Arc<MsgBuffer>,
-        resp_msgbuf: Arc<MsgBuffer>,
+        req_msgbuf: SharedMsgBuffer,
+        resp_msgbuf: SharedMsgBuffer,
         cb: ContFunc,
     ) -> Result<Resp> {
+        let trace_ctx =
+            (subchan.trace_context && method.req_mar.trace_trailer).then(TraceContext::child);
+        let span = debug_span!(
+            "erpc_call",
+            method = method.id,
+            session = i32::from(subchan.id),
+            req_size = Empty,
+            resp_size = Empty,
+            latency_us = Empty,
+            outcome = Empty,
+            trace_id = trace_ctx.map(|c| c.trace_id),
+            span_id = trace_ctx.map(|c| c.span_id),
+        );
+        let start = Instant::now();
+        let res = Call::unary_inner(subchan, method, req, req_msgbuf, resp_msgbuf, cb, trace_ctx)
+            .instrument(span.clone())
+            .await;
+        span.record("latency_us", start.elapsed().as_micros() as u64);
+        match res {
+            Ok(_) => span.record("outcome", "ok"),
+            Err(ref e) => {
+                debug!(parent: &span, error = %e, "call failed");
+                span.record("outcome", "error")
+            }
+        };
+        res
+    }
+
+    async fn unary_inner<Req, Resp>(
+        subchan: &SubChannel,
+        method: &Method<Req, Resp>,
+        req: &Req,
+        req_msgbuf: SharedMsgBuffer,
+        resp_msgbuf: SharedMsgBuffer,
+        cb: ContFunc,
+        trace_ctx: Option<TraceContext>,
+    ) -> Result<Resp> {
+        let span = Span::current();
+        // Both buffers are leased until the response is decoded, or until the
+        // continuation fires if this future is dropped first, so a buffer
+        // shared by several tasks is never in two calls at once.
+        let mut req_lease = req_msgbuf
+            .try_lease()
+            .ok_or_else(|| Error::Internal("request buffer is in use".into()))?;
+        let resp_lease = resp_msgbuf
+            .try_lease()
+            .ok_or_else(|| Error::Internal("response buffer is in use".into()))?;
         let (tx, rx) = bounded::<MsgBufferReader>(1);
-        (method.req_ser())(req, unsafe { Arc::get_mut_unchecked(&mut req_msgbuf) })?;
+        (method.req_ser())(req, &mut req_lease)?;
+        if let Some(trace_ctx) = trace_ctx {
+            // `Client::alloc_msg_buffer` leaves room for the trailer, other
+            // buffers may be full, then the request goes without it.
+            if req_lease.remaining_capacity() >= TRAILER_LEN {
+                trace_ctx
+                    .write_trailer(&mut *req_lease)
+                    .map_err(|e| Error::Codec(Box::new(e)))?;
+            } else {
+                debug!("no room for the trace context in the request buffer");
+            }
+        }
+        span.record("req_size", req_lease.get_data_size());
+        let leases = Arc::new(CallLeases {
+            req: req_lease,
+            resp: resp_lease,
+        });
+        let stamps = subchan
+            .stages
+            .as_ref()
+            .map(|_| Arc::<CallStamps>::default());
+        let sent_tsc = rdtsc();
         subchan
             .tx
             .send(RpcCall::Call(Call {
                 sid: subchan.id,
                 req_type: method.id,
-                req_msgbuf,
-                resp_msgbuf,
                 cb,
                 tx,
+                stamps: stamps.clone(),
+                leases: leases.clone(),
             }))
             .await
-            .unwrap();
-        let resp = rx.recv().await.unwrap();
-        (method.resp_de())(resp)
+            .map_err(|_| Error::Channel("poll thread has exited".into()))?;
+        // The call is dropped without a response if the session isn't
+        // connected.
+        let resp = rx
+            .recv()
+            .await
+            .map_err(|_| Error::Disconnected(ErrorCode::Sm(SmErrType::kSrvDisconnected as u32)))?;
+        let woken_tsc = rdtsc();
+        span.record("resp_size", resp.len());
+        let res = (method.resp_de())(resp);
+        if let (Some(stages), Some(stamps)) = (subchan.stages.as_ref(), stamps) {
+            let dequeued = stamps.dequeued.load(Ordering::Relaxed);
+            let enqueued = stamps.enqueued.load(Ordering::Relaxed);
+            let fired = stamps.fired.load(Ordering::Relaxed);
+            stages.record(
+                0,
+                &[
+                    dequeued.saturating_sub(sent_tsc),
+                    enqueued.saturating_sub(dequeued),
+                    fired.saturating_sub(enqueued),
+                    woken_tsc.saturating_sub(fired),
+                    rdtsc().saturating_sub(woken_tsc),
+                ],
+            );
+        }
+        drop(leases);
+        res
     }
 
-    pub fn resolve(mut self, rpc: &mut Rpc, ctx: *mut c_void) {
+    pub(crate) fn resolve(self, rpc: &mut Rpc, ctx: *mut c_void) {
+        if let Some(stamps) = self.stamps.as_ref() {
+            stamps.dequeued.store(rdtsc(), Ordering::Relaxed);
+        }
+        // eRPC asserts that the session is connected.
+        if !rpc.is_client_session(self.sid) || !rpc.is_connected(self.sid) {
+            debug!(session = i32::from(self.sid), "session is not connected");
+            return;
+        }
         let ctx = unsafe { &mut *(ctx as *mut ClientRpcContext) };
         let idx = ctx
             .resp_msgbufs_idxs
             .get_mut(self.req_type as usize)
             .unwrap();
         *idx = idx.wrapping_add(1);
+        let start_tsc = rdtsc();
+        let stamps = self.stamps.clone();
         let tag = Tag {
             tx: self.tx,
             idx: *idx,
+            req_type: self.req_type,
+            sid: self.sid,
+            start_tsc,
+            cb: self.cb,
+            stamps: self.stamps,
+            leases: Some(self.leases.clone()),
+            resp_msgbuf: self.leases.resp.as_ptr(),
         };
-        let _ = ctx
-            .resp_msgbufs
-            .get_mut(self.req_type as usize)
-            .unwrap()
-            .insert(*idx, self.resp_msgbuf.clone());
-        #[cfg(feature = "bench_stat")]
-        {
-            ctx.bench_stat.req_ts[*idx as usize % 32] = rdtsc();
+        ctx.start_request(self.sid, self.req_type);
+        // The tag holds both leases until the continuation has fired.
+        unsafe {
+            rpc.enqueue_request_raw(
+                self.sid,
+                self.req_type,
+                self.leases.req.as_ptr(),
+                self.leases.resp.as_ptr(),
+                record_latency,
+                Some(Box::into_raw(Box::new(tag)) as *mut c_void),
+            );
+        }
+        // The continuation fires from the event loop, never from within
+        // `enqueue_request`.
+        if let Some(stamps) = stamps {
+            stamps.enqueued.store(rdtsc(), Ordering::Relaxed);
         }
-        rpc.enqueue_request(
-            self.sid,
-            self.req_type,
-            unsafe { Arc::get_mut_unchecked(&mut self.req_msgbuf) },
-            unsafe { Arc::get_mut_unchecked(&mut self.resp_msgbuf) },
-            self.cb,
-            Some(Box::into_raw(Box::new(tag)) as *mut c_void),
-        );
         #[cfg(feature = "bench_stat")]
         {
             ctx.bench_stat.stat_tx_bytes_tot += ctx.bench_stat.args_req_size;
@@ -125,11 +354,115 @@ impl<P, Q> Codec<P, Q> {
 
 pub struct CallTag {
     pub req_handle: ReqHandle,
+    /// The serialized response. When it's `None`, the response must have been
+    /// set by `ReqHandle::init_dyn_resp_msgbuf_from_allocated`.
+    pub resp_msgbuf: Option<PooledMsgBuffer>,
+    /// The TSC when the response was handed over.
+    pub(crate) created_tsc: usize,
 }
 
 impl CallTag {
-    pub fn resolve(mut self, rpc: &mut Rpc) {
-        let mut resp_msgbuf = self.req_handle.get_dyn_resp_msgbuf();
+    pub fn new(req_handle: ReqHandle) -> Self {
+        req_handle.set_responded();
+        CallTag {
+            req_handle,
+            resp_msgbuf: None,
+            created_tsc: rdtsc(),
+        }
+    }
+
+    pub fn with_resp_msgbuf(req_handle: ReqHandle, resp_msgbuf: PooledMsgBuffer) -> Self {
+        req_handle.set_responded();
+        CallTag {
+            req_handle,
+            resp_msgbuf: Some(resp_msgbuf),
+            created_tsc: rdtsc(),
+        }
+    }
+
+    /// Enqueue the response, returns its size.
+    pub fn resolve(mut self, rpc: &mut Rpc) -> usize {
+        let mut resp_msgbuf = match self.resp_msgbuf.take() {
+            Some(buf) => {
+                let mut pre_resp_msgbuf = self.req_handle.get_pre_resp_msgbuf();
+                let len = buf.get_data_size();
+                if len <= pre_resp_msgbuf.get_max_data_size() {
+                    // Small responses are copied so that the pooled buffer can be
+                    // reused right away.
+                    pre_resp_msgbuf.resize(len);
+                    pre_resp_msgbuf
+                        .as_mut_slice()
+                        .copy_from_slice(buf.as_slice());
+                    pre_resp_msgbuf
+                } else {
+                    // eRPC frees dynamic responses after they are sent, so the
+                    // buffer can't go back to the pool.
+                    let mut buf = buf.into_inner();
+                    self.req_handle
+                        .init_dyn_resp_msgbuf_from_allocated(&mut buf)
+                }
+            }
+            None => self.req_handle.get_dyn_resp_msgbuf(),
+        };
+        let resp_size = resp_msgbuf.get_data_size();
+        trace!(resp_size, "enqueue response");
         rpc.enqueue_response(&mut self.req_handle, &mut resp_msgbuf);
+        resp_size
+    }
+}
+
+/// A request to allocate a buffer from the poll thread's pool.
+pub struct AllocReq {
+    pub data_size: usize,
+    pub tx: Sender<Result<PooledMsgBuffer>>,
+}
+
+impl AllocReq {
+    pub fn resolve(self, rpc: &mut Rpc, pool: &mut MsgBufferPool) {
+        // If the requester has gone away the buffer goes straight back to the pool.
+        let _ = self.tx.try_send(pool.alloc(rpc, self.data_size));
+    }
+}
+
+/// A request to snapshot a client session on its poll thread.
+pub struct StatsReq {
+    pub(crate) sid: c_int,
+    pub(crate) tx: Sender<Result<SessionStats>>,
+}
+
+impl StatsReq {
+    pub(crate) fn resolve(self, rpc: &mut Rpc, ctx: *mut c_void) {
+        let ctx = unsafe { &mut *(ctx as *mut ClientRpcContext) };
+        let outstanding = ctx.outstanding(self.sid);
+        let _ = self
+            .tx
+            .try_send(SessionStats::sample(rpc, self.sid, outstanding));
+    }
+}
+
+/// A request to snapshot the call latency of a channel on its poll thread.
+pub struct LatencyReq {
+    pub(crate) tx: Sender<LatencySnapshot>,
+}
+
+impl LatencyReq {
+    pub(crate) fn resolve(self, ctx: *mut c_void) {
+        let ctx = unsafe { &*(ctx as *const ClientRpcContext) };
+        let _ = self.tx.try_send(ctx.latency_snapshot());
+    }
+}
+
+/// Sent by a server's handler once it has completed, to settle its request
+/// in the method statistics.
+pub struct SettleReq {
+    pub(crate) id: u64,
+}
+
+impl SettleReq {
+    pub(crate) fn resolve(self, ctx: *mut c_void) {
+        let ctx = unsafe { &*(ctx as *const ServerRpcContext) };
+        if let Some(method_stats) = ctx.method_stats.as_ref() {
+            method_stats.settle(self.id);
+        }
     }
 }
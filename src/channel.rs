@@ -4,30 +4,62 @@ use std::{
     boxed::Box,
     collections::HashMap,
     fmt::Debug,
+    ptr,
     sync::{
         atomic::{AtomicUsize, Ordering},
         Arc,
     },
+    time::SystemTime,
 };
 
 use async_channel::{bounded, unbounded, Receiver, Sender, TryRecvError};
 use erpc_sys::{
     c_int, c_void,
-    erpc::{ms_to_cycles, rdtsc, SmErrType, SmEventType},
+    erpc::{
+        ms_to_cycles, rdtsc, sm_err_type_str, sm_event_type_str, to_usec, SmErrType, SmEventType,
+    },
+    ETIMEDOUT,
 };
+use tracing::{debug, info, trace, warn};
 
 use crate::{
-    call::RpcCall, env::Environment, error::Result, msg_buffer::MsgBuffer, nexus::Nexus, rpc::Rpc,
+    admin::{Role, SessionState, ThreadPublisher, ThreadSnapshot},
+    call::{LatencyReq, PollRole, RpcCall, StatsReq},
+    env::Environment,
+    error::{Error, ErrorCode, Result},
+    histogram::{Histogram, LatencySnapshot},
+    metrics::{SessionSampler, SessionStats},
+    nexus::Nexus,
+    poll_stats::PollCounters,
+    pool::{MsgBufferPool, PooledMsgBuffer},
+    rpc::Rpc,
+    stage::{StageBreakdown, StageRecorder, CLIENT_STAGES},
 };
 
 #[cfg(feature = "bench_stat")]
-use crate::stat::BenchStat;
+use crate::stat::{BenchStat, StatsSink};
 
 pub struct ClientRpcContext {
-    pub resp_msgbufs: Vec<HashMap<u16, Arc<MsgBuffer>>>,
     pub resp_msgbufs_idxs: Vec<u16>,
+    /// Requests in flight, by request type.
+    in_flight: Vec<usize>,
+
+    /// The poll thread's `Rpc`, only valid on the poll thread.
+    pub(crate) rpc: *mut Rpc,
+    pub pool: MsgBufferPool,
+
+    /// Number of sessions which finished connecting, successfully or not.
+    pub(crate) sm_resps: usize,
+    /// The first session management error seen while connecting.
+    pub(crate) sm_err: Option<Error>,
 
-    pub rpc: Option<Arc<Rpc>>,
+    /// Latency of every call on this channel, in nanoseconds.
+    latency: Histogram,
+    /// Latency of every call on this channel by request type.
+    method_latency: HashMap<u8, Histogram>,
+    /// Requests in flight by session.
+    outstanding: HashMap<i32, usize>,
+    pub(crate) poll_counters: Arc<PollCounters>,
 
     #[cfg(feature = "bench_stat")]
     pub bench_stat: BenchStat,
@@ -36,32 +68,212 @@ pub struct ClientRpcContext {
 impl Default for ClientRpcContext {
     fn default() -> Self {
         ClientRpcContext {
-            resp_msgbufs: vec![HashMap::new(); MAX_REQ_TYPE],
             resp_msgbufs_idxs: vec![0; MAX_REQ_TYPE],
-            rpc: None,
+            in_flight: vec![0; MAX_REQ_TYPE],
+            rpc: ptr::null_mut(),
+            pool: MsgBufferPool::default(),
+            sm_resps: 0,
+            sm_err: None,
+            latency: Histogram::new(),
+            method_latency: HashMap::new(),
+            outstanding: HashMap::new(),
+            poll_counters: Arc::default(),
             #[cfg(feature = "bench_stat")]
             bench_stat: Default::default(),
         }
     }
 }
 
-pub type RpcPollFn = Box<dyn Fn(u8, &mut Arc<Nexus>, Sender<Channel>) + Send + 'static>;
+impl ClientRpcContext {
+    /// Returns the poll thread's `Rpc`. Must be called on the poll thread,
+    /// e.g. from a continuation.
+    #[inline]
+    pub fn rpc(&mut self) -> &mut Rpc {
+        unsafe { &mut *self.rpc }
+    }
+
+    /// Returns the latency of every call on this channel, in nanoseconds.
+    #[inline]
+    pub fn latency(&self) -> &Histogram {
+        &self.latency
+    }
+
+    /// Returns the latency of the calls of `req_type`, in nanoseconds.
+    #[inline]
+    pub fn method_latency(&self, req_type: u8) -> Option<&Histogram> {
+        self.method_latency.get(&req_type)
+    }
+
+    pub(crate) fn latency_snapshot(&self) -> LatencySnapshot {
+        LatencySnapshot {
+            all: self.latency.clone(),
+            methods: self.method_latency.clone(),
+        }
+    }
+
+    pub(crate) fn record_latency(&mut self, req_type: u8, cycles: usize) {
+        let ns = (to_usec(cycles, self.rpc().get_freq_ghz()) * 1000.0) as u64;
+        self.latency.record(ns);
+        self.method_latency.entry(req_type).or_default().record(ns);
+        #[cfg(feature = "bench_stat")]
+        self.bench_stat.lat.record(ns);
+    }
+
+    pub(crate) fn record_continuation(&mut self, cycles: usize) {
+        let ns = (to_usec(cycles, self.rpc().get_freq_ghz()) * 1000.0) as u64;
+        self.poll_counters.record_continuation(ns);
+    }
+
+    /// Returns the number of requests in flight on session `sid`.
+    #[inline]
+    pub fn outstanding(&self, sid: c_int) -> usize {
+        self.outstanding.get(&i32::from(sid)).copied().unwrap_or(0)
+    }
+
+    pub(crate) fn start_request(&mut self, sid: c_int, req_type: u8) {
+        *self.outstanding.entry(i32::from(sid)).or_default() += 1;
+        self.in_flight[req_type as usize] += 1;
+    }
+
+    pub(crate) fn finish_request(&mut self, sid: c_int, req_type: u8) {
+        if let Some(n) = self.outstanding.get_mut(&i32::from(sid)) {
+            *n = n.saturating_sub(1);
+        }
+        let n = &mut self.in_flight[req_type as usize];
+        *n = n.saturating_sub(1);
+    }
+
+    fn snapshot(
+        &self,
+        remote_uri: &str,
+        subchans: &[c_int],
+        queued_calls: usize,
+    ) -> ThreadSnapshot {
+        let rpc = unsafe { &*self.rpc };
+        ThreadSnapshot {
+            role: Role::Channel {
+                remote_uri: remote_uri.to_owned(),
+                sessions: subchans
+                    .iter()
+                    .map(|&sid| SessionState {
+                        id: i32::from(sid),
+                        connected: rpc.is_connected(sid),
+                        outstanding: self.outstanding(sid),
+                    })
+                    .collect(),
+                in_flight: self
+                    .in_flight
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, &n)| n > 0)
+                    .map(|(req_type, &n)| (req_type as u8, n))
+                    .collect(),
+            },
+            queued_calls,
+            updated: SystemTime::now(),
+        }
+    }
+
+    /// Get a buffer from this poll thread's pool. Must be called on the poll
+    /// thread, e.g. from a continuation.
+    #[inline]
+    pub fn alloc_msg_buffer(&mut self, data_size: usize) -> Result<PooledMsgBuffer> {
+        let rpc = unsafe { &mut *self.rpc };
+        self.pool.alloc(rpc, data_size)
+    }
+}
+
+/// Runs on a reserved poll thread, which is handed back to the environment
+/// once it returns. The returned callback runs after that, e.g. to report a
+/// failure to the caller, who can then reserve the thread again.
+pub type RpcPollFn = Box<dyn Fn(u8, &Nexus) -> Option<PollDone> + Send + 'static>;
+
+/// See [`RpcPollFn`].
+pub type PollDone = Box<dyn FnOnce()>;
+
+/// Report `err` on `chan_tx` once the poll thread is handed back.
+pub(crate) fn report_err(chan_tx: &Sender<Result<Channel>>, err: Error) -> Option<PollDone> {
+    let chan_tx = chan_tx.clone();
+    Some(Box::new(move || {
+        let _ = chan_tx.send_blocking(Err(err));
+    }))
+}
 
 pub struct ChannelBuilder {
     env: Arc<Environment>,
     subchan_count: usize,
     phy_port: u8,
     timeout_ms: usize,
+    connect_timeout_ms: usize,
+    trace_context: bool,
+    stage_latency: bool,
     #[cfg(feature = "bench_stat")]
     req_size: usize,
     #[cfg(feature = "bench_stat")]
     resp_size: usize,
+    #[cfg(feature = "bench_stat")]
+    stats_sink: Option<Arc<dyn StatsSink>>,
 }
 
-// TODO: impl this to make sure session has been connected before us
-extern "C" fn sm_handler(_: c_int, _: SmEventType, _: SmErrType, _: *mut c_void) {}
+extern "C" fn sm_handler(sid: c_int, event: SmEventType, err: SmErrType, ctx: *mut c_void) {
+    debug!(
+        session = i32::from(sid),
+        event = %sm_event_type_str(event),
+        error = %sm_err_type_str(err),
+        "session event"
+    );
+    let ctx = unsafe { &mut *(ctx as *mut ClientRpcContext) };
+    match event {
+        SmEventType::kConnected => ctx.sm_resps += 1,
+        SmEventType::kConnectFailed => {
+            ctx.sm_resps += 1;
+            if ctx.sm_err.is_none() {
+                ctx.sm_err = Some(
+                    Error::from_sm_err(err)
+                        .unwrap_or(Error::SessionCreate(ErrorCode::Sm(err as u32))),
+                );
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Run the event loop until `count` sessions are connected.
+fn wait_connected(
+    rpc: &mut Rpc,
+    ctx: &mut ClientRpcContext,
+    count: usize,
+    timeout_ms: usize,
+) -> Result<()> {
+    let timeout_tsc = ms_to_cycles(timeout_ms as f64, rpc.get_freq_ghz());
+    let start_tsc = rdtsc();
+    while ctx.sm_resps < count {
+        if let Some(err) = ctx.sm_err.take() {
+            return Err(err);
+        }
+        if rdtsc() - start_tsc > timeout_tsc {
+            return Err(Error::Timeout(ErrorCode::Errno(ETIMEDOUT)));
+        }
+        rpc.run_event_loop_once();
+    }
+    ctx.sm_err.take().map_or(Ok(()), Err)
+}
+
+/// Destroy the connected ones of `sessions`. The others are freed with the
+/// `Rpc`.
+fn destroy_sessions(rpc: &mut Rpc, id: u8, sessions: &[c_int]) {
+    for &sid in sessions {
+        if !rpc.is_connected(sid) {
+            continue;
+        }
+        if let Err(e) = rpc.destroy_session(sid) {
+            warn!(thread = id, session = i32::from(sid), error = %e, "failed to destroy session");
+        }
+    }
+}
 
 const MAX_REQ_TYPE: usize = 257;
+const DEFAULT_CONNECT_TIMEOUT_MS: usize = 5000;
 
 impl ChannelBuilder {
     /// Initialize a new [`ChannelBuilder`].
@@ -71,10 +283,15 @@ impl ChannelBuilder {
             subchan_count: 128,
             phy_port: port,
             timeout_ms: 0,
+            connect_timeout_ms: DEFAULT_CONNECT_TIMEOUT_MS,
+            trace_context: false,
+            stage_latency: false,
             #[cfg(feature = "bench_stat")]
             req_size: 0,
             #[cfg(feature = "bench_stat")]
             resp_size: 0,
+            #[cfg(feature = "bench_stat")]
+            stats_sink: None,
         }
     }
 
@@ -91,6 +308,27 @@ impl ChannelBuilder {
         self
     }
 
+    /// Set how long `connect` waits for the sessions to be connected.
+    pub fn connect_timeout_ms(mut self, timeout_ms: usize) -> ChannelBuilder {
+        self.connect_timeout_ms = timeout_ms;
+        self
+    }
+
+    /// Send a [`TraceContext`](crate::prelude::TraceContext) with the
+    /// requests of the methods whose request marshaller opts in with
+    /// [`Marshaller::trace_trailer`](crate::prelude::Marshaller::trace_trailer),
+    /// so that the server's spans join the caller's trace.
+    pub fn propagate_trace_context(mut self, enable: bool) -> ChannelBuilder {
+        self.trace_context = enable;
+        self
+    }
+
+    /// Record the latency of each stage of the calls, see [`StageBreakdown`].
+    pub fn stage_latency(mut self, enable: bool) -> ChannelBuilder {
+        self.stage_latency = enable;
+        self
+    }
+
     #[cfg(feature = "bench_stat")]
     /// Set req_size
     pub fn req_size(mut self, req_size: usize) -> ChannelBuilder {
@@ -105,100 +343,172 @@ impl ChannelBuilder {
         self
     }
 
+    #[cfg(feature = "bench_stat")]
+    /// Publish the periodic stats to `sink` as well.
+    pub fn stats_sink(mut self, sink: Arc<dyn StatsSink>) -> ChannelBuilder {
+        self.stats_sink = Some(sink);
+        self
+    }
+
+    /// Connect to `uri`. Once all sessions are connected, the [`Channel`]
+    /// is returned, otherwise the poll thread is handed back to the
+    /// environment and the error is returned.
     pub async fn connect<S: Into<String>>(self, uri: S) -> Result<Channel> {
-        let env = self.env.pick_channel_env().unwrap();
+        let poll_tx = self
+            .env
+            .pick_channel_env()
+            .ok_or_else(|| Error::Internal("no idle poll thread".into()))?;
         let uri = uri.into();
-        env.0
-            .send(Box::new(
-                move |id: u8, nexus: &mut Arc<Nexus>, chan_tx: Sender<Channel>| {
+        let (chan_tx, chan_rx) = bounded::<Result<Channel>>(1);
+        poll_tx
+            .send(Box::new(move |id: u8, nexus: &Nexus| {
+                #[cfg(feature = "bench_stat")]
+                let bench_stat = BenchStat {
+                    thread_id: id as usize,
+                    args_req_size: self.req_size,
+                    args_resp_size: self.resp_size,
+                    sink: self.stats_sink.clone(),
+                    ..Default::default()
+                };
+                let poll_counters = self.env.poll_counters(id);
+                let mut ctx = ClientRpcContext {
+                    poll_counters: poll_counters.clone(),
                     #[cfg(feature = "bench_stat")]
-                    let bench_stat = BenchStat {
-                        thread_id: id as usize,
-                        args_req_size: self.req_size,
-                        args_resp_size: self.resp_size,
-                        ..Default::default()
-                    };
-                    let mut ctx = ClientRpcContext {
-                        #[cfg(feature = "bench_stat")]
-                        bench_stat,
-                        ..Default::default()
-                    };
-                    let raw_ctx = &mut ctx as *mut ClientRpcContext as *mut c_void;
-                    let (tx, rx) = unbounded::<RpcCall>();
-                    let mut rpc = Arc::new(Rpc::new(
-                        unsafe { Arc::get_mut_unchecked(nexus) },
-                        Some(raw_ctx),
-                        id,
-                        Some(sm_handler),
-                        self.phy_port,
-                    ));
-                    ctx.rpc = Some(rpc.clone());
-                    let rpc_clone = rpc.clone();
-                    let rpc = unsafe { Arc::get_mut_unchecked(&mut rpc) };
-                    let mut subchans = Vec::new();
-                    for _i in 0..self.subchan_count {
-                        // TODO: make rem_rpc_id configurable
-                        subchans.push(rpc.create_session(uri.as_str(), 0).unwrap());
+                    bench_stat,
+                    ..Default::default()
+                };
+                let raw_ctx = &mut ctx as *mut ClientRpcContext as *mut c_void;
+                let (tx, rx) = unbounded::<RpcCall>();
+                // The `Rpc` lives on this thread's stack until the loop exits.
+                let mut rpc = Rpc::new(nexus, Some(raw_ctx), id, Some(sm_handler), self.phy_port);
+                let rpc = &mut rpc;
+                ctx.rpc = rpc;
+                let mut subchans = Vec::new();
+                for _i in 0..self.subchan_count {
+                    // TODO: make rem_rpc_id configurable
+                    match rpc.create_session(uri.as_str(), 0) {
+                        Ok(sid) => subchans.push(sid),
+                        Err(e) => {
+                            warn!(thread = id, %uri, error = %e, "failed to create session");
+                            destroy_sessions(rpc, id, &subchans);
+                            return report_err(&chan_tx, e);
+                        }
                     }
-                    let (stx, srx) = bounded::<()>(1);
-                    let chan = Channel {
-                        subchans: subchans.clone(),
-                        assigned_idx: Arc::new(AtomicUsize::new(0)),
-                        rpc: rpc_clone,
-                        tx,
-                        rx: srx,
-                    };
-                    chan_tx.send_blocking(chan).unwrap();
+                }
+                if let Err(e) =
+                    wait_connected(rpc, &mut ctx, subchans.len(), self.connect_timeout_ms)
+                {
+                    warn!(thread = id, %uri, error = %e, "failed to connect");
+                    destroy_sessions(rpc, id, &subchans);
+                    return report_err(&chan_tx, e);
+                }
+                info!(thread = id, %uri, sessions = subchans.len(), "channel connected");
+                let mut sampler = self.env.metrics().map(|registry| {
+                    SessionSampler::new(registry.clone(), id, uri.clone(), rpc.get_freq_ghz())
+                });
+                let mut publisher = self
+                    .env
+                    .admin_state()
+                    .map(|state| ThreadPublisher::new(state.clone(), id, rpc.get_freq_ghz()));
+                let (stx, srx) = bounded::<()>(1);
+                self.env.set_call_queue(id, Some(tx.downgrade()));
+                let chan = Channel {
+                    subchans: subchans.clone(),
+                    assigned_idx: Arc::new(AtomicUsize::new(0)),
+                    trace_context: self.trace_context,
+                    stages: self
+                        .stage_latency
+                        .then(|| Arc::new(StageRecorder::new(CLIENT_STAGES, rpc.get_freq_ghz()))),
+                    tx,
+                    rx: srx,
+                };
+                // If `connect` was cancelled, the channel is dropped here, which
+                // closes the call queue and tears the sessions down below.
+                let _ = chan_tx.send_blocking(Ok(chan));
 
-                    #[cfg(feature = "bench_stat")]
-                    ctx.bench_stat.init();
-
-                    'outer: loop {
-                        let timeout_tsc = ms_to_cycles(self.timeout_ms as f64, rpc.get_freq_ghz());
-                        let start_tsc = rdtsc();
-                        loop {
-                            rpc.run_event_loop_once();
-                            // TODO: make it configurable
-                            for _i in 0..8192 {
-                                match rx.try_recv() {
-                                    Ok(call) => call.resolve(rpc, raw_ctx),
-                                    Err(TryRecvError::Empty) => break,
-                                    Err(TryRecvError::Closed) => {
-                                        break 'outer;
-                                    }
+                #[cfg(feature = "bench_stat")]
+                ctx.bench_stat.init();
+
+                'outer: loop {
+                    let timeout_tsc = ms_to_cycles(self.timeout_ms as f64, rpc.get_freq_ghz());
+                    let start_tsc = rdtsc();
+                    loop {
+                        let events = poll_counters.events();
+                        rpc.run_event_loop_once();
+                        // TODO: make it configurable
+                        let mut backlog = true;
+                        let mut drained = 0;
+                        for _i in 0..8192 {
+                            match rx.try_recv() {
+                                Ok(call) => {
+                                    call.resolve(PollRole::Client, rpc, raw_ctx, &mut ctx.pool);
+                                    drained += 1;
+                                }
+                                Err(TryRecvError::Empty) => {
+                                    backlog = false;
+                                    break;
+                                }
+                                Err(TryRecvError::Closed) => {
+                                    break 'outer;
                                 }
-                            }
-                            if rpc.get_ev_loop_tsc() - start_tsc > timeout_tsc {
-                                break;
                             }
                         }
-
-                        #[cfg(feature = "bench_stat")]
-                        {
-                            let mut timely = rpc.get_timely(c_int::from(0));
-
-                            ctx.bench_stat.compute(
-                                rpc.get_num_re_tx(subchans[0]),
-                                self.timeout_ms,
-                                timely.get_rtt_perc(0.5),
-                                timely.get_rtt_perc(0.99),
-                            );
-                            ctx.bench_stat.output(timely.get_rate_gbps());
-                            ctx.bench_stat.reset();
-                            timely.reset_rtt_stats();
-                            rpc.reset_num_re_tx(subchans[0]);
+                        poll_counters.record_iteration(events, drained);
+                        if backlog {
+                            trace!(thread = id, "call queue not drained");
+                        }
+                        if rpc.get_ev_loop_tsc() - start_tsc > timeout_tsc {
+                            break;
+                        }
+                    }
+                    ctx.pool.reclaim(rpc);
+                    if let Some(sampler) = sampler.as_mut() {
+                        sampler.poll(rpc, &subchans);
+                    }
+                    if let Some(publisher) = publisher.as_mut() {
+                        if publisher.due() {
+                            publisher.publish(ctx.snapshot(&uri, &subchans, rx.len()));
                         }
                     }
 
-                    for sid in subchans {
-                        rpc.destroy_session(sid).unwrap();
+                    #[cfg(feature = "bench_stat")]
+                    {
+                        let mut timely = rpc.get_timely(c_int::from(0));
+
+                        ctx.bench_stat.compute(
+                            rpc.get_num_re_tx(subchans[0]),
+                            self.timeout_ms,
+                            timely.get_rtt_perc(0.5),
+                            timely.get_rtt_perc(0.99),
+                        );
+                        ctx.bench_stat.output(timely.get_rate_gbps());
+                        ctx.bench_stat.reset();
+                        timely.reset_rtt_stats();
+                        match sampler.as_mut() {
+                            Some(sampler) => sampler.reset_num_re_tx(rpc, subchans[0]),
+                            None => rpc.reset_num_re_tx(subchans[0]),
+                        }
                     }
-                    stx.send_blocking(()).unwrap();
-                },
-            ))
+                }
+
+                if let Some(sampler) = sampler.as_mut() {
+                    sampler.clear(&subchans);
+                }
+                if let Some(publisher) = publisher.as_ref() {
+                    publisher.clear();
+                }
+                debug!(thread = id, %uri, "channel shutting down");
+                self.env.set_call_queue(id, None);
+                destroy_sessions(rpc, id, &subchans);
+                ctx.pool.release(rpc);
+                // The shutdown completes once the thread is handed back.
+                Some(Box::new(move || {
+                    let _ = stx.send_blocking(());
+                }))
+            }))
             .await
-            .unwrap();
-        env.1.recv().await.map_err(Into::into)
+            .map_err(|_| Error::Channel("poll thread has exited".into()))?;
+        chan_rx.recv().await?
     }
 }
 
@@ -206,14 +516,28 @@ impl ChannelBuilder {
 pub struct Channel {
     pub subchans: Vec<c_int>,
     pub assigned_idx: Arc<AtomicUsize>,
-    pub rpc: Arc<Rpc>,
+    /// Whether calls carry a trace context.
+    pub trace_context: bool,
+    pub(crate) stages: Option<Arc<StageRecorder>>,
     pub tx: Sender<RpcCall>,
     pub rx: Receiver<()>,
 }
 
 impl Debug for Channel {
-    fn fmt(&self, _f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        unimplemented!()
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Channel")
+            .field(
+                "subchans",
+                &self
+                    .subchans
+                    .iter()
+                    .map(|&s| i32::from(s))
+                    .collect::<Vec<_>>(),
+            )
+            .field("assigned_idx", &self.assigned_idx.load(Ordering::Relaxed))
+            .field("trace_context", &self.trace_context)
+            .field("stage_latency", &self.stages.is_some())
+            .finish_non_exhaustive()
     }
 }
 
@@ -223,13 +547,31 @@ impl Channel {
         if idx < self.subchans.len() {
             return Some(SubChannel {
                 id: self.subchans[idx],
-                rpc: self.rpc.clone(),
+                trace_context: self.trace_context,
+                stages: self.stages.clone(),
                 tx: self.tx.clone(),
             });
         }
         None
     }
 
+    /// Returns the latency of the calls on this channel, taken on its poll
+    /// thread.
+    pub async fn latency(&self) -> Result<LatencySnapshot> {
+        let (tx, rx) = bounded::<LatencySnapshot>(1);
+        self.tx
+            .send(RpcCall::Latency(LatencyReq { tx }))
+            .await
+            .map_err(|_| Error::Channel("poll thread has exited".into()))?;
+        rx.recv().await.map_err(Into::into)
+    }
+
+    /// Returns the per-stage latency of the calls, if enabled by
+    /// [`ChannelBuilder::stage_latency`] or [`ServerBuilder::stage_latency`](crate::prelude::ServerBuilder::stage_latency).
+    pub fn stage_latency(&self) -> Option<StageBreakdown> {
+        self.stages.as_ref().map(|s| s.snapshot())
+    }
+
     pub async fn shutdown(&mut self) -> Result<()> {
         self.tx.close();
         self.rx.recv().await.map_err(Into::into)
@@ -239,6 +581,20 @@ impl Channel {
 #[derive(Clone)]
 pub struct SubChannel {
     pub id: c_int,
-    pub rpc: Arc<Rpc>,
+    pub trace_context: bool,
+    pub(crate) stages: Option<Arc<StageRecorder>>,
     pub tx: Sender<RpcCall>,
 }
+
+impl SubChannel {
+    /// Take a snapshot of the session on its poll thread. Returns an error
+    /// if the session has been destroyed.
+    pub async fn stats(&self) -> Result<SessionStats> {
+        let (tx, rx) = bounded::<Result<SessionStats>>(1);
+        self.tx
+            .send(RpcCall::Stats(StatsReq { sid: self.id, tx }))
+            .await
+            .map_err(|_| Error::Channel("poll thread has exited".into()))?;
+        rx.recv().await?
+    }
+}
@@ -1,14 +1,15 @@
 // Copyright (c) 2023, IOMesh Inc. All rights reserved.
 
-use std::sync::Arc;
-
 use crate::{
     call::Call,
     channel::{Channel, SubChannel},
-    error::Result,
+    error::{Error, Result},
     method::Method,
-    msg_buffer::MsgBuffer,
+    metrics::SessionStats,
+    msg_buffer::{MsgBuffer, SharedMsgBuffer},
+    pool::MsgBufferAllocator,
     rpc::ContFunc,
+    trace::TRAILER_LEN,
 };
 
 /// A generic client for making RPC calls.
@@ -30,15 +31,38 @@ impl Client {
         &self,
         method: &Method<Req, Resp>,
         req: &Req,
-        req_msgbuf: Arc<MsgBuffer>,
-        resp_msgbuf: Arc<MsgBuffer>,
+        req_msgbuf: SharedMsgBuffer,
+        resp_msgbuf: SharedMsgBuffer,
         cb: ContFunc,
     ) -> Result<Resp> {
         Call::unary(&self.chan, method, req, req_msgbuf, resp_msgbuf, cb).await
     }
 
-    pub fn alloc_msg_buffer(&mut self, max_data_size: usize) -> MsgBuffer {
-        let rpc = unsafe { Arc::get_mut_unchecked(&mut self.chan.rpc) };
-        rpc.alloc_msg_buffer_or_die(max_data_size)
+    /// Allocate a buffer on the channel's poll thread.
+    ///
+    /// If the channel propagates trace contexts, the buffer has room for the
+    /// request's trailer on top of `max_data_size`, up to the max message
+    /// size.
+    pub async fn alloc_msg_buffer(&self, max_data_size: usize) -> Result<MsgBuffer> {
+        let allocator = MsgBufferAllocator::new(self.chan.tx.clone());
+        let buf = if self.chan.trace_context {
+            match allocator
+                .alloc(max_data_size.saturating_add(TRAILER_LEN))
+                .await
+            {
+                Err(Error::MsgTooLarge(..)) => allocator.alloc(max_data_size).await?,
+                res => res?,
+            }
+        } else {
+            allocator.alloc(max_data_size).await?
+        };
+        let mut buf = buf.into_inner();
+        buf.resize(max_data_size);
+        Ok(buf)
+    }
+
+    /// Take a snapshot of the client's session.
+    pub async fn session_stats(&self) -> Result<SessionStats> {
+        self.chan.stats().await
     }
 }
@@ -8,35 +8,70 @@ pub type SerializeFn<T> = fn(&T, &mut MsgBuffer) -> Result<()>;
 pub struct Marshaller<T> {
     pub ser: SerializeFn<T>,
     pub de: DeserializeFn<T>,
+    /// Whether `de` skips a [`TraceContext`](crate::prelude::TraceContext)
+    /// trailer, an unknown protobuf field appended to requests. Requests of a
+    /// method carry one when this is set for its request marshaller and the
+    /// channel propagates trace contexts, e.g. for [`pr_codec`].
+    pub trace_trailer: bool,
 }
 
 pub mod pr_codec {
+    use bytes::Buf;
     use prost::Message;
 
-    use super::{MsgBuffer, MsgBufferReader};
-    use crate::error::{Error, Result};
+    use crate::{
+        buf::WriteBuf,
+        error::{Error, Result},
+    };
 
     #[inline]
-    pub fn ser<T: Message>(t: &T, buf: &mut MsgBuffer) -> Result<()> {
+    pub fn ser<T: Message, B: WriteBuf>(t: &T, buf: &mut B) -> Result<()> {
         let cap = t.encoded_len();
-        if cap <= buf.get_max_data_size() {
-            buf.resize(cap);
-            let start = buf.get_inner_buf();
-            let len = buf.get_data_size();
-            let mut s = unsafe { std::slice::from_raw_parts_mut(start, len) };
-            t.encode(&mut s)?;
+        if cap <= buf.max_len() {
+            t.encode(&mut buf.reset(cap))?;
             Ok(())
         } else {
             Err(Error::Codec(
-                format!("message is too large: {cap} > {}", buf.get_max_data_size()).into(),
+                format!("message is too large: {cap} > {}", buf.max_len()).into(),
             ))
         }
     }
 
+    /// Decode a message from any [`Buf`], e.g. a [`MsgBufferReader`] or, to use
+    /// a codec without eRPC, `Bytes` or `&[u8]`.
+    ///
+    /// [`MsgBufferReader`]: crate::buf::MsgBufferReader
     #[inline]
-    pub fn de<T: Message + Default>(mut reader: MsgBufferReader) -> Result<T> {
-        use bytes::buf::Buf;
-        reader.advance(0);
-        T::decode(reader).map_err(Into::into)
+    pub fn de<T: Message + Default, B: Buf>(buf: B) -> Result<T> {
+        T::decode(buf).map_err(Into::into)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::{Bytes, BytesMut};
+
+    use super::pr_codec;
+
+    #[test]
+    fn test_pr_codec_vec() {
+        let msg = "hello erpc".to_string();
+        let mut buf = vec![0xff; 64];
+        pr_codec::ser(&msg, &mut buf).unwrap();
+        let decoded: String = pr_codec::de(buf.as_slice()).unwrap();
+        assert_eq!(decoded, msg);
+    }
+
+    #[test]
+    fn test_pr_codec_bytes() {
+        let msg = vec![7u8; 300];
+        let mut buf = BytesMut::new();
+        pr_codec::ser(&msg, &mut buf).unwrap();
+        let decoded: Vec<u8> = pr_codec::de(buf.freeze()).unwrap();
+        assert_eq!(decoded, msg);
+
+        let decoded: Vec<u8> = pr_codec::de(Bytes::new()).unwrap();
+        assert!(decoded.is_empty());
+        assert!(pr_codec::de::<Vec<u8>, _>(Bytes::from_static(&[0x0a, 0x05])).is_err());
     }
 }
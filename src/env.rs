@@ -4,23 +4,33 @@ extern crate num_cpus;
 
 use std::{
     sync::{
-        atomic::{AtomicUsize, Ordering},
-        Arc,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
     },
     thread::{Builder as ThreadBuilder, JoinHandle},
 };
 
-use async_channel::{bounded, Receiver, Sender};
+use async_channel::{bounded, Receiver, Sender, WeakSender};
 
 use crate::{
-    channel::{Channel, RpcPollFn},
+    admin::{AdminRegistry, EnvState},
+    call::{LatencyReq, RpcCall},
+    channel::RpcPollFn,
+    histogram::LatencySnapshot,
+    metrics::MetricsRegistry,
     nexus::Nexus,
+    poll_stats::{PollCounters, PollThreadStats},
 };
 
-// event loop
-fn poll_channel(id: u8, mut nexus: Arc<Nexus>, rx: Receiver<RpcPollFn>, tx: Sender<Channel>) {
-    if let Ok(rpc_poll_fn) = rx.recv_blocking() {
-        rpc_poll_fn(id, &mut nexus, tx);
+// event loop, the thread is handed back to the environment whenever a
+// channel fails to start or is shut down.
+fn poll_channel(id: u8, nexus: Arc<Nexus>, busy: Arc<AtomicBool>, rx: Receiver<RpcPollFn>) {
+    while let Ok(rpc_poll_fn) = rx.recv_blocking() {
+        let done = rpc_poll_fn(id, &nexus);
+        busy.store(false, Ordering::Release);
+        if let Some(done) = done {
+            done();
+        }
     }
 }
 
@@ -33,9 +43,12 @@ pub struct EnvBuilder {
     /// interface that eRPC uses for performance-critical RPC traffic.
     local_uri: String,
     chan_count: usize,
+    bg_thread_count: usize,
     name_prefix: Option<String>,
     after_start: Option<Arc<dyn Fn() + Send + Sync>>,
     before_stop: Option<Arc<dyn Fn() + Send + Sync>>,
+    metrics: Option<MetricsRegistry>,
+    admin: Option<AdminRegistry>,
 }
 
 impl EnvBuilder {
@@ -44,9 +57,12 @@ impl EnvBuilder {
         EnvBuilder {
             local_uri: local_uri.into(),
             chan_count: num_cpus::get(),
+            bg_thread_count: 0,
             name_prefix: None,
             after_start: None,
             before_stop: None,
+            metrics: None,
+            admin: None,
         }
     }
 
@@ -62,6 +78,14 @@ impl EnvBuilder {
         self
     }
 
+    /// Set the number of eRPC background threads, which run the handlers
+    /// added by [`ServiceBuilder::add_unary_bg_handler`](crate::prelude::ServiceBuilder::add_unary_bg_handler).
+    /// Defaults to 0.
+    pub fn bg_thread_count(mut self, count: usize) -> EnvBuilder {
+        self.bg_thread_count = count;
+        self
+    }
+
     /// Set the thread name prefix of each polling thread.
     pub fn name_prefix<S: Into<String>>(mut self, prefix: S) -> EnvBuilder {
         self.name_prefix = Some(prefix.into());
@@ -80,15 +104,39 @@ impl EnvBuilder {
         self
     }
 
+    /// Sample per-session transport metrics of every channel into `registry`,
+    /// servers aren't sampled.
+    pub fn metrics(mut self, registry: MetricsRegistry) -> EnvBuilder {
+        self.metrics = Some(registry);
+        self
+    }
+
+    /// Publish the state of every poll thread to `registry`, for the admin
+    /// endpoint.
+    pub fn admin(mut self, registry: AdminRegistry) -> EnvBuilder {
+        self.admin = Some(registry);
+        self
+    }
+
     /// Finalize the [`EnvBuilder`], build the [`Environment`] and initialize the gRPC library.
     pub fn build(self) -> Environment {
-        let nexus = Arc::new(Nexus::new(self.local_uri.as_str(), 0));
+        let nexus = Arc::new(Nexus::with_bg_threads(
+            self.local_uri.as_str(),
+            0,
+            self.bg_thread_count,
+        ));
         let mut handles = Vec::with_capacity(self.chan_count);
         let mut chs = Vec::with_capacity(self.chan_count);
+        let mut busy = Vec::with_capacity(self.chan_count);
+        let mut poll_counters = Vec::with_capacity(self.chan_count);
+        let mut calls = Vec::with_capacity(self.chan_count);
         for i in 0..self.chan_count {
             let (tx, rx) = bounded::<RpcPollFn>(1);
-            let (chan_tx, chan_rx) = bounded::<Channel>(1);
             let nexus = nexus.clone();
+            let thread_busy = Arc::new(AtomicBool::new(false));
+            busy.push(thread_busy.clone());
+            poll_counters.push(Arc::new(PollCounters::default()));
+            calls.push(Mutex::new(None));
             let mut builder = ThreadBuilder::new();
             if let Some(ref prefix) = self.name_prefix {
                 builder = builder.name(format!("{prefix}-{i}"));
@@ -100,27 +148,41 @@ impl EnvBuilder {
                     if let Some(f) = after_start {
                         f();
                     }
-                    poll_channel(i.try_into().unwrap(), nexus, rx, chan_tx);
+                    poll_channel(i.try_into().unwrap(), nexus, thread_busy, rx);
                     if let Some(f) = before_stop {
                         f();
                     }
                 })
                 .unwrap();
             handles.push(handle);
-            chs.push((tx, chan_rx));
+            chs.push(tx);
         }
 
+        let admin = self
+            .admin
+            .map(|registry| registry.register(self.local_uri.clone(), busy.clone()));
         Environment {
             chs,
+            busy,
+            poll_counters,
+            calls,
+            admin,
             idx: AtomicUsize::new(0),
+            metrics: self.metrics,
             _handles: handles,
         }
     }
 }
 
 pub struct Environment {
-    chs: Vec<(Sender<RpcPollFn>, Receiver<Channel>)>,
+    chs: Vec<Sender<RpcPollFn>>,
+    busy: Vec<Arc<AtomicBool>>,
+    poll_counters: Vec<Arc<PollCounters>>,
+    /// The call queue of the channel each poll thread runs, if any.
+    calls: Vec<Mutex<Option<WeakSender<RpcCall>>>>,
     idx: AtomicUsize,
+    metrics: Option<MetricsRegistry>,
+    admin: Option<Arc<EnvState>>,
     _handles: Vec<JoinHandle<()>>,
 }
 
@@ -132,11 +194,69 @@ impl Environment {
             .build()
     }
 
-    pub fn pick_channel_env(&self) -> Option<(Sender<RpcPollFn>, Receiver<Channel>)> {
-        let idx = self.idx.fetch_add(1, Ordering::Relaxed);
-        if idx < self.chs.len() {
-            return Some(self.chs[idx].clone());
+    /// Returns the metrics registry, if enabled by [`EnvBuilder::metrics`].
+    #[inline]
+    pub fn metrics(&self) -> Option<&MetricsRegistry> {
+        self.metrics.as_ref()
+    }
+
+    /// Returns the event-loop statistics of each poll thread, indexed by
+    /// thread id.
+    pub fn poll_stats(&self) -> Vec<PollThreadStats> {
+        self.poll_counters.iter().map(|c| c.snapshot()).collect()
+    }
+
+    #[inline]
+    pub(crate) fn poll_counters(&self, id: u8) -> Arc<PollCounters> {
+        self.poll_counters[id as usize].clone()
+    }
+
+    /// Returns the call latency of every channel, merged.
+    pub async fn latency(&self) -> LatencySnapshot {
+        let queues: Vec<_> = self
+            .calls
+            .iter()
+            .filter_map(|calls| calls.lock().unwrap().as_ref().and_then(WeakSender::upgrade))
+            .collect();
+        let mut snapshot = LatencySnapshot::default();
+        for calls in queues {
+            let (tx, rx) = bounded::<LatencySnapshot>(1);
+            // The channel may shut down meanwhile.
+            if calls
+                .send(RpcCall::Latency(LatencyReq { tx }))
+                .await
+                .is_err()
+            {
+                continue;
+            }
+            if let Ok(latency) = rx.recv().await {
+                snapshot.merge(&latency);
+            }
         }
-        None
+        snapshot
+    }
+
+    pub(crate) fn set_call_queue(&self, id: u8, calls: Option<WeakSender<RpcCall>>) {
+        *self.calls[id as usize].lock().unwrap() = calls;
+    }
+
+    #[inline]
+    pub(crate) fn admin_state(&self) -> Option<&Arc<EnvState>> {
+        self.admin.as_ref()
+    }
+
+    /// Reserve an idle poll thread, returns `None` if all of them are busy.
+    /// The thread runs the first [`RpcPollFn`] sent to it and is handed
+    /// back once that returns.
+    pub fn pick_channel_env(&self) -> Option<Sender<RpcPollFn>> {
+        let start = self.idx.fetch_add(1, Ordering::Relaxed);
+        (0..self.chs.len())
+            .map(|i| (start + i) % self.chs.len())
+            .find(|&i| {
+                self.busy[i]
+                    .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+                    .is_ok()
+            })
+            .map(|i| self.chs[i].clone())
     }
 }
@@ -6,6 +6,26 @@ use std::{
     result,
 };
 
+use erpc_sys::{erpc::SmErrType, EALREADY, EBUSY};
+
+/// The raw code eRPC reported an error with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorCode {
+    /// An errno value returned by an eRPC call, e.g. `EBUSY`.
+    Errno(u32),
+    /// A session management error, the value of a `SmErrType`.
+    Sm(u32),
+}
+
+impl Display for ErrorCode {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrorCode::Errno(code) => write!(fmt, "errno {code}"),
+            ErrorCode::Sm(code) => write!(fmt, "sm error {code}"),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum Error {
     /// Codec error.
@@ -14,6 +34,69 @@ pub enum Error {
     Channel(Box<dyn error::Error + Send + Sync>),
     /// Erpc internal error.
     Internal(String),
+    /// Session creation failed, e.g. it's called from a thread other than the
+    /// one that created the `Rpc`.
+    SessionCreate(ErrorCode),
+    /// Session destruction failed. `EBUSY` means the session has pending
+    /// requests, `EALREADY` means a disconnection is in progress.
+    SessionDestroy(ErrorCode),
+    /// Ring buffers or hugepages are exhausted.
+    OutOfMemory(ErrorCode),
+    /// The remote URI can't be resolved or refers to the local `Rpc`.
+    InvalidUri(ErrorCode),
+    /// No `Rpc` with the requested id exists on the remote host (yet).
+    RemoteRpcIdInvalid(ErrorCode),
+    /// The session was disconnected.
+    Disconnected(ErrorCode),
+    /// The operation timed out.
+    Timeout(ErrorCode),
+    /// A request handler can't be registered for the request type.
+    RegisterHandler(u8, ErrorCode),
+    /// A buffer of the size, the first field, exceeds eRPC's max message
+    /// size, the second one.
+    MsgTooLarge(usize, usize),
+}
+
+impl Error {
+    /// Map a session management error to an [`Error`], `kNoError` maps to `None`.
+    pub fn from_sm_err(err: SmErrType) -> Option<Error> {
+        let code = ErrorCode::Sm(err as u32);
+        match err {
+            SmErrType::kNoError => None,
+            SmErrType::kSrvDisconnected => Some(Error::Disconnected(code)),
+            SmErrType::kRingExhausted | SmErrType::kOutOfMemory => Some(Error::OutOfMemory(code)),
+            SmErrType::kRoutingResolutionFailure => Some(Error::InvalidUri(code)),
+            SmErrType::kInvalidRemoteRpcId => Some(Error::RemoteRpcIdInvalid(code)),
+            _ => Some(Error::SessionCreate(code)),
+        }
+    }
+
+    /// Returns the raw code eRPC reported, if any.
+    pub fn code(&self) -> Option<ErrorCode> {
+        match *self {
+            Error::SessionCreate(code)
+            | Error::SessionDestroy(code)
+            | Error::OutOfMemory(code)
+            | Error::InvalidUri(code)
+            | Error::RemoteRpcIdInvalid(code)
+            | Error::Disconnected(code)
+            | Error::Timeout(code)
+            | Error::RegisterHandler(_, code) => Some(code),
+            _ => None,
+        }
+    }
+
+    /// Returns true if the same operation may succeed when tried again later.
+    pub fn is_retryable(&self) -> bool {
+        match *self {
+            Error::OutOfMemory(_)
+            | Error::RemoteRpcIdInvalid(_)
+            | Error::Disconnected(_)
+            | Error::Timeout(_) => true,
+            Error::SessionDestroy(ErrorCode::Errno(code)) => code == EBUSY,
+            _ => false,
+        }
+    }
 }
 
 impl Display for Error {
@@ -28,6 +111,42 @@ impl Display for Error {
             Error::Internal(s) => {
                 write!(fmt, "internal error: {s:?}")
             }
+            Error::SessionCreate(code) => {
+                write!(fmt, "failed to create session: {code}")
+            }
+            Error::SessionDestroy(ErrorCode::Errno(EBUSY)) => {
+                write!(fmt, "failed to destroy session: requests pending")
+            }
+            Error::SessionDestroy(ErrorCode::Errno(EALREADY)) => {
+                write!(fmt, "failed to destroy session: disconnection in progress")
+            }
+            Error::SessionDestroy(code) => {
+                write!(fmt, "failed to destroy session: {code}")
+            }
+            Error::OutOfMemory(code) => {
+                write!(fmt, "out of memory: {code}")
+            }
+            Error::InvalidUri(code) => {
+                write!(fmt, "invalid remote uri: {code}")
+            }
+            Error::RemoteRpcIdInvalid(code) => {
+                write!(fmt, "invalid remote rpc id: {code}")
+            }
+            Error::Disconnected(code) => {
+                write!(fmt, "session disconnected: {code}")
+            }
+            Error::Timeout(code) => {
+                write!(fmt, "timed out: {code}")
+            }
+            Error::RegisterHandler(req_type, code) => {
+                write!(fmt, "failed to register handler for {req_type}: {code}")
+            }
+            Error::MsgTooLarge(size, max) => {
+                write!(
+                    fmt,
+                    "message size {size} exceeds the max message size {max}"
+                )
+            }
         }
     }
 }
@@ -62,3 +181,56 @@ impl From<async_channel::RecvError> for Error {
 
 /// Type alias to use this library's [`Error`] type in a `Result`.
 pub type Result<T> = result::Result<T, Error>;
+
+#[cfg(test)]
+mod tests {
+    use erpc_sys::ETIMEDOUT;
+
+    use super::*;
+
+    #[test]
+    fn test_from_sm_err() {
+        assert!(Error::from_sm_err(SmErrType::kNoError).is_none());
+
+        let cases = [
+            (SmErrType::kSrvDisconnected, true),
+            (SmErrType::kRingExhausted, true),
+            (SmErrType::kOutOfMemory, true),
+            (SmErrType::kRoutingResolutionFailure, false),
+            (SmErrType::kInvalidRemoteRpcId, true),
+            (SmErrType::kInvalidTransport, false),
+        ];
+        for (sm_err, retryable) in cases {
+            let err = Error::from_sm_err(sm_err).unwrap();
+            assert_eq!(err.code(), Some(ErrorCode::Sm(sm_err as u32)));
+            assert_eq!(err.is_retryable(), retryable, "{sm_err:?}");
+        }
+
+        assert!(matches!(
+            Error::from_sm_err(SmErrType::kSrvDisconnected),
+            Some(Error::Disconnected(_))
+        ));
+        assert!(matches!(
+            Error::from_sm_err(SmErrType::kRingExhausted),
+            Some(Error::OutOfMemory(_))
+        ));
+        assert!(matches!(
+            Error::from_sm_err(SmErrType::kRoutingResolutionFailure),
+            Some(Error::InvalidUri(_))
+        ));
+        assert!(matches!(
+            Error::from_sm_err(SmErrType::kInvalidTransport),
+            Some(Error::SessionCreate(_))
+        ));
+    }
+
+    #[test]
+    fn test_is_retryable() {
+        assert!(Error::Timeout(ErrorCode::Errno(ETIMEDOUT)).is_retryable());
+        assert!(Error::SessionDestroy(ErrorCode::Errno(EBUSY)).is_retryable());
+        assert!(!Error::SessionDestroy(ErrorCode::Errno(EALREADY)).is_retryable());
+        assert!(!Error::RegisterHandler(1, ErrorCode::Errno(EBUSY)).is_retryable());
+        assert!(!Error::Internal("internal".to_string()).is_retryable());
+        assert!(Error::Internal("internal".to_string()).code().is_none());
+    }
+}
@@ -0,0 +1,274 @@
+// Copyright (c) 2023, IOMesh Inc. All rights reserved.
+
+use std::{
+    collections::HashMap,
+    fmt::{self, Debug},
+};
+
+/// Each power of two is split into `1 << SUB_BUCKET_BITS` buckets, which
+/// bounds the relative error of a percentile to 1/16.
+const SUB_BUCKET_BITS: u32 = 4;
+const SUB_BUCKETS: usize = 1 << SUB_BUCKET_BITS;
+const BUCKETS: usize = (u64::BITS - SUB_BUCKET_BITS + 1) as usize * SUB_BUCKETS;
+
+#[inline]
+fn bucket_index(value: u64) -> usize {
+    if value < SUB_BUCKETS as u64 {
+        return value as usize;
+    }
+    let shift = u64::BITS - 1 - value.leading_zeros() - SUB_BUCKET_BITS;
+    let sub = (value >> shift) as usize & (SUB_BUCKETS - 1);
+    (shift as usize + 1) * SUB_BUCKETS + sub
+}
+
+/// The largest value that falls into bucket `index`.
+#[inline]
+fn bucket_upper_bound(index: usize) -> u64 {
+    if index < SUB_BUCKETS {
+        return index as u64;
+    }
+    let shift = (index / SUB_BUCKETS - 1) as u32;
+    let lower = ((SUB_BUCKETS + index % SUB_BUCKETS) as u64) << shift;
+    lower + ((1u64 << shift) - 1)
+}
+
+/// A fixed-memory latency histogram with log-scaled buckets.
+///
+/// Values are usually nanoseconds. Histograms recorded on different poll
+/// threads can be combined with [`Histogram::merge`].
+#[derive(Clone)]
+pub struct Histogram {
+    buckets: Box<[u64]>,
+    count: u64,
+    sum: u64,
+    min: u64,
+    max: u64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Histogram::new()
+    }
+}
+
+impl Debug for Histogram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Histogram")
+            .field("count", &self.count)
+            .field("min", &self.min())
+            .field("max", &self.max)
+            .field("mean", &self.mean())
+            .finish()
+    }
+}
+
+impl Histogram {
+    /// Initialize a new, empty [`Histogram`].
+    pub fn new() -> Self {
+        Histogram {
+            buckets: vec![0; BUCKETS].into_boxed_slice(),
+            count: 0,
+            sum: 0,
+            min: u64::MAX,
+            max: 0,
+        }
+    }
+
+    #[inline]
+    pub fn record(&mut self, value: u64) {
+        self.buckets[bucket_index(value)] += 1;
+        self.count += 1;
+        self.sum = self.sum.saturating_add(value);
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+    }
+
+    #[inline]
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Returns the smallest recorded value, or 0 if empty.
+    #[inline]
+    pub fn min(&self) -> u64 {
+        if self.is_empty() {
+            0
+        } else {
+            self.min
+        }
+    }
+
+    #[inline]
+    pub fn max(&self) -> u64 {
+        self.max
+    }
+
+    /// Returns the mean of the recorded values, or 0 if empty.
+    pub fn mean(&self) -> f64 {
+        if self.is_empty() {
+            0.0
+        } else {
+            self.sum as f64 / self.count as f64
+        }
+    }
+
+    /// Returns the value below which a fraction `q` of the recorded values
+    /// falls, e.g. `quantile(0.99)` for the 99th percentile. Returns 0 if empty.
+    pub fn quantile(&self, q: f64) -> u64 {
+        if self.is_empty() {
+            return 0;
+        }
+        let rank = ((q.clamp(0.0, 1.0) * self.count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (index, &n) in self.buckets.iter().enumerate() {
+            seen += n;
+            if seen >= rank {
+                return bucket_upper_bound(index).clamp(self.min, self.max);
+            }
+        }
+        self.max
+    }
+
+    /// Add the values recorded by `other` to this histogram.
+    pub fn merge(&mut self, other: &Histogram) {
+        for (a, b) in self.buckets.iter_mut().zip(other.buckets.iter()) {
+            *a += b;
+        }
+        self.count += other.count;
+        self.sum = self.sum.saturating_add(other.sum);
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+    }
+
+    pub fn reset(&mut self) {
+        self.buckets.iter_mut().for_each(|n| *n = 0);
+        self.count = 0;
+        self.sum = 0;
+        self.min = u64::MAX;
+        self.max = 0;
+    }
+}
+
+/// The call latency of one or more channels, in nanoseconds.
+///
+/// Get it with [`Channel::latency`](crate::prelude::Channel::latency) or, for
+/// every channel of an environment, [`Environment::latency`](crate::prelude::Environment::latency).
+#[derive(Default, Debug, Clone)]
+pub struct LatencySnapshot {
+    /// Latency of every call.
+    pub all: Histogram,
+    /// Latency of the calls by request type.
+    pub methods: HashMap<u8, Histogram>,
+}
+
+impl LatencySnapshot {
+    /// Add the calls of `other` to this snapshot.
+    pub fn merge(&mut self, other: &LatencySnapshot) {
+        self.all.merge(&other.all);
+        for (req_type, h) in &other.methods {
+            self.methods.entry(*req_type).or_default().merge(h);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bucket_bounds() {
+        for value in (0..4096).chain([u64::MAX / 3, u64::MAX]) {
+            let index = bucket_index(value);
+            assert!(index < BUCKETS);
+            assert!(value <= bucket_upper_bound(index), "{value}");
+            if index > 0 {
+                assert!(value > bucket_upper_bound(index - 1), "{value}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_record() {
+        let mut h = Histogram::new();
+        assert!(h.is_empty());
+        assert_eq!((h.min(), h.max(), h.quantile(0.5)), (0, 0, 0));
+        assert_eq!(h.mean(), 0.0);
+
+        for value in [10, 20, 30, 40] {
+            h.record(value);
+        }
+        assert_eq!(h.count(), 4);
+        assert_eq!((h.min(), h.max()), (10, 40));
+        assert_eq!(h.mean(), 25.0);
+
+        h.reset();
+        assert!(h.is_empty());
+        assert_eq!(h.quantile(0.99), 0);
+    }
+
+    #[test]
+    fn test_quantile() {
+        let mut h = Histogram::new();
+        for value in 1..=1000 {
+            h.record(value);
+        }
+        assert_eq!(h.quantile(0.0), 1);
+        assert_eq!(h.quantile(1.0), 1000);
+        // Quantiles are bucket upper bounds, within 1/16 of the exact value.
+        for (q, exact) in [(0.5, 500.0), (0.9, 900.0), (0.99, 990.0)] {
+            let value = h.quantile(q) as f64;
+            assert!(
+                value >= exact && value <= exact * (1.0 + 1.0 / 16.0),
+                "{q}: {value}"
+            );
+        }
+
+        let mut h = Histogram::new();
+        h.record(12345);
+        assert_eq!(h.quantile(0.5), 12345);
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut a = Histogram::new();
+        let mut b = Histogram::new();
+        let mut all = Histogram::new();
+        for value in 0..500 {
+            a.record(value * 3);
+            b.record(value * 7 + 1000);
+            all.record(value * 3);
+            all.record(value * 7 + 1000);
+        }
+        a.merge(&b);
+        assert_eq!(a.count(), all.count());
+        assert_eq!((a.min(), a.max()), (all.min(), all.max()));
+        assert_eq!(a.mean(), all.mean());
+        for q in [0.1, 0.5, 0.9, 0.999] {
+            assert_eq!(a.quantile(q), all.quantile(q));
+        }
+
+        a.merge(&Histogram::new());
+        assert_eq!((a.count(), a.min()), (all.count(), all.min()));
+    }
+
+    #[test]
+    fn test_merge_snapshot() {
+        let mut a = LatencySnapshot::default();
+        a.all.record(100);
+        a.methods.entry(1).or_default().record(100);
+        let mut b = LatencySnapshot::default();
+        b.all.record(200);
+        b.methods.entry(1).or_default().record(200);
+        b.methods.entry(2).or_default().record(300);
+
+        a.merge(&b);
+        assert_eq!(a.all.count(), 2);
+        assert_eq!(a.methods[&1].count(), 2);
+        assert_eq!(a.methods[&2].max(), 300);
+    }
+}
@@ -1,8 +1,6 @@
 // Copyright (c) 2023, IOMesh Inc. All rights reserved.
 
-#![feature(get_mut_unchecked)]
-#![feature(sort_floats)]
-
+mod admin;
 mod buf;
 mod call;
 mod channel;
@@ -10,25 +8,42 @@ mod client;
 mod codec;
 mod env;
 mod error;
+mod histogram;
 mod method;
+mod method_stats;
+mod metrics;
 mod msg_buffer;
 mod nexus;
+mod poll_stats;
+mod pool;
 mod req_handle;
 mod rpc;
 mod server;
+mod stage;
 #[cfg(feature = "bench_stat")]
 mod stat;
+#[cfg(feature = "loopback")]
+pub mod testing;
 mod timely;
 mod timing_wheel;
+mod trace;
+
+/// Seeded fault injection for the loopback, see [`sim::SimBuilder`].
+#[cfg(feature = "loopback")]
+pub use erpc_sys::sim;
 
 pub mod prelude {
     //! A "prelude" for crates using `erpc-rs`.
     #[doc(no_inline)]
-    pub use crate::buf::MsgBufferReader;
+    pub use crate::admin::{AdminRegistry, AdminServer};
     #[doc(no_inline)]
-    pub use crate::call::{CallTag, Codec, RpcCall, Tag};
+    pub use crate::buf::{MsgBufferReader, WriteBuf};
+    #[doc(no_inline)]
+    pub use crate::call::{
+        AllocReq, CallTag, Codec, LatencyReq, RpcCall, SettleReq, StatsReq, Tag,
+    };
     #[doc(no_inline)]
-    pub use crate::channel::{Channel, ChannelBuilder, ClientRpcContext};
+    pub use crate::channel::{Channel, ChannelBuilder, ClientRpcContext, SubChannel};
     #[doc(no_inline)]
     pub use crate::client::Client;
     #[doc(no_inline)]
@@ -36,23 +51,42 @@ pub mod prelude {
     #[doc(no_inline)]
     pub use crate::env::{EnvBuilder, Environment};
     #[doc(no_inline)]
-    pub use crate::error::Result;
+    pub use crate::error::{Error, ErrorCode, Result};
+    #[doc(no_inline)]
+    pub use crate::histogram::{Histogram, LatencySnapshot};
     #[doc(no_inline)]
     pub use crate::method::Method;
     #[doc(no_inline)]
-    pub use crate::msg_buffer::MsgBuffer;
+    pub use crate::method_stats::{RequestStats, ServerStats};
+    #[doc(no_inline)]
+    pub use crate::metrics::{MetricsRegistry, SessionMetrics, SessionStats};
+    #[doc(no_inline)]
+    pub use crate::msg_buffer::{MsgBuffer, MsgBufferLease, SharedMsgBuffer};
     #[doc(no_inline)]
     pub use crate::nexus::{Nexus, ReqHandler};
     #[doc(no_inline)]
+    pub use crate::poll_stats::PollThreadStats;
+    #[doc(no_inline)]
+    pub use crate::pool::{MsgBufferAllocator, MsgBufferPool, PooledMsgBuffer};
+    #[doc(no_inline)]
     pub use crate::req_handle::ReqHandle;
     #[doc(no_inline)]
-    pub use crate::rpc::{ContFunc, Rpc, SmHandler};
+    pub use crate::rpc::{ContFunc, LinkInfo, Rpc, SmHandler};
     #[doc(no_inline)]
     pub use crate::server::{Server, ServerBuilder, ServerRpcContext, Service, ServiceBuilder};
     #[doc(no_inline)]
+    pub use crate::stage::StageBreakdown;
+    #[cfg(feature = "bench_stat")]
+    #[doc(no_inline)]
+    pub use crate::stat::{CsvSink, JsonLinesSink, StatsRecord, StatsSink, TransStats};
+    #[doc(no_inline)]
     pub use crate::timely::Timely;
     #[doc(no_inline)]
-    pub use crate::timing_wheel::TimingWheel;
+    pub use crate::timing_wheel::{
+        PacingReport, SessionPacing, TimingWheel, WheelEvent, WheelRecord, WheelRecords,
+    };
+    #[doc(no_inline)]
+    pub use crate::trace::TraceContext;
     #[doc(no_inline)]
     pub use erpc_sys::erpc::{
         get_uri_for_process, kSessionCredits, rdtsc, sm_err_type_str, sm_event_type_str, to_usec,
@@ -6,6 +6,9 @@ pub struct Method<Req, Resp> {
     /// The unique id of the method.
     pub id: u8,
 
+    /// The full name of the method, e.g. `/helloworld.Greeter/SayHello`.
+    pub name: &'static str,
+
     /// The marshaller used for request messages.
     pub req_mar: Marshaller<Req>,
 
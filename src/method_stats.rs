@@ -0,0 +1,208 @@
+// Copyright (c) 2023, IOMesh Inc. All rights reserved.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Instant,
+};
+
+use crate::histogram::Histogram;
+
+/// Counters of the requests of one method, or of all methods of a service.
+#[derive(Default, Debug, Clone)]
+pub struct RequestStats {
+    pub requests: u64,
+    /// Requests which failed, by status: `panicked` if the handler panicked,
+    /// `cancelled` if it was dropped before completing, `unanswered` if it
+    /// completed without responding.
+    pub errors: BTreeMap<&'static str, u64>,
+    pub req_bytes: u64,
+    pub resp_bytes: u64,
+    /// Time from the request being dispatched until its response is
+    /// enqueued, in nanoseconds.
+    pub latency: Histogram,
+    /// Requests dispatched but not answered yet.
+    pub in_flight: u64,
+}
+
+impl RequestStats {
+    /// Returns the number of failed requests of any status.
+    pub fn error_count(&self) -> u64 {
+        self.errors.values().sum()
+    }
+
+    pub fn merge(&mut self, other: &RequestStats) {
+        self.requests += other.requests;
+        for (status, n) in &other.errors {
+            *self.errors.entry(status).or_default() += n;
+        }
+        self.req_bytes += other.req_bytes;
+        self.resp_bytes += other.resp_bytes;
+        self.latency.merge(&other.latency);
+        self.in_flight += other.in_flight;
+    }
+}
+
+/// A snapshot of the per-method statistics of a server.
+///
+/// Enable it with [`ServerBuilder::method_stats`](crate::prelude::ServerBuilder::method_stats).
+#[derive(Default, Debug, Clone)]
+pub struct ServerStats {
+    methods: BTreeMap<u8, (&'static str, RequestStats)>,
+}
+
+impl ServerStats {
+    /// Returns the statistics of method `id`.
+    pub fn method(&self, id: u8) -> Option<&RequestStats> {
+        self.methods.get(&id).map(|(_, s)| s)
+    }
+
+    /// Returns the statistics of the method named `name`, e.g.
+    /// `/helloworld.Greeter/SayHello` for methods generated by `erpc-build`.
+    pub fn method_by_name(&self, name: &str) -> Option<&RequestStats> {
+        self.methods
+            .values()
+            .find(|(n, _)| *n == name)
+            .map(|(_, s)| s)
+    }
+
+    /// Iterate over the methods as `(id, name, stats)`.
+    pub fn methods(&self) -> impl Iterator<Item = (u8, &'static str, &RequestStats)> {
+        self.methods.iter().map(|(id, (name, s))| (*id, *name, s))
+    }
+
+    /// Returns the statistics of each service, keyed by the service part of
+    /// the method names. Methods without a name are accounted to `""`.
+    pub fn services(&self) -> BTreeMap<&'static str, RequestStats> {
+        let mut services = BTreeMap::<_, RequestStats>::new();
+        for (name, s) in self.methods.values() {
+            services.entry(service_name(name)).or_default().merge(s);
+        }
+        services
+    }
+}
+
+/// `/pkg.Service/Method` -> `pkg.Service`.
+fn service_name(method_name: &'static str) -> &'static str {
+    method_name
+        .trim_start_matches('/')
+        .rsplit_once('/')
+        .map_or("", |(service, _)| service)
+}
+
+#[derive(Default)]
+struct Inner {
+    methods: BTreeMap<u8, RequestStats>,
+    /// Method and dispatch time of the requests in flight, by request id.
+    pending: HashMap<u64, (u8, Instant)>,
+}
+
+/// Records the requests of one server, on its poll thread and the handlers'
+/// runtime.
+pub(crate) struct MethodRecorder {
+    names: HashMap<u8, &'static str>,
+    next_id: AtomicU64,
+    inner: Mutex<Inner>,
+}
+
+impl MethodRecorder {
+    pub(crate) fn new(names: HashMap<u8, &'static str>) -> Self {
+        MethodRecorder {
+            names,
+            next_id: AtomicU64::new(1),
+            inner: Mutex::new(Inner::default()),
+        }
+    }
+
+    /// A request of `method` is dispatched to its handler, returns the id
+    /// to track it by.
+    pub(crate) fn start(&self, method: u8, req_bytes: usize) -> u64 {
+        let req = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let mut inner = self.inner.lock().unwrap();
+        let s = inner.methods.entry(method).or_default();
+        s.requests += 1;
+        s.req_bytes += req_bytes as u64;
+        s.in_flight += 1;
+        inner.pending.insert(req, (method, Instant::now()));
+        req
+    }
+
+    /// The response of `req` is enqueued.
+    pub(crate) fn respond(&self, req: u64, resp_bytes: usize) {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some((method, start)) = inner.pending.remove(&req) {
+            let ns = start.elapsed().as_nanos() as u64;
+            let s = inner.methods.entry(method).or_default();
+            s.resp_bytes += resp_bytes as u64;
+            s.latency.record(ns);
+            s.in_flight -= 1;
+        }
+    }
+
+    /// The handler of `req` has completed, and its response, if any, has
+    /// been enqueued.
+    pub(crate) fn settle(&self, req: u64) {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some((method, _)) = inner.pending.remove(&req) {
+            let s = inner.methods.entry(method).or_default();
+            s.in_flight -= 1;
+            *s.errors.entry("unanswered").or_default() += 1;
+        }
+    }
+
+    /// The handler of `req` failed with `status`.
+    pub(crate) fn fail(&self, req: u64, method: u8, status: &'static str) {
+        let mut inner = self.inner.lock().unwrap();
+        let answered = inner.pending.remove(&req).is_none();
+        let s = inner.methods.entry(method).or_default();
+        if !answered {
+            s.in_flight -= 1;
+        }
+        *s.errors.entry(status).or_default() += 1;
+    }
+
+    pub(crate) fn snapshot(&self) -> ServerStats {
+        let inner = self.inner.lock().unwrap();
+        ServerStats {
+            methods: self
+                .names
+                .iter()
+                .map(|(&id, &name)| {
+                    let s = inner.methods.get(&id).cloned().unwrap_or_default();
+                    (id, (name, s))
+                })
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_settle() {
+        let recorder = MethodRecorder::new(HashMap::from([(1, "/pkg.Service/Method")]));
+        let answered = recorder.start(1, 10);
+        let unanswered = recorder.start(1, 10);
+        let panicked = recorder.start(1, 10);
+        assert!(answered < unanswered && unanswered < panicked);
+
+        recorder.respond(answered, 20);
+        recorder.settle(answered);
+        recorder.settle(unanswered);
+        recorder.fail(panicked, 1, "panicked");
+
+        let stats = recorder.snapshot();
+        let s = stats.method_by_name("/pkg.Service/Method").unwrap();
+        assert_eq!((s.requests, s.req_bytes, s.resp_bytes), (3, 30, 20));
+        assert_eq!(s.latency.count(), 1);
+        assert_eq!(s.in_flight, 0);
+        assert_eq!(s.errors["unanswered"], 1);
+        assert_eq!(s.errors["panicked"], 1);
+        assert_eq!(stats.services()["pkg.Service"].error_count(), 2);
+    }
+}
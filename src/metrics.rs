@@ -0,0 +1,346 @@
+// Copyright (c) 2023, IOMesh Inc. All rights reserved.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write,
+    sync::{Arc, Mutex},
+};
+
+use erpc_sys::{
+    c_int,
+    erpc::{kSessionCredits, ms_to_cycles, rdtsc},
+};
+
+use crate::{
+    error::{Error, Result},
+    rpc::Rpc,
+};
+
+const DEFAULT_SAMPLE_INTERVAL_MS: usize = 1000;
+
+/// Name, type, help and value of each exported metric family.
+type Family = (
+    &'static str,
+    &'static str,
+    &'static str,
+    fn(&SessionMetrics) -> f64,
+);
+
+const FAMILIES: [Family; 5] = [
+    (
+        "erpc_session_retransmissions_total",
+        "counter",
+        "Packets retransmitted on the session.",
+        |m| m.re_tx as f64,
+    ),
+    (
+        "erpc_session_rtt_p50_microseconds",
+        "gauge",
+        "Median packet RTT of the session.",
+        |m| m.rtt_50_us,
+    ),
+    (
+        "erpc_session_rtt_p99_microseconds",
+        "gauge",
+        "99th percentile packet RTT of the session.",
+        |m| m.rtt_99_us,
+    ),
+    (
+        "erpc_session_timely_rate_gbps",
+        "gauge",
+        "Sending rate chosen by Timely for the session.",
+        |m| m.rate_gbps,
+    ),
+    (
+        "erpc_session_connected",
+        "gauge",
+        "Whether the session is connected.",
+        |m| if m.connected { 1.0 } else { 0.0 },
+    ),
+];
+
+/// Transport metrics of one client session, sampled on its poll thread.
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct SessionMetrics {
+    /// The URI of the remote process.
+    pub remote_uri: String,
+    /// Packets retransmitted since the session was created.
+    pub re_tx: usize,
+    /// Median packet RTT.
+    pub rtt_50_us: f64,
+    /// 99th percentile packet RTT.
+    pub rtt_99_us: f64,
+    /// The sending rate chosen by Timely.
+    pub rate_gbps: f64,
+    pub connected: bool,
+}
+
+/// A snapshot of one client session, taken on its poll thread.
+///
+/// Get it with [`SubChannel::stats`](crate::prelude::SubChannel::stats).
+#[derive(Default, Debug, Clone, Copy, PartialEq)]
+pub struct SessionStats {
+    pub connected: bool,
+    /// Session credits held by outstanding requests, out of `kSessionCredits`.
+    pub credits_in_use: usize,
+    /// Requests enqueued on the session whose continuation hasn't fired yet.
+    pub outstanding: usize,
+    /// Packets retransmitted since the session was created. With `bench_stat`,
+    /// the first session of a channel counts from the last stats interval.
+    pub re_tx: usize,
+    /// Median packet RTT.
+    pub rtt_50_us: f64,
+    /// 99th percentile packet RTT.
+    pub rtt_99_us: f64,
+    /// The sending rate chosen by Timely.
+    pub rate_gbps: f64,
+}
+
+impl SessionStats {
+    /// Returns an error if the session has been destroyed.
+    pub(crate) fn sample(rpc: &mut Rpc, sid: c_int, outstanding: usize) -> Result<Self> {
+        let credits = rpc.get_session_credits(sid).ok_or_else(|| {
+            Error::Channel(format!("session {} doesn't exist", i32::from(sid)).into())
+        })?;
+        let mut timely = rpc.get_timely(sid);
+        Ok(SessionStats {
+            connected: rpc.is_connected(sid),
+            credits_in_use: kSessionCredits.saturating_sub(credits),
+            outstanding,
+            re_tx: rpc.get_num_re_tx(sid),
+            rtt_50_us: timely.get_rtt_perc(0.5),
+            rtt_99_us: timely.get_rtt_perc(0.99),
+            rate_gbps: timely.get_rate_gbps(),
+        })
+    }
+}
+
+/// A registry of per-session transport metrics.
+///
+/// Enable it with [`EnvBuilder::metrics`](crate::prelude::EnvBuilder::metrics),
+/// every channel of the environment then samples its sessions periodically.
+/// Use [`MetricsRegistry::render`] to export them in the Prometheus text format.
+///
+/// Only client sessions are sampled, a server's sessions belong to its
+/// clients, which count their retransmissions and RTTs.
+#[derive(Clone)]
+pub struct MetricsRegistry {
+    sessions: Arc<Mutex<BTreeMap<(u8, i32), SessionMetrics>>>,
+    sample_interval_ms: usize,
+}
+
+impl Default for MetricsRegistry {
+    fn default() -> Self {
+        MetricsRegistry::new(DEFAULT_SAMPLE_INTERVAL_MS)
+    }
+}
+
+impl MetricsRegistry {
+    /// Initialize a new [`MetricsRegistry`] whose sessions are sampled every
+    /// `sample_interval_ms`.
+    pub fn new(sample_interval_ms: usize) -> Self {
+        MetricsRegistry {
+            sessions: Arc::new(Mutex::new(BTreeMap::new())),
+            sample_interval_ms,
+        }
+    }
+
+    #[inline]
+    pub fn sample_interval_ms(&self) -> usize {
+        self.sample_interval_ms
+    }
+
+    /// Returns the latest samples keyed by poll thread id and session number.
+    pub fn sessions(&self) -> BTreeMap<(u8, i32), SessionMetrics> {
+        self.sessions.lock().unwrap().clone()
+    }
+
+    /// Render all sessions in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let sessions = self.sessions.lock().unwrap();
+        let mut out = String::new();
+        for (name, ty, help, value) in FAMILIES {
+            let _ = writeln!(out, "# HELP {name} {help}");
+            let _ = writeln!(out, "# TYPE {name} {ty}");
+            for ((thread_id, session), m) in sessions.iter() {
+                let _ = writeln!(
+                    out,
+                    "{name}{{thread=\"{thread_id}\",session=\"{session}\",remote=\"{}\"}} {}",
+                    escape_label(&m.remote_uri),
+                    value(m)
+                );
+            }
+        }
+        out
+    }
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Samples the sessions of one channel, owned by its poll thread.
+pub(crate) struct SessionSampler {
+    registry: MetricsRegistry,
+    thread_id: u8,
+    remote_uri: String,
+    interval_tsc: usize,
+    last_tsc: usize,
+    /// The retransmission counter last read from each session, and the
+    /// running total, which survives `Rpc::reset_num_re_tx`.
+    re_tx: HashMap<c_int, (usize, usize)>,
+}
+
+impl SessionSampler {
+    pub(crate) fn new(
+        registry: MetricsRegistry,
+        thread_id: u8,
+        remote_uri: String,
+        freq_ghz: f64,
+    ) -> Self {
+        let interval_tsc = ms_to_cycles(registry.sample_interval_ms as f64, freq_ghz);
+        SessionSampler {
+            registry,
+            thread_id,
+            remote_uri,
+            interval_tsc,
+            last_tsc: 0,
+            re_tx: HashMap::new(),
+        }
+    }
+
+    /// Add the retransmissions of `sid` since the last read to its total, and
+    /// return the total.
+    fn fold_re_tx(&mut self, rpc: &Rpc, sid: c_int) -> usize {
+        let count = rpc.get_num_re_tx(sid);
+        let (last, total) = self.re_tx.entry(sid).or_default();
+        // A counter below the last read was reset behind our back.
+        *total += count.checked_sub(*last).unwrap_or(count);
+        *last = count;
+        *total
+    }
+
+    /// Reset the retransmission counter of `sid`, keeping its total.
+    #[cfg(feature = "bench_stat")]
+    pub(crate) fn reset_num_re_tx(&mut self, rpc: &mut Rpc, sid: c_int) {
+        self.fold_re_tx(rpc, sid);
+        rpc.reset_num_re_tx(sid);
+        if let Some((last, _)) = self.re_tx.get_mut(&sid) {
+            *last = 0;
+        }
+    }
+
+    /// Sample `sessions` if the interval has elapsed since the last sample.
+    pub(crate) fn poll(&mut self, rpc: &mut Rpc, sessions: &[c_int]) {
+        let now = rdtsc();
+        if now - self.last_tsc < self.interval_tsc {
+            return;
+        }
+        self.last_tsc = now;
+        let samples: Vec<_> = sessions
+            .iter()
+            .filter_map(|&sid| {
+                let s = SessionStats::sample(rpc, sid, 0).ok()?;
+                let m = SessionMetrics {
+                    remote_uri: self.remote_uri.clone(),
+                    re_tx: self.fold_re_tx(rpc, sid),
+                    rtt_50_us: s.rtt_50_us,
+                    rtt_99_us: s.rtt_99_us,
+                    rate_gbps: s.rate_gbps,
+                    connected: s.connected,
+                };
+                Some(((self.thread_id, i32::from(sid)), m))
+            })
+            .collect();
+        self.registry.sessions.lock().unwrap().extend(samples);
+    }
+
+    /// Drop the samples of `sessions`, e.g. when they're destroyed.
+    pub(crate) fn clear(&mut self, sessions: &[c_int]) {
+        let mut registry = self.registry.sessions.lock().unwrap();
+        for &sid in sessions {
+            registry.remove(&(self.thread_id, i32::from(sid)));
+            self.re_tx.remove(&sid);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let registry = MetricsRegistry::default();
+        let families = concat!(
+            "# HELP erpc_session_retransmissions_total Packets retransmitted on the session.\n",
+            "# TYPE erpc_session_retransmissions_total counter\n",
+            "# HELP erpc_session_rtt_p50_microseconds Median packet RTT of the session.\n",
+            "# TYPE erpc_session_rtt_p50_microseconds gauge\n",
+            "# HELP erpc_session_rtt_p99_microseconds 99th percentile packet RTT of the session.\n",
+            "# TYPE erpc_session_rtt_p99_microseconds gauge\n",
+            "# HELP erpc_session_timely_rate_gbps Sending rate chosen by Timely for the session.\n",
+            "# TYPE erpc_session_timely_rate_gbps gauge\n",
+            "# HELP erpc_session_connected Whether the session is connected.\n",
+            "# TYPE erpc_session_connected gauge\n",
+        );
+        assert_eq!(registry.render(), families);
+
+        registry.sessions.lock().unwrap().extend([
+            (
+                (1, 3),
+                SessionMetrics {
+                    remote_uri: "a\"b\\c\nd".into(),
+                    re_tx: 7,
+                    rtt_50_us: 12.5,
+                    rtt_99_us: 40.0,
+                    rate_gbps: 56.25,
+                    connected: true,
+                },
+            ),
+            (
+                (0, 0),
+                SessionMetrics {
+                    remote_uri: "localhost:31850".into(),
+                    ..Default::default()
+                },
+            ),
+        ]);
+        // Sessions are sorted by thread and session number.
+        let remote = r#"remote="a\"b\\c\nd""#;
+        let expected = format!(
+            "# HELP erpc_session_retransmissions_total Packets retransmitted on the session.\n\
+             # TYPE erpc_session_retransmissions_total counter\n\
+             erpc_session_retransmissions_total{{thread=\"0\",session=\"0\",remote=\"localhost:31850\"}} 0\n\
+             erpc_session_retransmissions_total{{thread=\"1\",session=\"3\",{remote}}} 7\n\
+             # HELP erpc_session_rtt_p50_microseconds Median packet RTT of the session.\n\
+             # TYPE erpc_session_rtt_p50_microseconds gauge\n\
+             erpc_session_rtt_p50_microseconds{{thread=\"0\",session=\"0\",remote=\"localhost:31850\"}} 0\n\
+             erpc_session_rtt_p50_microseconds{{thread=\"1\",session=\"3\",{remote}}} 12.5\n\
+             # HELP erpc_session_rtt_p99_microseconds 99th percentile packet RTT of the session.\n\
+             # TYPE erpc_session_rtt_p99_microseconds gauge\n\
+             erpc_session_rtt_p99_microseconds{{thread=\"0\",session=\"0\",remote=\"localhost:31850\"}} 0\n\
+             erpc_session_rtt_p99_microseconds{{thread=\"1\",session=\"3\",{remote}}} 40\n\
+             # HELP erpc_session_timely_rate_gbps Sending rate chosen by Timely for the session.\n\
+             # TYPE erpc_session_timely_rate_gbps gauge\n\
+             erpc_session_timely_rate_gbps{{thread=\"0\",session=\"0\",remote=\"localhost:31850\"}} 0\n\
+             erpc_session_timely_rate_gbps{{thread=\"1\",session=\"3\",{remote}}} 56.25\n\
+             # HELP erpc_session_connected Whether the session is connected.\n\
+             # TYPE erpc_session_connected gauge\n\
+             erpc_session_connected{{thread=\"0\",session=\"0\",remote=\"localhost:31850\"}} 0\n\
+             erpc_session_connected{{thread=\"1\",session=\"3\",{remote}}} 1\n"
+        );
+        assert_eq!(registry.render(), expected);
+    }
+
+    #[test]
+    fn test_escape_label() {
+        assert_eq!(escape_label("localhost:31850"), "localhost:31850");
+        assert_eq!(escape_label("\"\\\n"), r#"\"\\\n"#);
+        // Only these three are escaped in the text format.
+        assert_eq!(escape_label("\t\u{1}é"), "\t\u{1}é");
+    }
+}
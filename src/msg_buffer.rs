@@ -1,7 +1,18 @@
 // Copyright (c) 2023, IOMesh Inc. All rights reserved.
 
-use std::pin::Pin;
+use std::{
+    cell::UnsafeCell,
+    io::{self, Write},
+    ops::{Deref, DerefMut},
+    pin::Pin,
+    ptr, slice,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
 
+use bytes::buf::{BufMut, UninitSlice};
 use erpc_sys::{erpc, UniquePtr};
 
 pub struct MsgBuffer {
@@ -12,6 +23,11 @@ unsafe impl Send for MsgBuffer {}
 unsafe impl Sync for MsgBuffer {}
 
 impl MsgBuffer {
+    #[inline]
+    pub(crate) fn from_inner(inner: UniquePtr<erpc::MsgBuffer>) -> Self {
+        MsgBuffer { inner }
+    }
+
     #[inline]
     pub fn get_inner_buf(&self) -> *mut u8 {
         self.as_inner().get_inner_buf()
@@ -34,6 +50,40 @@ impl MsgBuffer {
         }
     }
 
+    /// Shrink the data region to zero bytes so that it can be refilled through
+    /// [`Write`] or [`BufMut`].
+    #[inline]
+    pub fn clear(&mut self) {
+        self.resize(0);
+    }
+
+    /// Returns the number of bytes that can still be appended before reaching
+    /// `get_max_data_size`.
+    #[inline]
+    pub fn remaining_capacity(&self) -> usize {
+        self.get_max_data_size() - self.get_data_size()
+    }
+
+    /// Returns the data region, i.e. the first `get_data_size` bytes.
+    #[inline]
+    pub fn as_slice(&self) -> &[u8] {
+        let start = self.get_inner_buf();
+        if start.is_null() {
+            return &[];
+        }
+        unsafe { slice::from_raw_parts(start, self.get_data_size()) }
+    }
+
+    /// Returns the data region, i.e. the first `get_data_size` bytes.
+    #[inline]
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        let start = self.get_inner_buf();
+        if start.is_null() {
+            return &mut [];
+        }
+        unsafe { slice::from_raw_parts_mut(start, self.get_data_size()) }
+    }
+
     #[inline]
     pub fn as_inner_mut(&mut self) -> Pin<&mut erpc::MsgBuffer> {
         self.inner.pin_mut()
@@ -43,4 +93,132 @@ impl MsgBuffer {
     pub fn as_inner(&self) -> &erpc::MsgBuffer {
         &self.inner
     }
+
+    /// The underlying C++ object, e.g. to hand it to eRPC.
+    #[inline]
+    pub(crate) fn as_ptr(&self) -> *mut erpc::MsgBuffer {
+        self.inner
+            .as_ref()
+            .map_or(ptr::null_mut(), |r| r as *const _ as *mut _)
+    }
+}
+
+/// A [`MsgBuffer`] shared by several tasks, e.g. one that's reused by
+/// several calls. Its data is only reachable through a [`MsgBufferLease`],
+/// which one holder has at a time.
+#[derive(Clone)]
+pub struct SharedMsgBuffer(Arc<Shared>);
+
+struct Shared {
+    buf: UnsafeCell<MsgBuffer>,
+    leased: AtomicBool,
+}
+
+// `buf` is only reached by the holder of the lease.
+unsafe impl Sync for Shared {}
+
+impl SharedMsgBuffer {
+    pub fn new(buf: MsgBuffer) -> Self {
+        SharedMsgBuffer(Arc::new(Shared {
+            buf: UnsafeCell::new(buf),
+            leased: AtomicBool::new(false),
+        }))
+    }
+
+    /// Get exclusive access to the buffer. Returns `None` if it's already
+    /// leased, e.g. by a call in flight.
+    pub fn try_lease(&self) -> Option<MsgBufferLease> {
+        self.0
+            .leased
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .ok()?;
+        Some(MsgBufferLease(self.0.clone()))
+    }
+
+    #[inline]
+    pub fn is_leased(&self) -> bool {
+        self.0.leased.load(Ordering::Acquire)
+    }
+}
+
+impl From<MsgBuffer> for SharedMsgBuffer {
+    #[inline]
+    fn from(buf: MsgBuffer) -> Self {
+        SharedMsgBuffer::new(buf)
+    }
+}
+
+/// Exclusive access to a [`SharedMsgBuffer`], released on drop.
+pub struct MsgBufferLease(Arc<Shared>);
+
+impl Deref for MsgBufferLease {
+    type Target = MsgBuffer;
+
+    #[inline]
+    fn deref(&self) -> &MsgBuffer {
+        unsafe { &*self.0.buf.get() }
+    }
+}
+
+impl DerefMut for MsgBufferLease {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut MsgBuffer {
+        unsafe { &mut *self.0.buf.get() }
+    }
+}
+
+impl Drop for MsgBufferLease {
+    fn drop(&mut self) {
+        self.0.leased.store(false, Ordering::Release);
+    }
+}
+
+impl Write for MsgBuffer {
+    /// Appends to the data region, growing `data_size` up to `get_max_data_size`.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let amt = buf.len().min(self.remaining_capacity());
+        if amt == 0 {
+            return Ok(0);
+        }
+        let offset = self.get_data_size();
+        self.resize(offset + amt);
+        self.as_mut_slice()[offset..].copy_from_slice(&buf[..amt]);
+        Ok(amt)
+    }
+
+    #[inline]
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+unsafe impl BufMut for MsgBuffer {
+    #[inline]
+    fn remaining_mut(&self) -> usize {
+        self.remaining_capacity()
+    }
+
+    #[inline]
+    unsafe fn advance_mut(&mut self, cnt: usize) {
+        let remaining = self.remaining_capacity();
+        assert!(
+            cnt <= remaining,
+            "cannot advance past `remaining_mut`: {cnt:?} <= {remaining:?}"
+        );
+        self.resize(self.get_data_size() + cnt);
+    }
+
+    #[inline]
+    fn chunk_mut(&mut self) -> &mut UninitSlice {
+        let start = self.get_inner_buf();
+        if start.is_null() {
+            return UninitSlice::new(&mut []);
+        }
+        unsafe {
+            UninitSlice::from_raw_parts_mut(
+                start.add(self.get_data_size()),
+                self.remaining_capacity(),
+            )
+        }
+    }
 }
@@ -1,18 +1,25 @@
 // Copyright (c) 2023, IOMesh Inc. All rights reserved.
 
-use std::pin::Pin;
+use std::{collections::HashSet, pin::Pin, sync::Mutex};
 
-use crate::error::{Error, Result};
+use crate::error::{Error, ErrorCode, Result};
 use erpc_sys::{
     c_void,
     erpc::{self, ReqHandle as RawReqHandle},
-    UniquePtr, WithinUniquePtr, EEXIST, EINVAL, EPERM,
+    UniquePtr, WithinUniquePtr, EEXIST, EPERM,
 };
 
 pub type ReqHandler = extern "C" fn(*mut RawReqHandle, *mut c_void);
 
+/// A per-process eRPC [`Nexus`], shared by every poll thread.
+///
+/// eRPC synchronizes `Rpc` creation internally, handler registration is
+/// serialized by `registered`, so the shared reference is all callers need.
 pub struct Nexus {
-    inner: UniquePtr<erpc::Nexus>,
+    inner: *mut erpc::Nexus,
+    /// The request types with a handler.
+    registered: Mutex<HashSet<u8>>,
+    num_bg_threads: usize,
 }
 
 unsafe impl Send for Nexus {}
@@ -21,38 +28,157 @@ unsafe impl Sync for Nexus {}
 impl Nexus {
     #[inline]
     pub fn new(local_uri: &str, numa_node: usize) -> Self {
+        Nexus::with_bg_threads(local_uri, numa_node, 0)
+    }
+
+    /// Create a nexus with `num_bg_threads` eRPC background threads, which run
+    /// the handlers registered by [`Nexus::register_bg_req_func`].
+    #[inline]
+    pub fn with_bg_threads(local_uri: &str, numa_node: usize, num_bg_threads: usize) -> Self {
         Nexus {
-            inner: erpc::Nexus::new(local_uri, numa_node, 0).within_unique_ptr(),
+            inner: erpc::Nexus::new(local_uri, numa_node, num_bg_threads)
+                .within_unique_ptr()
+                .into_raw(),
+            registered: Mutex::new(HashSet::new()),
+            num_bg_threads,
         }
     }
 
+    /// Register `req_func` to run on the poll thread of the `Rpc`.
+    #[inline]
+    pub fn register_req_func(&self, req_type: u8, req_func: ReqHandler) -> Result<()> {
+        self.register(req_type, req_func, erpc::ReqFuncType::kForeground)
+    }
+
+    /// Register `req_func` to run on a background thread. Fails with `EPERM`
+    /// if the nexus has no background threads.
     #[inline]
-    pub fn register_req_func(&mut self, req_type: u8, req_func: ReqHandler) -> Result<()> {
+    pub fn register_bg_req_func(&self, req_type: u8, req_func: ReqHandler) -> Result<()> {
+        self.register(req_type, req_func, erpc::ReqFuncType::kBackground)
+    }
+
+    /// Register each of `req_funcs` with [`Nexus::register_bg_req_func`] if
+    /// its flag is set, else with [`Nexus::register_req_func`].
+    ///
+    /// The request types are checked first, so that none is registered if
+    /// one is repeated or already has a handler, or if the nexus has no
+    /// background threads for them. eRPC can't unregister handlers.
+    pub fn register_req_funcs(&self, req_funcs: &[(u8, ReqHandler, bool)]) -> Result<()> {
+        let mut registered = self.registered.lock().unwrap();
+        let mut seen = HashSet::new();
+        for &(req_type, _, bg) in req_funcs {
+            if registered.contains(&req_type) || !seen.insert(req_type) {
+                return Err(Error::RegisterHandler(req_type, ErrorCode::Errno(EEXIST)));
+            }
+            if bg && self.num_bg_threads == 0 {
+                return Err(Error::RegisterHandler(req_type, ErrorCode::Errno(EPERM)));
+            }
+        }
+        for &(req_type, req_func, bg) in req_funcs {
+            let req_func_type = if bg {
+                erpc::ReqFuncType::kBackground
+            } else {
+                erpc::ReqFuncType::kForeground
+            };
+            self.register_locked(&mut registered, req_type, req_func, req_func_type)?;
+        }
+        Ok(())
+    }
+
+    fn register(
+        &self,
+        req_type: u8,
+        req_func: ReqHandler,
+        req_func_type: erpc::ReqFuncType,
+    ) -> Result<()> {
+        let mut registered = self.registered.lock().unwrap();
+        self.register_locked(&mut registered, req_type, req_func, req_func_type)
+    }
+
+    fn register_locked(
+        &self,
+        registered: &mut HashSet<u8>,
+        req_type: u8,
+        req_func: ReqHandler,
+        req_func_type: erpc::ReqFuncType,
+    ) -> Result<()> {
+        // Safe to mutate through `&self` while holding `registered`.
         let res = 0 - i32::from(unsafe {
-            self.as_inner_mut().register_req_func(
+            Pin::new_unchecked(&mut *self.inner).register_req_func(
                 req_type,
                 req_func as *mut c_void,
-                erpc::ReqFuncType::kForeground,
+                req_func_type,
             )
         });
         match res as u32 {
-            0 => Ok(()),
-            EPERM => Err(Error::Internal("registration not permitted".into())),
-            EEXIST => Err(Error::Internal(format!(
-                "handler for {req_type} already exists"
-            ))),
-            EINVAL => Err(Error::Internal("invalid handler".into())),
-            _ => unreachable!(),
+            0 => {
+                registered.insert(req_type);
+                Ok(())
+            }
+            // EPERM: registration not permitted, EEXIST: a handler already
+            // exists, EINVAL: invalid handler.
+            code => Err(Error::RegisterHandler(req_type, ErrorCode::Errno(code))),
         }
     }
 
     #[inline]
     pub fn as_inner_mut(&mut self) -> Pin<&mut erpc::Nexus> {
-        self.inner.pin_mut()
+        unsafe { Pin::new_unchecked(&mut *self.inner) }
     }
 
     #[inline]
     pub fn as_inner(&self) -> &erpc::Nexus {
-        &self.inner
+        unsafe { &*self.inner }
+    }
+
+    #[inline]
+    pub(crate) fn as_ptr(&self) -> *mut erpc::Nexus {
+        self.inner
+    }
+}
+
+impl Drop for Nexus {
+    fn drop(&mut self) {
+        drop(unsafe { UniquePtr::from_raw(self.inner) });
+    }
+}
+
+#[cfg(all(test, feature = "loopback"))]
+mod tests {
+    use super::*;
+
+    extern "C" fn req_func(_req: *mut RawReqHandle, _ctx: *mut c_void) {}
+
+    fn errno(res: Result<()>) -> Option<(u8, u32)> {
+        match res {
+            Err(Error::RegisterHandler(req_type, ErrorCode::Errno(code))) => Some((req_type, code)),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_register_req_funcs() {
+        let nexus = Nexus::new("nexus:0", 0);
+        // Nothing is registered when one of the handlers can't be.
+        let res = nexus.register_req_funcs(&[(1, req_func, false), (2, req_func, true)]);
+        assert_eq!(errno(res), Some((2, EPERM)));
+        let res = nexus.register_req_funcs(&[(3, req_func, false), (3, req_func, false)]);
+        assert_eq!(errno(res), Some((3, EEXIST)));
+
+        nexus
+            .register_req_funcs(&[(1, req_func, false), (3, req_func, false)])
+            .unwrap();
+        let res = nexus.register_req_funcs(&[(2, req_func, false), (1, req_func, false)]);
+        assert_eq!(errno(res), Some((1, EEXIST)));
+        assert_eq!(
+            errno(nexus.register_req_func(3, req_func)),
+            Some((3, EEXIST))
+        );
+        nexus.register_req_func(2, req_func).unwrap();
+
+        let nexus = Nexus::with_bg_threads("nexus:1", 0, 1);
+        nexus
+            .register_req_funcs(&[(1, req_func, false), (2, req_func, true)])
+            .unwrap();
     }
 }
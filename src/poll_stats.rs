@@ -0,0 +1,203 @@
+// Copyright (c) 2023, IOMesh Inc. All rights reserved.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Event-loop counters of one poll thread.
+///
+/// Only the poll thread writes them, so they're bumped with plain loads and
+/// stores instead of read-modify-write operations.
+#[derive(Default)]
+pub(crate) struct PollCounters {
+    iterations: AtomicU64,
+    busy_iterations: AtomicU64,
+    calls_drained: AtomicU64,
+    continuations: AtomicU64,
+    continuation_ns: AtomicU64,
+    handlers: AtomicU64,
+    handler_ns: AtomicU64,
+}
+
+#[inline]
+fn add(counter: &AtomicU64, n: u64) {
+    counter.store(counter.load(Ordering::Relaxed) + n, Ordering::Relaxed);
+}
+
+impl PollCounters {
+    /// Returns the number of continuations and handlers run so far, to tell
+    /// whether an event loop iteration did any work.
+    #[inline]
+    pub(crate) fn events(&self) -> u64 {
+        self.continuations.load(Ordering::Relaxed) + self.handlers.load(Ordering::Relaxed)
+    }
+
+    /// Account one iteration of the poll loop.
+    #[inline]
+    pub(crate) fn record_iteration(&self, events_before: u64, calls_drained: u64) {
+        add(&self.iterations, 1);
+        add(&self.calls_drained, calls_drained);
+        if calls_drained > 0 || self.events() != events_before {
+            add(&self.busy_iterations, 1);
+        }
+    }
+
+    #[inline]
+    pub(crate) fn record_continuation(&self, ns: u64) {
+        add(&self.continuations, 1);
+        add(&self.continuation_ns, ns);
+    }
+
+    #[inline]
+    pub(crate) fn record_handler(&self, ns: u64) {
+        add(&self.handlers, 1);
+        add(&self.handler_ns, ns);
+    }
+
+    pub(crate) fn snapshot(&self) -> PollThreadStats {
+        PollThreadStats {
+            iterations: self.iterations.load(Ordering::Relaxed),
+            busy_iterations: self.busy_iterations.load(Ordering::Relaxed),
+            calls_drained: self.calls_drained.load(Ordering::Relaxed),
+            continuations: self.continuations.load(Ordering::Relaxed),
+            continuation_ns: self.continuation_ns.load(Ordering::Relaxed),
+            handlers: self.handlers.load(Ordering::Relaxed),
+            handler_ns: self.handler_ns.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Event-loop statistics of one poll thread since it was started.
+///
+/// Get them with [`Environment::poll_stats`](crate::prelude::Environment::poll_stats).
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PollThreadStats {
+    /// Iterations of the poll loop, each runs the eRPC event loop once and
+    /// drains the call queue.
+    pub iterations: u64,
+    /// Iterations which drained a call or ran a continuation or handler.
+    pub busy_iterations: u64,
+    /// `RpcCall`s taken from the call queue.
+    pub calls_drained: u64,
+    /// Client continuations run.
+    pub continuations: u64,
+    /// Time spent in client continuations.
+    pub continuation_ns: u64,
+    /// Server request handlers run on the poll thread.
+    pub handlers: u64,
+    /// Time spent in server request handlers on the poll thread.
+    pub handler_ns: u64,
+}
+
+impl PollThreadStats {
+    /// The fraction of iterations doing useful work.
+    pub fn utilization(&self) -> f64 {
+        if self.iterations == 0 {
+            0.0
+        } else {
+            self.busy_iterations as f64 / self.iterations as f64
+        }
+    }
+
+    /// The mean number of calls drained per iteration.
+    pub fn calls_per_iteration(&self) -> f64 {
+        if self.iterations == 0 {
+            0.0
+        } else {
+            self.calls_drained as f64 / self.iterations as f64
+        }
+    }
+
+    /// Returns the counters accumulated since `earlier`, e.g. to compute the
+    /// utilization over an interval.
+    pub fn since(&self, earlier: &PollThreadStats) -> PollThreadStats {
+        PollThreadStats {
+            iterations: self.iterations.saturating_sub(earlier.iterations),
+            busy_iterations: self.busy_iterations.saturating_sub(earlier.busy_iterations),
+            calls_drained: self.calls_drained.saturating_sub(earlier.calls_drained),
+            continuations: self.continuations.saturating_sub(earlier.continuations),
+            continuation_ns: self.continuation_ns.saturating_sub(earlier.continuation_ns),
+            handlers: self.handlers.saturating_sub(earlier.handlers),
+            handler_ns: self.handler_ns.saturating_sub(earlier.handler_ns),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counters() {
+        let counters = PollCounters::default();
+        let events = counters.events();
+        counters.record_iteration(events, 0);
+        let events = counters.events();
+        counters.record_continuation(100);
+        counters.record_iteration(events, 0);
+        let events = counters.events();
+        counters.record_handler(50);
+        counters.record_handler(70);
+        counters.record_iteration(events, 3);
+        let events = counters.events();
+        counters.record_iteration(events, 1);
+
+        assert_eq!(
+            counters.snapshot(),
+            PollThreadStats {
+                iterations: 4,
+                busy_iterations: 3,
+                calls_drained: 4,
+                continuations: 1,
+                continuation_ns: 100,
+                handlers: 2,
+                handler_ns: 120,
+            }
+        );
+    }
+
+    #[test]
+    fn test_interval() {
+        let earlier = PollThreadStats {
+            iterations: 100,
+            busy_iterations: 20,
+            calls_drained: 30,
+            continuations: 5,
+            continuation_ns: 500,
+            handlers: 7,
+            handler_ns: 700,
+        };
+        let later = PollThreadStats {
+            iterations: 300,
+            busy_iterations: 170,
+            calls_drained: 430,
+            continuations: 6,
+            continuation_ns: 800,
+            handlers: 7,
+            handler_ns: 700,
+        };
+        let interval = later.since(&earlier);
+        assert_eq!(
+            interval,
+            PollThreadStats {
+                iterations: 200,
+                busy_iterations: 150,
+                calls_drained: 400,
+                continuations: 1,
+                continuation_ns: 300,
+                handlers: 0,
+                handler_ns: 0,
+            }
+        );
+        assert_eq!(interval.utilization(), 0.75);
+        assert_eq!(interval.calls_per_iteration(), 2.0);
+        // Over the whole run.
+        assert_eq!(later.utilization(), 170.0 / 300.0);
+
+        // An interval without iterations.
+        let empty = later.since(&later);
+        assert_eq!(empty, PollThreadStats::default());
+        assert_eq!(empty.utilization(), 0.0);
+        assert_eq!(empty.calls_per_iteration(), 0.0);
+        // Counters never go backwards, a stale `earlier` saturates.
+        assert_eq!(earlier.since(&later), PollThreadStats::default());
+    }
+}
@@ -0,0 +1,319 @@
+// Copyright (c) 2023, IOMesh Inc. All rights reserved.
+
+use std::ops::{Deref, DerefMut};
+
+use async_channel::{bounded, unbounded, Receiver, Sender};
+
+use crate::{
+    call::{AllocReq, RpcCall},
+    error::{Error, Result},
+    msg_buffer::MsgBuffer,
+    rpc::Rpc,
+};
+
+/// The smallest size class is 64 bytes.
+const MIN_SIZE_CLASS_SHIFT: u32 = 6;
+const DEFAULT_MAX_CACHED_PER_CLASS: usize = 256;
+
+#[inline]
+fn size_class(size: usize) -> usize {
+    let shift = size
+        .max(1 << MIN_SIZE_CLASS_SHIFT)
+        .next_power_of_two()
+        .trailing_zeros();
+    (shift - MIN_SIZE_CLASS_SHIFT) as usize
+}
+
+#[inline]
+fn class_size(class: usize) -> usize {
+    1 << (class as u32 + MIN_SIZE_CLASS_SHIFT)
+}
+
+/// A size-class [`MsgBuffer`] cache owned by a poll thread.
+///
+/// Buffers are handed out as [`PooledMsgBuffer`]s, which can be moved to any
+/// thread and go back to the pool when dropped. Only the owning poll thread
+/// allocates from the pool, so the hugepage allocator (and its mutex) is only
+/// hit while the pool warms up.
+pub struct MsgBufferPool {
+    free: Vec<Vec<MsgBuffer>>,
+    max_cached_per_class: usize,
+    recycle_tx: Sender<MsgBuffer>,
+    recycle_rx: Receiver<MsgBuffer>,
+}
+
+impl Default for MsgBufferPool {
+    fn default() -> Self {
+        MsgBufferPool::new(DEFAULT_MAX_CACHED_PER_CLASS)
+    }
+}
+
+impl MsgBufferPool {
+    /// Initialize a new [`MsgBufferPool`] which keeps at most
+    /// `max_cached_per_class` idle buffers for each size class.
+    pub fn new(max_cached_per_class: usize) -> Self {
+        let (recycle_tx, recycle_rx) = unbounded();
+        MsgBufferPool {
+            free: Vec::new(),
+            max_cached_per_class,
+            recycle_tx,
+            recycle_rx,
+        }
+    }
+
+    /// Get a buffer whose data size is `data_size`.
+    ///
+    /// The buffer's max data size is rounded up to the next power of two.
+    /// Sizes whose class would exceed eRPC's max message size get an
+    /// exact-size buffer, which isn't cached. Sizes above the max message
+    /// size fail with [`Error::MsgTooLarge`].
+    pub fn alloc(&mut self, rpc: &mut Rpc, data_size: usize) -> Result<PooledMsgBuffer> {
+        let max_msg_size = rpc.get_max_msg_size();
+        if data_size > max_msg_size {
+            return Err(Error::MsgTooLarge(data_size, max_msg_size));
+        }
+        let class = size_class(data_size);
+        if class_size(class) > max_msg_size {
+            return Ok(PooledMsgBuffer {
+                buf: Some(rpc.alloc_msg_buffer_or_die(data_size)),
+                recycle_tx: self.recycle_tx.clone(),
+            });
+        }
+        let mut buf = match self.pop(class) {
+            Some(buf) => buf,
+            None => {
+                self.reclaim(rpc);
+                self.pop(class)
+                    .unwrap_or_else(|| rpc.alloc_msg_buffer_or_die(class_size(class)))
+            }
+        };
+        buf.resize(data_size);
+        Ok(PooledMsgBuffer {
+            buf: Some(buf),
+            recycle_tx: self.recycle_tx.clone(),
+        })
+    }
+
+    /// Move buffers dropped since the last call back into the free lists.
+    pub fn reclaim(&mut self, rpc: &mut Rpc) {
+        while let Ok(buf) = self.recycle_rx.try_recv() {
+            let class = size_class(buf.get_max_data_size());
+            // Exact-size buffers above the largest class aren't cached.
+            if class_size(class) != buf.get_max_data_size()
+                || class_size(class) > rpc.get_max_msg_size()
+            {
+                rpc.free_msg_buffer(&buf);
+                continue;
+            }
+            if self.free.len() <= class {
+                self.free.resize_with(class + 1, Vec::new);
+            }
+            let list = &mut self.free[class];
+            if list.len() < self.max_cached_per_class {
+                list.push(buf);
+            } else {
+                rpc.free_msg_buffer(&buf);
+            }
+        }
+    }
+
+    /// Returns the number of idle buffers held by the pool.
+    pub fn cached(&self) -> usize {
+        self.free.iter().map(Vec::len).sum()
+    }
+
+    /// Free every idle buffer. Must be called on the poll thread before `rpc`
+    /// is destroyed, buffers still in use at that point are leaked.
+    pub fn release(&mut self, rpc: &mut Rpc) {
+        self.reclaim(rpc);
+        for buf in self.free.drain(..).flatten() {
+            rpc.free_msg_buffer(&buf);
+        }
+    }
+
+    #[inline]
+    fn pop(&mut self, class: usize) -> Option<MsgBuffer> {
+        self.free.get_mut(class).and_then(Vec::pop)
+    }
+}
+
+/// A [`MsgBuffer`] borrowed from a [`MsgBufferPool`], returned to it on drop.
+pub struct PooledMsgBuffer {
+    buf: Option<MsgBuffer>,
+    recycle_tx: Sender<MsgBuffer>,
+}
+
+impl PooledMsgBuffer {
+    /// Detach the buffer from its pool. Use it when ownership is handed over
+    /// to eRPC, e.g. for dynamic responses, which eRPC frees by itself.
+    pub fn into_inner(mut self) -> MsgBuffer {
+        self.buf.take().unwrap()
+    }
+}
+
+impl Deref for PooledMsgBuffer {
+    type Target = MsgBuffer;
+
+    #[inline]
+    fn deref(&self) -> &MsgBuffer {
+        self.buf.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for PooledMsgBuffer {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut MsgBuffer {
+        self.buf.as_mut().unwrap()
+    }
+}
+
+impl Drop for PooledMsgBuffer {
+    fn drop(&mut self) {
+        if let Some(buf) = self.buf.take() {
+            // The pool is gone when its poll thread has exited, the buffer
+            // will be released along with the `Rpc`.
+            let _ = self.recycle_tx.try_send(buf);
+        }
+    }
+}
+
+/// A handle to allocate [`PooledMsgBuffer`]s from any thread or task.
+///
+/// Allocations are routed to the poll thread which owns the pool, so the
+/// `Rpc` is never touched outside of it.
+#[derive(Clone)]
+pub struct MsgBufferAllocator {
+    tx: Sender<RpcCall>,
+}
+
+impl MsgBufferAllocator {
+    /// Initialize a new [`MsgBufferAllocator`] from the sender of a poll
+    /// thread, e.g. the one passed to a request handler.
+    pub fn new(tx: Sender<RpcCall>) -> Self {
+        MsgBufferAllocator { tx }
+    }
+
+    /// Get a buffer whose data size is `data_size` from the poll thread's pool,
+    /// see [`MsgBufferPool::alloc`].
+    pub async fn alloc(&self, data_size: usize) -> Result<PooledMsgBuffer> {
+        let (tx, rx) = bounded::<Result<PooledMsgBuffer>>(1);
+        self.tx
+            .send(RpcCall::Alloc(AllocReq { data_size, tx }))
+            .await
+            .map_err(|_| Error::Channel("poll thread has exited".into()))?;
+        rx.recv().await?
+    }
+}
+
+#[cfg(all(test, feature = "loopback"))]
+mod tests {
+    use super::*;
+    use crate::nexus::Nexus;
+
+    /// Runs `f` with an `Rpc` of its own, then checks that the pool gave
+    /// every buffer back.
+    fn with_rpc<F: FnOnce(&mut Rpc, &mut MsgBufferPool)>(
+        uri: &str,
+        max_cached_per_class: usize,
+        f: F,
+    ) {
+        let nexus = Nexus::new(uri, 0);
+        let mut rpc = Rpc::new(&nexus, None, 0, None, 0);
+        let mut pool = MsgBufferPool::new(max_cached_per_class);
+        f(&mut rpc, &mut pool);
+        pool.release(&mut rpc);
+        assert_eq!(pool.cached(), 0);
+        assert_eq!(rpc.get_stat_user_alloc_tot(), 0);
+    }
+
+    #[test]
+    fn test_size_class() {
+        assert_eq!(size_class(0), 0);
+        assert_eq!(size_class(64), 0);
+        assert_eq!(size_class(65), 1);
+        assert_eq!(size_class(128), 1);
+        assert_eq!(size_class(129), 2);
+        assert_eq!(class_size(size_class(4096)), 4096);
+
+        with_rpc("pool:0", 1, |rpc, pool| {
+            for (size, max_data_size) in [(0, 64), (1, 64), (100, 128), (4096, 4096), (4097, 8192)]
+            {
+                let buf = pool.alloc(rpc, size).unwrap();
+                assert_eq!(buf.get_data_size(), size);
+                assert_eq!(buf.get_max_data_size(), max_data_size);
+            }
+        });
+    }
+
+    #[test]
+    fn test_reuse() {
+        with_rpc("pool:1", 1, |rpc, pool| {
+            let buf = pool.alloc(rpc, 100).unwrap();
+            let data = buf.get_inner_buf();
+            drop(buf);
+            // Dropped buffers wait for the poll thread to reclaim them.
+            assert_eq!(pool.cached(), 0);
+            pool.reclaim(rpc);
+            assert_eq!(pool.cached(), 1);
+
+            let buf = pool.alloc(rpc, 120).unwrap();
+            assert_eq!(buf.get_inner_buf(), data);
+            assert_eq!(buf.get_data_size(), 120);
+            assert_eq!(pool.cached(), 0);
+            drop(buf);
+
+            // Allocating reclaims by itself when the free list is empty.
+            let buf = pool.alloc(rpc, 128).unwrap();
+            assert_eq!(buf.get_inner_buf(), data);
+            // Other classes don't share buffers.
+            let other = pool.alloc(rpc, 64).unwrap();
+            assert_ne!(other.get_inner_buf(), data);
+            assert_eq!(rpc.get_stat_user_alloc_tot(), 128 + 64);
+        });
+    }
+
+    #[test]
+    fn test_max_cached_per_class() {
+        with_rpc("pool:2", 2, |rpc, pool| {
+            let bufs = (0..3)
+                .map(|_| pool.alloc(rpc, 64).unwrap())
+                .collect::<Vec<_>>();
+            let big = pool.alloc(rpc, 1024).unwrap();
+            assert_eq!(rpc.get_stat_user_alloc_tot(), 3 * 64 + 1024);
+            drop(bufs);
+            drop(big);
+            pool.reclaim(rpc);
+            // The third buffer of the class is freed, the cap is per class.
+            assert_eq!(pool.cached(), 3);
+            assert_eq!(rpc.get_stat_user_alloc_tot(), 2 * 64 + 1024);
+        });
+    }
+
+    #[test]
+    fn test_oversize() {
+        with_rpc("pool:3", 1, |rpc, pool| {
+            let max = rpc.get_max_msg_size();
+            match pool.alloc(rpc, max + 1) {
+                Err(Error::MsgTooLarge(size, max_msg_size)) => {
+                    assert_eq!((size, max_msg_size), (max + 1, max))
+                }
+                Err(e) => panic!("unexpected error: {e}"),
+                Ok(_) => panic!("allocated {} bytes", max + 1),
+            }
+            assert_eq!(rpc.get_stat_user_alloc_tot(), 0);
+
+            let buf = pool.alloc(rpc, max).unwrap();
+            assert_eq!(buf.get_max_data_size(), max);
+        });
+    }
+
+    #[test]
+    fn test_detached() {
+        with_rpc("pool:4", 1, |rpc, pool| {
+            let buf = pool.alloc(rpc, 64).unwrap().into_inner();
+            pool.reclaim(rpc);
+            assert_eq!(pool.cached(), 0);
+            rpc.free_msg_buffer(&buf);
+        });
+    }
+}
@@ -1,12 +1,23 @@
 // Copyright (c) 2023, IOMesh Inc. All rights reserved.
 
-use std::pin::Pin;
+use std::{
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
 
 use crate::msg_buffer::MsgBuffer;
 use erpc_sys::{erpc::MsgBuffer as RawMsgBuffer, erpc::ReqHandle as RawReqHandle, WithinUniquePtr};
 
 pub struct ReqHandle {
     inner: *mut RawReqHandle,
+    /// The id the server's method statistics track the request by, 0 if
+    /// they're disabled.
+    id: u64,
+    /// Set once a response is handed over, if tracked.
+    responded: Option<Arc<AtomicBool>>,
 }
 
 unsafe impl Send for ReqHandle {}
@@ -15,7 +26,11 @@ unsafe impl Sync for ReqHandle {}
 impl ReqHandle {
     #[inline]
     pub fn from_inner_raw(raw: *mut RawReqHandle) -> Self {
-        ReqHandle { inner: raw }
+        ReqHandle {
+            inner: raw,
+            id: 0,
+            responded: None,
+        }
     }
 
     #[inline]
@@ -25,34 +40,59 @@ impl ReqHandle {
 
     #[inline]
     pub fn get_pre_resp_msgbuf(&mut self) -> MsgBuffer {
-        MsgBuffer {
-            inner: self
-                .as_inner_mut()
+        MsgBuffer::from_inner(
+            self.as_inner_mut()
                 .get_pre_resp_msgbuf()
                 .within_unique_ptr(),
-        }
+        )
     }
 
     #[inline]
     pub fn init_dyn_resp_msgbuf_from_allocated(&mut self, msgbuf: &mut MsgBuffer) -> MsgBuffer {
-        MsgBuffer {
-            inner: self
-                .as_inner_mut()
+        MsgBuffer::from_inner(
+            self.as_inner_mut()
                 .init_dyn_resp_msgbuf_from_allocated(msgbuf.as_inner_mut())
                 .within_unique_ptr(),
-        }
+        )
     }
 
     #[inline]
     pub fn get_dyn_resp_msgbuf(&mut self) -> MsgBuffer {
-        MsgBuffer {
-            inner: self
-                .as_inner_mut()
+        MsgBuffer::from_inner(
+            self.as_inner_mut()
                 .get_dyn_resp_msgbuf()
                 .within_unique_ptr(),
+        )
+    }
+
+    #[inline]
+    pub(crate) fn id(&self) -> u64 {
+        self.id
+    }
+
+    #[inline]
+    pub(crate) fn set_id(&mut self, id: u64) {
+        self.id = id;
+    }
+
+    /// Track whether a response is handed over for the request, i.e. whether
+    /// it went into a [`CallTag`](crate::prelude::CallTag).
+    pub(crate) fn track_response(&mut self) -> Arc<AtomicBool> {
+        self.responded.get_or_insert_with(Default::default).clone()
+    }
+
+    #[inline]
+    pub(crate) fn set_responded(&self) {
+        if let Some(responded) = self.responded.as_ref() {
+            responded.store(true, Ordering::Release);
         }
     }
 
+    #[inline]
+    pub(crate) fn as_ptr(&self) -> *mut RawReqHandle {
+        self.inner
+    }
+
     #[inline]
     pub fn as_inner_mut(&mut self) -> Pin<&mut RawReqHandle> {
         unsafe { Pin::new_unchecked(&mut *self.inner) }
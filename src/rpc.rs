@@ -5,11 +5,11 @@ use std::{pin::Pin, ptr};
 use erpc_sys::{
     c_int, c_void,
     erpc::{self, kInvalidBgETid, SmErrType, SmEventType},
-    UniquePtr, WithinUniquePtr, EALREADY, EBUSY, EINVAL, ENOMEM, EPERM,
+    erpc_wrapper, UniquePtr, WithinUniquePtr, EINVAL, ENOMEM,
 };
 
 use crate::{
-    error::{Error, Result},
+    error::{Error, ErrorCode, Result},
     msg_buffer::MsgBuffer,
     nexus::Nexus,
     req_handle::ReqHandle,
@@ -20,18 +20,29 @@ use crate::{
 pub type SmHandler = extern "C" fn(c_int, SmEventType, SmErrType, *mut c_void);
 pub type ContFunc = extern "C" fn(*mut c_void, *mut c_void);
 
+/// The physical port an [`Rpc`] runs on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LinkInfo {
+    pub phy_port: u8,
+    /// Bytes per second.
+    pub bandwidth: usize,
+    pub mtu: usize,
+    /// The message data carried by one packet.
+    pub max_data_per_pkt: usize,
+}
+
 pub struct Rpc {
     inner: UniquePtr<erpc::Rpc>,
+    phy_port: u8,
 }
 
-/// Notice: Only MsgBuffer related ops are thread safe
+/// Notice: An `Rpc` must only be used by the thread which created it
 unsafe impl Send for Rpc {}
-unsafe impl Sync for Rpc {}
 
 impl Rpc {
     #[inline]
     pub fn new(
-        nexus: &mut Nexus,
+        nexus: &Nexus,
         context: Option<*mut c_void>,
         rpc_id: u8,
         sm_handler: Option<SmHandler>,
@@ -40,7 +51,7 @@ impl Rpc {
         Rpc {
             inner: unsafe {
                 erpc::Rpc::new(
-                    nexus.as_inner_mut().get_unchecked_mut(),
+                    nexus.as_ptr(),
                     context.unwrap_or(ptr::null_mut()),
                     rpc_id,
                     match sm_handler {
@@ -51,6 +62,7 @@ impl Rpc {
                 )
             }
             .within_unique_ptr(),
+            phy_port,
         }
     }
 
@@ -60,24 +72,23 @@ impl Rpc {
         if i32::from(sid) >= 0 {
             return Ok(sid);
         }
-        match (0 - i32::from(sid)) as u32 {
-            EPERM => Err(Error::Internal("can't create session from a thread other than the one that created this Rpc object".into())),
-            EINVAL => Err(Error::Internal("invalid remote hostname or remote Rpc is same as local".into())),
-            ENOMEM => Err(Error::Internal("ring buffers exhausted".into())),
-            _ => unreachable!(),
+        let code = ErrorCode::Errno((0 - i32::from(sid)) as u32);
+        match code {
+            ErrorCode::Errno(EINVAL) => Err(Error::InvalidUri(code)),
+            ErrorCode::Errno(ENOMEM) => Err(Error::OutOfMemory(code)),
+            // EPERM: not called from the thread that created this Rpc.
+            _ => Err(Error::SessionCreate(code)),
         }
     }
 
+    /// Destroy the session. On `EBUSY` the session has pending requests and
+    /// the call may be retried.
     #[inline]
     pub fn destroy_session(&mut self, session_num: c_int) -> Result<()> {
         let res = self.as_inner_mut().destroy_session(session_num);
         match (0 - i32::from(res)) as u32 {
             0 => Ok(()),
-            EPERM => Err(Error::Internal("can't destroy session from a thread other than the one that created this Rpc object or session already destroyed or session connection in progress.".into())),
-            EINVAL => Err(Error::Internal("invalid session number or it's a server session.".into())),
-            EBUSY => Err(Error::Internal("session has pending RPC requests.".into())),
-            EALREADY => Err(Error::Internal("session disconnection in progress.".into())),
-            _ => unreachable!(),
+            code => Err(Error::SessionDestroy(ErrorCode::Errno(code))),
         }
     }
 
@@ -111,24 +122,22 @@ impl Rpc {
 
     #[inline]
     pub fn alloc_msg_buffer_or_die(&mut self, max_data_size: usize) -> MsgBuffer {
-        MsgBuffer {
-            inner: self
-                .inner
+        MsgBuffer::from_inner(
+            self.inner
                 .pin_mut()
                 .alloc_msg_buffer_or_die(max_data_size)
                 .within_unique_ptr(),
-        }
+        )
     }
 
     #[inline]
     pub fn alloc_msg_buffer(&mut self, max_data_size: usize) -> MsgBuffer {
-        MsgBuffer {
-            inner: self
-                .inner
+        MsgBuffer::from_inner(
+            self.inner
                 .pin_mut()
                 .alloc_msg_buffer_or_die(max_data_size)
                 .within_unique_ptr(),
-        }
+        )
     }
 
     #[inline]
@@ -147,18 +156,41 @@ impl Rpc {
         tag: Option<*mut c_void>,
     ) {
         unsafe {
-            self.as_inner_mut().enqueue_request(
+            self.enqueue_request_raw(
                 session_num,
                 req_type,
                 req_msgbuf.as_inner_mut().get_unchecked_mut(),
                 resp_msgbuf.as_inner_mut().get_unchecked_mut(),
-                cont_func as *mut c_void,
-                tag.unwrap_or(ptr::null_mut()),
-                kInvalidBgETid,
+                cont_func,
+                tag,
             );
         }
     }
 
+    /// # Safety
+    ///
+    /// Both buffers must stay valid and untouched until `cont_func` runs.
+    #[inline]
+    pub(crate) unsafe fn enqueue_request_raw(
+        &mut self,
+        session_num: c_int,
+        req_type: u8,
+        req_msgbuf: *mut erpc::MsgBuffer,
+        resp_msgbuf: *mut erpc::MsgBuffer,
+        cont_func: ContFunc,
+        tag: Option<*mut c_void>,
+    ) {
+        self.as_inner_mut().enqueue_request(
+            session_num,
+            req_type,
+            req_msgbuf,
+            resp_msgbuf,
+            cont_func as *mut c_void,
+            tag.unwrap_or(ptr::null_mut()),
+            kInvalidBgETid,
+        );
+    }
+
     #[inline]
     pub fn enqueue_response(&mut self, req_handle: &mut ReqHandle, resp_msgbuf: &mut MsgBuffer) {
         unsafe {
@@ -214,6 +246,62 @@ impl Rpc {
         self.as_inner_mut().force_retry_connect_on_invalid_rpc_id();
     }
 
+    /// The largest request or response eRPC sends.
+    #[inline]
+    pub fn get_max_msg_size(&self) -> usize {
+        erpc_wrapper::rpc_max_msg_size(self.as_inner())
+    }
+
+    #[inline]
+    pub fn get_max_data_per_pkt(&self) -> usize {
+        erpc_wrapper::rpc_max_data_per_pkt(self.as_inner())
+    }
+
+    /// Bytes of message buffers allocated through this `Rpc` and not freed.
+    #[inline]
+    pub fn get_stat_user_alloc_tot(&mut self) -> usize {
+        erpc_wrapper::rpc_stat_user_alloc_tot(self.as_inner_mut())
+    }
+
+    #[inline]
+    pub fn num_active_sessions(&mut self) -> usize {
+        erpc_wrapper::rpc_num_active_sessions(self.as_inner_mut())
+    }
+
+    /// Requests queued for the background threads of the [`Nexus`], including
+    /// those of other `Rpc`s.
+    #[inline]
+    pub fn bg_queue_len(&self) -> usize {
+        erpc_wrapper::rpc_bg_queue_len(self.as_inner())
+    }
+
+    /// Whether `session_num` is a client session which hasn't been destroyed.
+    #[inline]
+    pub fn is_client_session(&self, session_num: c_int) -> bool {
+        erpc_wrapper::rpc_is_client_session(self.as_inner(), session_num)
+    }
+
+    /// The credits left to the client session, out of `kSessionCredits`.
+    /// Returns `None` if `session_num` isn't a client session.
+    #[inline]
+    pub fn get_session_credits(&self, session_num: c_int) -> Option<usize> {
+        usize::try_from(erpc_wrapper::rpc_session_credits(
+            self.as_inner(),
+            session_num,
+        ))
+        .ok()
+    }
+
+    #[inline]
+    pub fn get_link_info(&self) -> LinkInfo {
+        LinkInfo {
+            phy_port: self.phy_port,
+            bandwidth: self.get_bandwidth(),
+            mtu: erpc_wrapper::rpc_mtu(),
+            max_data_per_pkt: self.get_max_data_per_pkt(),
+        }
+    }
+
     #[inline]
     pub fn as_inner_mut(&mut self) -> Pin<&mut erpc::Rpc> {
         self.inner.pin_mut()
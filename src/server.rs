@@ -1,30 +1,48 @@
 // Copyright (c) 2023, IOMesh Inc. All rights reserved.
 
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     future::Future,
+    io::BufRead,
+    panic::{self, AssertUnwindSafe},
     pin::Pin,
-    sync::{atomic::AtomicUsize, Arc},
+    process, ptr,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll},
+    time::{Instant, SystemTime},
 };
 
 use async_channel::{bounded, unbounded, Sender, TryRecvError};
 use erpc_sys::{
     c_int, c_void,
-    erpc::{ms_to_cycles, rdtsc, SmErrType, SmEventType},
+    erpc::{
+        ms_to_cycles, rdtsc, sm_err_type_str, sm_event_type_str, to_usec, SmErrType, SmEventType,
+    },
 };
-use tokio::runtime::Runtime;
+use tokio::runtime::{Handle, Runtime};
+use tracing::{debug, debug_span, field::Empty, trace, warn, Instrument};
 
 use crate::{
-    call::{Codec, RpcCall},
-    channel::Channel,
+    admin::{Role, ThreadPublisher, ThreadSnapshot},
+    buf::MsgBufferReader,
+    call::{CallTag, Codec, PollRole, RpcCall, SettleReq},
+    channel::{report_err, Channel},
     codec::{DeserializeFn, SerializeFn},
     env::Environment,
-    error::Result,
+    error::{Error, Result},
     method::Method,
+    method_stats::{MethodRecorder, ServerStats},
     msg_buffer::MsgBuffer,
     nexus::{Nexus, ReqHandler},
+    poll_stats::PollCounters,
+    pool::{MsgBufferAllocator, MsgBufferPool, PooledMsgBuffer},
     req_handle::ReqHandle,
     rpc::Rpc,
+    stage::{StageBreakdown, StageRecorder, SERVER_STAGES, SERVER_STAGE_SCHEDULE},
+    trace::{Scoped, TraceContext},
 };
 
 pub type AsyncReqHandler = Pin<Box<dyn Future<Output = ()> + Send + 'static>>;
@@ -42,17 +60,17 @@ impl<F> Handler<F> {
 }
 
 pub trait CloneableHandler: Send {
-    fn handle(&mut self, req: ReqHandle, rpc: Arc<Rpc>, tx: Sender<RpcCall>) -> AsyncReqHandler;
+    fn handle(&mut self, req: ReqHandle, tx: Sender<RpcCall>) -> AsyncReqHandler;
     fn box_clone(&self) -> Box<dyn CloneableHandler>;
 }
 
 impl<F: 'static> CloneableHandler for Handler<F>
 where
-    F: FnMut(ReqHandle, Arc<Rpc>, Sender<RpcCall>) -> AsyncReqHandler + Send + Clone,
+    F: FnMut(ReqHandle, Sender<RpcCall>) -> AsyncReqHandler + Send + Clone,
 {
     #[inline]
-    fn handle(&mut self, req: ReqHandle, rpc: Arc<Rpc>, tx: Sender<RpcCall>) -> AsyncReqHandler {
-        (self.cb)(req, rpc, tx)
+    fn handle(&mut self, req: ReqHandle, tx: Sender<RpcCall>) -> AsyncReqHandler {
+        (self.cb)(req, tx)
     }
 
     #[inline]
@@ -63,11 +81,164 @@ where
 
 pub type BoxHandler = Box<dyn CloneableHandler>;
 
+/// Records the requests of the wrapped handler of `method`.
+struct MeteredHandler {
+    method: u8,
+    inner: BoxHandler,
+    recorder: Arc<MethodRecorder>,
+}
+
+impl CloneableHandler for MeteredHandler {
+    fn handle(&mut self, mut req: ReqHandle, tx: Sender<RpcCall>) -> AsyncReqHandler {
+        let req_bytes = unsafe { MsgBufferReader::new(req.get_req_msgbuf()) }.len();
+        let id = self.recorder.start(self.method, req_bytes);
+        req.set_id(id);
+        Box::pin(Metered {
+            inner: self.inner.handle(req, tx.clone()),
+            recorder: self.recorder.clone(),
+            tx,
+            id,
+            method: self.method,
+            done: false,
+        })
+    }
+
+    fn box_clone(&self) -> Box<dyn CloneableHandler> {
+        Box::new(MeteredHandler {
+            method: self.method,
+            inner: self.inner.box_clone(),
+            recorder: self.recorder.clone(),
+        })
+    }
+}
+
+/// A handler future which reports to the recorder if it panics or is dropped
+/// before completing. Once it completes, the request is settled by the poll
+/// thread, after the response the handler sent, if any.
+struct Metered {
+    inner: AsyncReqHandler,
+    recorder: Arc<MethodRecorder>,
+    tx: Sender<RpcCall>,
+    id: u64,
+    method: u8,
+    done: bool,
+}
+
+impl Future for Metered {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let res = panic::catch_unwind(AssertUnwindSafe(|| self.inner.as_mut().poll(cx)));
+        match res {
+            Ok(Poll::Ready(())) => {
+                self.done = true;
+                // The queue only closes once the server has shut down.
+                let _ = self.tx.try_send(RpcCall::Settle(SettleReq { id: self.id }));
+                Poll::Ready(())
+            }
+            Ok(Poll::Pending) => Poll::Pending,
+            Err(e) => {
+                self.done = true;
+                self.recorder.fail(self.id, self.method, "panicked");
+                panic::resume_unwind(e)
+            }
+        }
+    }
+}
+
+impl Drop for Metered {
+    fn drop(&mut self) {
+        if !self.done {
+            self.recorder.fail(self.id, self.method, "cancelled");
+        }
+    }
+}
+
+/// Records the `schedule` and `handle` stages of the wrapped handler.
+struct StagedHandler {
+    inner: BoxHandler,
+    stages: Arc<StageRecorder>,
+}
+
+impl CloneableHandler for StagedHandler {
+    fn handle(&mut self, req: ReqHandle, tx: Sender<RpcCall>) -> AsyncReqHandler {
+        let start_tsc = rdtsc();
+        let fut = self.inner.handle(req, tx);
+        let stages = self.stages.clone();
+        Box::pin(async move {
+            let scheduled_tsc = rdtsc();
+            fut.await;
+            stages.record(
+                SERVER_STAGE_SCHEDULE,
+                &[
+                    scheduled_tsc.saturating_sub(start_tsc),
+                    rdtsc().saturating_sub(scheduled_tsc),
+                ],
+            );
+        })
+    }
+
+    fn box_clone(&self) -> Box<dyn CloneableHandler> {
+        Box::new(StagedHandler {
+            inner: self.inner.box_clone(),
+            stages: self.stages.clone(),
+        })
+    }
+}
+
+/// The state shared with the handlers run by eRPC's background threads.
+struct BgContext {
+    registry: HashMap<u8, Mutex<BoxHandler>>,
+    rt: Handle,
+    tx: Sender<RpcCall>,
+    /// Cleared once the server shuts down, later requests are dropped.
+    accepting: AtomicBool,
+    /// Background handlers which haven't returned yet.
+    running: AtomicUsize,
+}
+
+const _: fn() = || {
+    fn assert_sync<T: Sync>() {}
+    assert_sync::<BgContext>();
+};
+
+/// Respond to a request which failed on a background thread with an empty
+/// message.
+fn respond_empty(bg: &BgContext, req: ReqHandle) {
+    let res = bg.rt.block_on(async {
+        let resp_msgbuf = MsgBufferAllocator::new(bg.tx.clone()).alloc(0).await?;
+        bg.tx
+            .send(RpcCall::CallTag(CallTag::with_resp_msgbuf(
+                req,
+                resp_msgbuf,
+            )))
+            .await
+            .map_err(|_| Error::Channel("poll thread has exited".into()))
+    });
+    if let Err(e) = res {
+        warn!(error = %e, "failed to respond to a failed background request");
+    }
+}
+
+/// The context eRPC passes to the request handlers. The handlers on the poll
+/// thread get the [`ServerRpcContext`] at its start, the background ones
+/// only read `bg`, which the poll thread never borrows mutably.
+#[repr(C)]
+struct RawServerContext {
+    ctx: ServerRpcContext,
+    bg: Arc<BgContext>,
+}
+
 pub struct ServerRpcContext {
     registry: HashMap<u8, BoxHandler>,
-    pub rpc: Arc<Rpc>,
+    /// The poll thread's `Rpc`, only valid on the poll thread.
+    pub(crate) rpc: *mut Rpc,
     pub rt: Runtime,
     pub tx: Sender<RpcCall>,
+    pub pool: MsgBufferPool,
+    poll_counters: Arc<PollCounters>,
+    pub(crate) stages: Option<Arc<StageRecorder>>,
+    pub(crate) method_stats: Option<Arc<MethodRecorder>>,
 }
 
 impl ServerRpcContext {
@@ -76,6 +247,94 @@ impl ServerRpcContext {
         self.registry.get_mut(&req_type)
     }
 
+    /// Run request handler `f` on the poll thread, accounting its time in
+    /// the thread's [`PollThreadStats`](crate::prelude::PollThreadStats).
+    ///
+    /// # Safety
+    ///
+    /// `ctx` must be the context passed by eRPC to a request handler, and
+    /// `f` must not keep the context after it returns.
+    pub unsafe fn run_handler<F>(ctx: *mut c_void, f: F)
+    where
+        F: FnOnce(&'static mut ServerRpcContext),
+    {
+        let start = rdtsc();
+        f(&mut *(ctx as *mut ServerRpcContext));
+        let ctx = &mut *(ctx as *mut ServerRpcContext);
+        let cycles = rdtsc() - start;
+        let ns = (to_usec(cycles, ctx.rpc().get_freq_ghz()) * 1000.0) as u64;
+        ctx.poll_counters.record_handler(ns);
+    }
+
+    /// Run the background handler of `req_type` to completion on the calling
+    /// eRPC background thread. Its buffers are allocated and its response is
+    /// enqueued by the poll thread, like those of the other handlers. Once
+    /// the server shuts down, the request is dropped.
+    ///
+    /// If there's no handler for `req_type`, or the handler panics before
+    /// responding, the request gets an empty response so that the client
+    /// isn't left waiting. The process aborts if anything else panics, like
+    /// in the generated foreground wrappers.
+    ///
+    /// # Safety
+    ///
+    /// `ctx` must be the context passed by eRPC to a background request
+    /// handler.
+    pub unsafe fn run_bg_handler(ctx: *mut c_void, req_type: u8, mut req: ReqHandle) {
+        let res = panic::catch_unwind(AssertUnwindSafe(|| {
+            let bg = &*ptr::addr_of!((*(ctx as *const RawServerContext)).bg);
+            bg.running.fetch_add(1, Ordering::AcqRel);
+            // The server drains the running handlers before it shuts down, so
+            // the context is valid until `running` is decremented.
+            let bg = bg.clone();
+            if !bg.accepting.load(Ordering::Acquire) {
+                debug!(
+                    method = req_type,
+                    "server is shutting down, dropping request"
+                );
+            } else if let Some(handler) = bg.registry.get(&req_type) {
+                let raw = req.as_ptr();
+                let responded = req.track_response();
+                let fut = handler.lock().unwrap().handle(req, bg.tx.clone());
+                if panic::catch_unwind(AssertUnwindSafe(|| bg.rt.block_on(fut))).is_err() {
+                    warn!(method = req_type, "background handler panicked");
+                    if !responded.load(Ordering::Acquire) {
+                        respond_empty(&bg, ReqHandle::from_inner_raw(raw));
+                    }
+                }
+            } else {
+                warn!(method = req_type, "no background handler");
+                respond_empty(&bg, req);
+            }
+            bg.running.fetch_sub(1, Ordering::AcqRel);
+        }));
+        if res.is_err() {
+            process::abort();
+        }
+    }
+
+    /// Returns the poll thread's `Rpc`. Must be called on the poll thread,
+    /// e.g. from a request handler.
+    #[inline]
+    pub fn rpc(&mut self) -> &mut Rpc {
+        unsafe { &mut *self.rpc }
+    }
+
+    /// Get a response buffer from this poll thread's pool. Must be called on
+    /// the poll thread, e.g. from a request handler.
+    #[inline]
+    pub fn alloc_msg_buffer(&mut self, data_size: usize) -> Result<PooledMsgBuffer> {
+        let rpc = unsafe { &mut *self.rpc };
+        self.pool.alloc(rpc, data_size)
+    }
+
+    /// Returns a handle to allocate response buffers from other threads, e.g.
+    /// from the async handlers spawned on `rt`.
+    #[inline]
+    pub fn allocator(&self) -> MsgBufferAllocator {
+        MsgBufferAllocator::new(self.tx.clone())
+    }
+
     #[inline]
     pub fn spawn<F>(&self, f: F)
     where
@@ -85,7 +344,14 @@ impl ServerRpcContext {
     }
 }
 
-extern "C" fn sm_handler(_: c_int, _: SmEventType, _: SmErrType, _: *mut c_void) {}
+extern "C" fn sm_handler(sid: c_int, event: SmEventType, err: SmErrType, _: *mut c_void) {
+    debug!(
+        session = i32::from(sid),
+        event = %sm_event_type_str(event),
+        error = %sm_err_type_str(err),
+        "session event"
+    );
+}
 
 /// [`Service`] factory in order to configure the properties.
 ///
@@ -94,6 +360,8 @@ extern "C" fn sm_handler(_: c_int, _: SmEventType, _: SmErrType, _: *mut c_void)
 pub struct ServiceBuilder {
     handlers: HashMap<u8, BoxHandler>,
     raw_handlers: HashMap<u8, ReqHandler>,
+    names: HashMap<u8, &'static str>,
+    bg_methods: HashSet<u8>,
 }
 
 impl ServiceBuilder {
@@ -102,6 +370,8 @@ impl ServiceBuilder {
         ServiceBuilder {
             handlers: HashMap::new(),
             raw_handlers: HashMap::new(),
+            names: HashMap::new(),
+            bg_methods: HashSet::new(),
         }
     }
 
@@ -115,26 +385,78 @@ impl ServiceBuilder {
     where
         Req: 'static,
         Resp: 'static,
-        F: FnMut(ReqHandle, Arc<Rpc>, Sender<RpcCall>, Codec<Req, Resp>) -> AsyncReqHandler
+        F: FnMut(ReqHandle, Sender<RpcCall>, Codec<Req, Resp>) -> AsyncReqHandler
             + Send
             + Clone
             + 'static,
     {
         let (ser, de) = (method.resp_ser(), method.req_de());
-        let h = move |req: ReqHandle, rpc: Arc<Rpc>, tx: Sender<RpcCall>| -> AsyncReqHandler {
-            execute_unary(ser, de, req, &mut handler, rpc, tx)
+        let method_id = method.id;
+        let trace_trailer = method.req_mar.trace_trailer;
+        let h = move |mut req: ReqHandle, tx: Sender<RpcCall>| -> AsyncReqHandler {
+            let mut reader = unsafe { MsgBufferReader::new(req.get_req_msgbuf()) };
+            let req_size = reader.len();
+            let parent = trace_trailer
+                .then(|| reader.fill_buf().ok().and_then(TraceContext::from_trailer))
+                .flatten();
+            let trace_ctx = parent.map(|p| p.child_of());
+            let span = debug_span!(
+                "erpc_handle",
+                method = method_id,
+                req_size,
+                latency_us = Empty,
+                trace_id = trace_ctx.map(|c| c.trace_id),
+                span_id = trace_ctx.map(|c| c.span_id),
+                parent_span_id = parent.map(|p| p.span_id),
+            );
+            let fut = execute_unary(ser, de, req, &mut handler, tx);
+            let start = Instant::now();
+            let fut = async move {
+                fut.await;
+                tracing::Span::current().record("latency_us", start.elapsed().as_micros() as u64);
+            };
+            Box::pin(Scoped::new(fut.instrument(span), trace_ctx))
         };
         let ch = Box::new(Handler::new(h));
         self.handlers.insert(method.id, ch);
         self.raw_handlers.insert(method.id, raw_handler);
+        self.names.insert(method.id, method.name);
         self
     }
 
+    /// Add a unary RPC call handler run by eRPC's background threads, see
+    /// [`EnvBuilder::bg_thread_count`](crate::prelude::EnvBuilder::bg_thread_count).
+    ///
+    /// `raw_handler` is registered as is and should pass the request to
+    /// [`ServerRpcContext::run_bg_handler`], which blocks the background
+    /// thread on `handler` until it completes. erpc-build generates it for
+    /// the methods passed to its `Generator::bg_method`.
+    pub fn add_unary_bg_handler<Req, Resp, F>(
+        self,
+        method: &Method<Req, Resp>,
+        handler: F,
+        raw_handler: ReqHandler,
+    ) -> ServiceBuilder
+    where
+        Req: 'static,
+        Resp: 'static,
+        F: FnMut(ReqHandle, Sender<RpcCall>, Codec<Req, Resp>) -> AsyncReqHandler
+            + Send
+            + Clone
+            + 'static,
+    {
+        let mut builder = self.add_unary_handler(method, handler, raw_handler);
+        builder.bg_methods.insert(method.id);
+        builder
+    }
+
     /// Finalize the [`ServiceBuilder`] and build the [`Service`].
     pub fn build(self) -> Service {
         Service {
             handlers: self.handlers,
             raw_handlers: self.raw_handlers,
+            names: self.names,
+            bg_methods: self.bg_methods,
         }
     }
 }
@@ -143,8 +465,10 @@ impl ServiceBuilder {
 ///
 /// Use [`ServiceBuilder`] to build a [`Service`].
 pub struct Service {
-    handlers: HashMap<u8, BoxHandler>,
+    pub(crate) handlers: HashMap<u8, BoxHandler>,
     raw_handlers: HashMap<u8, ReqHandler>,
+    names: HashMap<u8, &'static str>,
+    bg_methods: HashSet<u8>,
 }
 
 /// [`Server`] factory in order to configure the properties.
@@ -152,8 +476,12 @@ pub struct ServerBuilder {
     env: Arc<Environment>,
     phy_port: u8,
     timeout_ms: usize,
+    stage_latency: bool,
+    method_stats: bool,
     handlers: HashMap<u8, BoxHandler>,
     raw_handlers: HashMap<u8, ReqHandler>,
+    names: HashMap<u8, &'static str>,
+    bg_methods: HashSet<u8>,
 }
 
 impl ServerBuilder {
@@ -163,8 +491,12 @@ impl ServerBuilder {
             env,
             phy_port,
             timeout_ms,
+            stage_latency: false,
+            method_stats: false,
             handlers: HashMap::new(),
             raw_handlers: HashMap::new(),
+            names: HashMap::new(),
+            bg_methods: HashSet::new(),
         }
     }
 
@@ -172,83 +504,215 @@ impl ServerBuilder {
     pub fn register_service(mut self, service: Service) -> ServerBuilder {
         self.handlers.extend(service.handlers);
         self.raw_handlers.extend(service.raw_handlers);
+        self.names.extend(service.names);
+        self.bg_methods.extend(service.bg_methods);
+        self
+    }
+
+    /// Record the latency of each stage of the requests, see
+    /// [`StageBreakdown`](crate::prelude::StageBreakdown).
+    pub fn stage_latency(mut self, enable: bool) -> ServerBuilder {
+        self.stage_latency = enable;
+        self
+    }
+
+    /// Record per-method request statistics, see [`Server::method_stats`].
+    pub fn method_stats(mut self, enable: bool) -> ServerBuilder {
+        self.method_stats = enable;
         self
     }
 
     /// Finalize the [`ServerBuilder`] and build the [`Server`].
+    ///
+    /// Errors while starting up are returned, and the poll thread is handed
+    /// back to the environment.
     pub async fn build_and_start(self) -> Result<Server> {
-        let env = self.env.pick_channel_env().unwrap();
-        env.0
-            .send(Box::new(
-                move |id: u8, nexus: &mut Arc<Nexus>, chan_tx: Sender<Channel>| {
-                    for (k, v) in &self.raw_handlers {
-                        unsafe { Arc::get_mut_unchecked(nexus) }
-                            .register_req_func(k.to_owned(), v.to_owned())
-                            .unwrap();
+        let poll_tx = self
+            .env
+            .pick_channel_env()
+            .ok_or_else(|| Error::Internal("no idle poll thread".into()))?;
+        let admin = self.env.admin_state().cloned();
+        let method_stats = self
+            .method_stats
+            .then(|| Arc::new(MethodRecorder::new(self.names.clone())));
+        let recorder = method_stats.clone();
+        let env_handle = self.env.clone();
+        let (chan_tx, chan_rx) = bounded::<Result<Channel>>(1);
+        poll_tx
+            .send(Box::new(move |id: u8, nexus: &Nexus| {
+                let req_funcs: Vec<_> = self
+                    .raw_handlers
+                    .iter()
+                    .map(|(&k, &v)| (k, v, self.bg_methods.contains(&k)))
+                    .collect();
+                if let Err(e) = nexus.register_req_funcs(&req_funcs) {
+                    warn!(thread = id, error = %e, "failed to start server");
+                    return report_err(&chan_tx, e);
+                }
+                let rt = match Runtime::new() {
+                    Ok(rt) => rt,
+                    Err(e) => {
+                        warn!(thread = id, error = %e, "failed to create runtime");
+                        return report_err(
+                            &chan_tx,
+                            Error::Internal(format!("failed to create runtime: {e}")),
+                        );
                     }
-                    let mut rpc = Arc::new(Rpc::new(
-                        unsafe { Arc::get_mut_unchecked(nexus) },
-                        None,
-                        id,
-                        Some(sm_handler),
-                        self.phy_port,
-                    ));
-                    let (tx, rx) = unbounded::<RpcCall>();
-                    let mut ctx = ServerRpcContext {
-                        registry: self
-                            .handlers
-                            .iter()
-                            .map(|(k, v)| (k.to_owned(), v.box_clone()))
-                            .collect(),
-                        rpc: rpc.clone(),
-                        rt: tokio::runtime::Runtime::new().unwrap(),
-                        tx: tx.clone(),
-                    };
-                    let rpc_clone = rpc.clone();
-                    let rpc = unsafe { Arc::get_mut_unchecked(&mut rpc) };
-                    let raw_ctx = &mut ctx as *mut ServerRpcContext as *mut c_void;
-                    unsafe {
-                        rpc.set_context(raw_ctx);
+                };
+                let poll_counters = env_handle.poll_counters(id);
+                // The `Rpc` lives on this thread's stack until the loop exits.
+                let mut rpc = Rpc::new(nexus, None, id, Some(sm_handler), self.phy_port);
+                let rpc = &mut rpc;
+                let (tx, rx) = unbounded::<RpcCall>();
+                let stages = self
+                    .stage_latency
+                    .then(|| Arc::new(StageRecorder::new(SERVER_STAGES, rpc.get_freq_ghz())));
+                let mut registry = HashMap::new();
+                let mut bg_registry = HashMap::new();
+                for (k, v) in &self.handlers {
+                    let mut h = v.box_clone();
+                    if let Some(recorder) = recorder.as_ref() {
+                        h = Box::new(MeteredHandler {
+                            method: *k,
+                            inner: h,
+                            recorder: recorder.clone(),
+                        });
+                    }
+                    if let Some(stages) = stages.as_ref() {
+                        h = Box::new(StagedHandler {
+                            inner: h,
+                            stages: stages.clone(),
+                        });
                     }
-                    let (stx, srx) = bounded::<()>(1);
-                    let chan = Channel {
-                        subchans: Vec::default(),
-                        assigned_idx: Arc::new(AtomicUsize::new(0)),
-                        rpc: rpc_clone,
-                        tx,
-                        rx: srx,
-                    };
-                    chan_tx.send_blocking(chan).unwrap();
-
-                    'outer: loop {
-                        let timeout_tsc = ms_to_cycles(self.timeout_ms as f64, rpc.get_freq_ghz());
-                        let start_tsc = rdtsc();
-                        loop {
-                            rpc.run_event_loop_once();
-                            // TODO: make it configurable
-                            for _i in 0..8192 {
-                                match rx.try_recv() {
-                                    Ok(call) => call.resolve(rpc, raw_ctx),
-                                    Err(TryRecvError::Empty) => break,
-                                    Err(TryRecvError::Closed) => {
-                                        break 'outer;
-                                    }
+                    if self.bg_methods.contains(k) {
+                        bg_registry.insert(*k, Mutex::new(h));
+                    } else {
+                        registry.insert(*k, h);
+                    }
+                }
+                let bg = Arc::new(BgContext {
+                    registry: bg_registry,
+                    rt: rt.handle().clone(),
+                    tx: tx.clone(),
+                    accepting: AtomicBool::new(true),
+                    running: AtomicUsize::new(0),
+                });
+                let mut raw = RawServerContext {
+                    ctx: ServerRpcContext {
+                        registry,
+                        rpc,
+                        rt,
+                        tx: tx.clone(),
+                        pool: MsgBufferPool::default(),
+                        poll_counters: poll_counters.clone(),
+                        stages: stages.clone(),
+                        method_stats: recorder.clone(),
+                    },
+                    bg: bg.clone(),
+                };
+                let raw_ctx = ptr::addr_of_mut!(raw) as *mut c_void;
+                let ctx = unsafe { &mut (*(raw_ctx as *mut RawServerContext)).ctx };
+                unsafe {
+                    rpc.set_context(raw_ctx);
+                }
+                let mut publisher = admin
+                    .as_ref()
+                    .map(|state| ThreadPublisher::new(state.clone(), id, rpc.get_freq_ghz()));
+                let mut handler_ids: Vec<u8> = self.handlers.keys().copied().collect();
+                handler_ids.sort_unstable();
+                let (stx, srx) = bounded::<()>(1);
+                let chan = Channel {
+                    subchans: Vec::default(),
+                    assigned_idx: Arc::new(AtomicUsize::new(0)),
+                    trace_context: false,
+                    stages,
+                    tx,
+                    rx: srx,
+                };
+                if chan_tx.send_blocking(Ok(chan)).is_err() {
+                    // `build_and_start` was cancelled, stop right away. The
+                    // context holds a sender, so the queue must be closed.
+                    ctx.tx.close();
+                }
+                debug!(
+                    thread = id,
+                    handlers = self.handlers.len(),
+                    "server started"
+                );
+
+                'outer: loop {
+                    let timeout_tsc = ms_to_cycles(self.timeout_ms as f64, rpc.get_freq_ghz());
+                    let start_tsc = rdtsc();
+                    loop {
+                        let events = poll_counters.events();
+                        rpc.run_event_loop_once();
+                        // TODO: make it configurable
+                        let mut backlog = true;
+                        let mut drained = 0;
+                        for _i in 0..8192 {
+                            match rx.try_recv() {
+                                Ok(call) => {
+                                    call.resolve(PollRole::Server, rpc, raw_ctx, &mut ctx.pool);
+                                    drained += 1;
+                                }
+                                Err(TryRecvError::Empty) => {
+                                    backlog = false;
+                                    break;
+                                }
+                                Err(TryRecvError::Closed) => {
+                                    break 'outer;
                                 }
                             }
-                            if rpc.get_ev_loop_tsc() - start_tsc > timeout_tsc {
-                                break;
-                            }
                         }
+                        poll_counters.record_iteration(events, drained);
+                        if backlog {
+                            trace!(thread = id, "call queue not drained");
+                        }
+                        if rpc.get_ev_loop_tsc() - start_tsc > timeout_tsc {
+                            break;
+                        }
+                    }
+                    ctx.pool.reclaim(rpc);
+                    if let Some(publisher) = publisher.as_mut() {
+                        if publisher.due() {
+                            publisher.publish(ThreadSnapshot {
+                                role: Role::Server {
+                                    handlers: handler_ids.clone(),
+                                },
+                                queued_calls: rx.len(),
+                                updated: SystemTime::now(),
+                            });
+                        }
+                    }
+                }
+                if let Some(publisher) = publisher.as_ref() {
+                    publisher.clear();
+                }
+                debug!(thread = id, "server shutting down");
+                // The requests queued for the background threads point to the
+                // context and the `Rpc`, drop them and wait until they're
+                // gone. The queue is shared with the other `Rpc`s of the
+                // `Nexus`.
+                bg.accepting.store(false, Ordering::Release);
+                while rpc.bg_queue_len() > 0 || bg.running.load(Ordering::Acquire) > 0 {
+                    rpc.run_event_loop_once();
+                    while let Ok(call) = rx.try_recv() {
+                        call.resolve(PollRole::Server, rpc, raw_ctx, &mut ctx.pool);
                     }
-                    stx.send_blocking(()).unwrap();
-                },
-            ))
+                }
+                ctx.pool.release(rpc);
+                // The shutdown completes once the thread is handed back.
+                Some(Box::new(move || {
+                    let _ = stx.send_blocking(());
+                }))
+            }))
             .await
-            .unwrap();
+            .map_err(|_| Error::Channel("poll thread has exited".into()))?;
 
         Ok(Server {
             env: self.env,
-            ch: env.1.recv().await.unwrap(),
+            ch: chan_rx.recv().await??,
+            method_stats,
         })
     }
 }
@@ -257,12 +721,28 @@ impl ServerBuilder {
 pub struct Server {
     env: Arc<Environment>,
     pub ch: Channel,
+    method_stats: Option<Arc<MethodRecorder>>,
 }
 
 impl Server {
-    pub fn alloc_msg_buffer(&mut self, max_data_size: usize) -> MsgBuffer {
-        let rpc = unsafe { Arc::get_mut_unchecked(&mut self.ch.rpc) };
-        rpc.alloc_msg_buffer_or_die(max_data_size)
+    /// Allocate a buffer on the server's poll thread.
+    pub async fn alloc_msg_buffer(&self, max_data_size: usize) -> Result<MsgBuffer> {
+        let buf = MsgBufferAllocator::new(self.ch.tx.clone())
+            .alloc(max_data_size)
+            .await?;
+        Ok(buf.into_inner())
+    }
+
+    /// Returns the per-method request statistics, if enabled by
+    /// [`ServerBuilder::method_stats`].
+    pub fn method_stats(&self) -> Option<ServerStats> {
+        self.method_stats.as_ref().map(|m| m.snapshot())
+    }
+
+    /// Returns the per-stage latency of the requests, if enabled by
+    /// [`ServerBuilder::stage_latency`].
+    pub fn stage_latency(&self) -> Option<StageBreakdown> {
+        self.ch.stage_latency()
     }
 
     pub async fn shutdown(&mut self) -> Result<()> {
@@ -276,11 +756,10 @@ pub fn execute_unary<P, Q, F>(
     de: DeserializeFn<P>,
     req_handle: ReqHandle,
     f: &mut F,
-    rpc: Arc<Rpc>,
     tx: Sender<RpcCall>,
 ) -> AsyncReqHandler
 where
-    F: FnMut(ReqHandle, Arc<Rpc>, Sender<RpcCall>, Codec<P, Q>) -> AsyncReqHandler + Send + Clone,
+    F: FnMut(ReqHandle, Sender<RpcCall>, Codec<P, Q>) -> AsyncReqHandler + Send + Clone,
 {
-    f(req_handle, rpc, tx, Codec::new(ser, de))
+    f(req_handle, tx, Codec::new(ser, de))
 }
@@ -0,0 +1,97 @@
+// Copyright (c) 2023, IOMesh Inc. All rights reserved.
+
+use std::sync::{atomic::AtomicUsize, Mutex};
+
+use erpc_sys::erpc::to_usec;
+
+use crate::histogram::Histogram;
+
+/// Stages of a client call:
+/// - `queue`: from the task sending the call until the poll thread takes it.
+/// - `dispatch`: until `enqueue_request` returns.
+/// - `network`: until the continuation fires, i.e. eRPC's round trip.
+/// - `wake`: until the awaiting task is woken with the response.
+/// - `decode`: until the response is decoded.
+pub(crate) const CLIENT_STAGES: &[&str] = &["queue", "dispatch", "network", "wake", "decode"];
+
+/// Stages of a server request:
+/// - `schedule`: from the request handler on the poll thread until the async
+///   handler starts running.
+/// - `handle`: until the async handler completes.
+/// - `respond`: from the `CallTag` being created until the poll thread
+///   enqueues the response.
+pub(crate) const SERVER_STAGES: &[&str] = &["schedule", "handle", "respond"];
+
+pub(crate) const SERVER_STAGE_SCHEDULE: usize = 0;
+pub(crate) const SERVER_STAGE_RESPOND: usize = 2;
+
+/// The TSCs taken on the poll thread during a client call.
+#[derive(Default)]
+pub(crate) struct CallStamps {
+    pub(crate) dequeued: AtomicUsize,
+    pub(crate) enqueued: AtomicUsize,
+    pub(crate) fired: AtomicUsize,
+}
+
+/// Aggregates the per-stage latency of the calls of one channel or server.
+pub(crate) struct StageRecorder {
+    names: &'static [&'static str],
+    freq_ghz: f64,
+    histograms: Mutex<Vec<Histogram>>,
+}
+
+impl StageRecorder {
+    pub(crate) fn new(names: &'static [&'static str], freq_ghz: f64) -> Self {
+        StageRecorder {
+            names,
+            freq_ghz,
+            histograms: Mutex::new(vec![Histogram::new(); names.len()]),
+        }
+    }
+
+    /// Record the durations in cycles of consecutive stages, starting at
+    /// `first`.
+    pub(crate) fn record(&self, first: usize, cycles: &[usize]) {
+        let mut histograms = self.histograms.lock().unwrap();
+        for (h, &c) in histograms[first..].iter_mut().zip(cycles) {
+            h.record((to_usec(c, self.freq_ghz) * 1000.0) as u64);
+        }
+    }
+
+    pub(crate) fn snapshot(&self) -> StageBreakdown {
+        let histograms = self.histograms.lock().unwrap();
+        StageBreakdown {
+            stages: self
+                .names
+                .iter()
+                .copied()
+                .zip(histograms.iter().cloned())
+                .collect(),
+        }
+    }
+}
+
+/// Per-stage latency histograms in nanoseconds, in the order of the stages.
+///
+/// Enable it with [`ChannelBuilder::stage_latency`](crate::prelude::ChannelBuilder::stage_latency)
+/// on clients, whose stages are `queue`, `dispatch`, `network`, `wake` and
+/// `decode`, or [`ServerBuilder::stage_latency`](crate::prelude::ServerBuilder::stage_latency)
+/// on servers, whose stages are `schedule`, `handle` and `respond`.
+#[derive(Debug, Clone)]
+pub struct StageBreakdown {
+    stages: Vec<(&'static str, Histogram)>,
+}
+
+impl StageBreakdown {
+    /// Returns the histogram of `stage`.
+    pub fn get(&self, stage: &str) -> Option<&Histogram> {
+        self.stages
+            .iter()
+            .find(|(name, _)| *name == stage)
+            .map(|(_, h)| h)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&'static str, &Histogram)> {
+        self.stages.iter().map(|(name, h)| (*name, h))
+    }
+}
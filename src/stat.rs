@@ -1,11 +1,17 @@
 // Copyright (c) 2023, IOMesh Inc. All rights reserved.
 
+use std::{
+    io::{self, Write},
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
 use erpc_sys::{
     erpc::{kSessionCredits, ChronoTimer},
     UniquePtr, WithinUniquePtr,
 };
 
-pub const MAX_CONCURRENCY: usize = 32;
+use crate::histogram::Histogram;
 
 #[derive(Default, Debug, Copy, Clone, PartialEq)]
 pub struct TransStats {
@@ -21,36 +27,151 @@ pub struct TransStats {
     pub rpc_999_us: f64,
 }
 
+/// A [`TransStats`] snapshot published by a poll thread.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct StatsRecord {
+    /// The ID of the poll thread.
+    pub thread_id: usize,
+    /// When the snapshot was taken.
+    pub timestamp: SystemTime,
+    /// The sending rate chosen by Timely.
+    pub rate_gbps: f64,
+    pub stats: TransStats,
+}
+
+impl StatsRecord {
+    #[inline]
+    fn timestamp_ms(&self) -> u128 {
+        self.timestamp
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis())
+    }
+}
+
+/// Receives the [`StatsRecord`]s of every poll thread, once per `timeout_ms`.
+///
+/// Set it with [`ChannelBuilder::stats_sink`](crate::prelude::ChannelBuilder::stats_sink).
+pub trait StatsSink: Send + Sync {
+    fn record(&self, record: &StatsRecord) -> io::Result<()>;
+}
+
+/// Writes each record as a JSON object on its own line.
+pub struct JsonLinesSink<W> {
+    w: Mutex<W>,
+}
+
+impl<W: Write + Send> JsonLinesSink<W> {
+    pub fn new(w: W) -> Self {
+        JsonLinesSink { w: Mutex::new(w) }
+    }
+}
+
+impl<W: Write + Send> StatsSink for JsonLinesSink<W> {
+    fn record(&self, r: &StatsRecord) -> io::Result<()> {
+        let s = &r.stats;
+        let mut w = self.w.lock().unwrap();
+        writeln!(
+            w,
+            "{{\"thread_id\":{},\"timestamp_ms\":{},\"rx_gbps\":{},\"tx_gbps\":{},\
+             \"re_tx\":{},\"rtt_50_us\":{},\"rtt_99_us\":{},\"rpc_50_us\":{},\
+             \"rpc_99_us\":{},\"rpc_999_us\":{},\"rate_gbps\":{}}}",
+            r.thread_id,
+            r.timestamp_ms(),
+            json_f64(s.rx_gbps),
+            json_f64(s.tx_gbps),
+            s.re_tx,
+            json_f64(s.rtt_50_us),
+            json_f64(s.rtt_99_us),
+            json_f64(s.rpc_50_us),
+            json_f64(s.rpc_99_us),
+            json_f64(s.rpc_999_us),
+            json_f64(r.rate_gbps),
+        )?;
+        w.flush()
+    }
+}
+
+/// JSON has no NaN or infinity.
+fn json_f64(v: f64) -> String {
+    if v.is_finite() {
+        v.to_string()
+    } else {
+        "null".to_owned()
+    }
+}
+
+const CSV_HEADER: &str = "thread_id,timestamp_ms,rx_gbps,tx_gbps,re_tx,rtt_50_us,rtt_99_us,\
+                          rpc_50_us,rpc_99_us,rpc_999_us,rate_gbps";
+
+/// Writes each record as a CSV row, after a header row.
+pub struct CsvSink<W> {
+    w: Mutex<W>,
+}
+
+impl<W: Write + Send> CsvSink<W> {
+    /// Initialize a new [`CsvSink`], writing the header row to `w`.
+    pub fn new(mut w: W) -> io::Result<Self> {
+        writeln!(w, "{}", CSV_HEADER)?;
+        Ok(CsvSink { w: Mutex::new(w) })
+    }
+}
+
+impl<W: Write + Send> StatsSink for CsvSink<W> {
+    fn record(&self, r: &StatsRecord) -> io::Result<()> {
+        let s = &r.stats;
+        let mut w = self.w.lock().unwrap();
+        writeln!(
+            w,
+            "{},{},{},{},{},{},{},{},{},{},{}",
+            r.thread_id,
+            r.timestamp_ms(),
+            s.rx_gbps,
+            s.tx_gbps,
+            s.re_tx,
+            s.rtt_50_us,
+            s.rtt_99_us,
+            s.rpc_50_us,
+            s.rpc_99_us,
+            s.rpc_999_us,
+            r.rate_gbps,
+        )?;
+        w.flush()
+    }
+}
+
 pub struct BenchStat {
     /// The ID of the thread that owns this context
     pub thread_id: usize,
     // We need a wide range of latency measurements: ~4 us for 4KB RPCs, to
     // >10 ms for 8MB RPCs under congestion. So erpc::Latency doesn't work here.
-    pub lat_vec: Vec<f64>,
+    /// RPC latency in nanoseconds since the last reset.
+    pub lat: Histogram,
 
     pub tput: UniquePtr<ChronoTimer>,
     pub tr_stats: TransStats,
 
     pub stat_rx_bytes_tot: usize,
     pub stat_tx_bytes_tot: usize,
-    pub req_ts: [usize; MAX_CONCURRENCY],
 
     pub args_req_size: usize,
     pub args_resp_size: usize,
+
+    /// Where the snapshots are published besides the log.
+    pub sink: Option<Arc<dyn StatsSink>>,
 }
 
 impl Default for BenchStat {
     fn default() -> Self {
         BenchStat {
             thread_id: usize::MAX,
-            lat_vec: Vec::default(),
+            lat: Histogram::new(),
             tput: ChronoTimer::new().within_unique_ptr(),
             tr_stats: TransStats::default(),
             stat_rx_bytes_tot: 0,
             stat_tx_bytes_tot: 0,
-            req_ts: [0; MAX_CONCURRENCY],
             args_req_size: 0,
             args_resp_size: 0,
+            sink: None,
         }
     }
 }
@@ -69,14 +190,10 @@ impl BenchStat {
         self.tr_stats.rtt_50_us = rtt_50_us;
         self.tr_stats.rtt_99_us = rtt_99_us;
 
-        if !self.lat_vec.is_empty() {
-            self.lat_vec.sort_floats();
-            self.tr_stats.rpc_50_us =
-                self.lat_vec[(self.lat_vec.len() as f64 * 0.5).floor() as usize];
-            self.tr_stats.rpc_99_us =
-                self.lat_vec[(self.lat_vec.len() as f64 * 0.99).floor() as usize];
-            self.tr_stats.rpc_999_us =
-                self.lat_vec[(self.lat_vec.len() as f64 * 0.999).floor() as usize];
+        if !self.lat.is_empty() {
+            self.tr_stats.rpc_50_us = self.lat.quantile(0.5) as f64 / 1000.0;
+            self.tr_stats.rpc_99_us = self.lat.quantile(0.99) as f64 / 1000.0;
+            self.tr_stats.rpc_999_us = self.lat.quantile(0.999) as f64 / 1000.0;
         } else {
             // Even if no RPCs completed, we need retransmission counter
             self.tr_stats.rpc_50_us = timeout_ms as f64 * 1000.0;
@@ -86,6 +203,16 @@ impl BenchStat {
     }
 
     pub fn output(&self, rate_gbps: f64) {
+        // The sizes are 0 unless set with `ChannelBuilder::req_size` and
+        // `ChannelBuilder::resp_size`.
+        let rx_iops = self
+            .stat_rx_bytes_tot
+            .checked_div(self.args_resp_size)
+            .unwrap_or(0);
+        let tx_iops = self
+            .stat_tx_bytes_tot
+            .checked_div(self.args_req_size)
+            .unwrap_or(0);
         let stats = format!(
                             "Rpc throughput: Thread {}: Tput {{RX {:.2} ({}), TX {:.2} ({})}} \
                              Gbps (IOPS). Retransmissions {}. Packet RTTs: {{{:.1}, {:.1}}} us. \
@@ -93,9 +220,9 @@ impl BenchStat {
                              Gbps. Credits {} (best = 32).",
                             self.thread_id,
                             self.tr_stats.rx_gbps,
-                            self.stat_rx_bytes_tot / self.args_resp_size,
+                            rx_iops,
                             self.tr_stats.tx_gbps,
-                            self.stat_tx_bytes_tot / self.args_req_size,
+                            tx_iops,
                             self.tr_stats.re_tx,
                             self.tr_stats.rtt_50_us,
                             self.tr_stats.rtt_99_us,
@@ -105,14 +232,87 @@ impl BenchStat {
                             rate_gbps,
                             kSessionCredits
                         );
-        println!("{}", stats);
+        tracing::info!("{}", stats);
+        if let Some(sink) = &self.sink {
+            let record = StatsRecord {
+                thread_id: self.thread_id,
+                timestamp: SystemTime::now(),
+                rate_gbps,
+                stats: self.tr_stats,
+            };
+            if let Err(e) = sink.record(&record) {
+                tracing::warn!(thread = self.thread_id, error = %e, "failed to write stats");
+            }
+        }
     }
 
     pub fn reset(&mut self) {
         // Reset stats for next iteration
         self.stat_rx_bytes_tot = 0;
         self.stat_tx_bytes_tot = 0;
-        self.lat_vec.clear();
+        self.lat.reset();
         self.tput.pin_mut().as_mut().reset();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn record() -> StatsRecord {
+        StatsRecord {
+            thread_id: 2,
+            timestamp: UNIX_EPOCH + Duration::from_millis(1234),
+            rate_gbps: 12.5,
+            stats: TransStats {
+                rx_gbps: 1.5,
+                tx_gbps: 0.25,
+                re_tx: 3,
+                rtt_50_us: 4.0,
+                rtt_99_us: f64::NAN,
+                rpc_50_us: 10.0,
+                rpc_99_us: 20.5,
+                rpc_999_us: f64::INFINITY,
+            },
+        }
+    }
+
+    #[cfg(feature = "loopback")]
+    #[test]
+    fn test_output_without_sizes() {
+        let stat = BenchStat {
+            stat_rx_bytes_tot: 4096,
+            stat_tx_bytes_tot: 4096,
+            ..Default::default()
+        };
+        stat.output(0.0);
+    }
+
+    #[test]
+    fn test_json_lines_sink() {
+        let sink = JsonLinesSink::new(Vec::new());
+        sink.record(&record()).unwrap();
+        let out = String::from_utf8(sink.w.into_inner().unwrap()).unwrap();
+        assert_eq!(
+            out,
+            "{\"thread_id\":2,\"timestamp_ms\":1234,\"rx_gbps\":1.5,\"tx_gbps\":0.25,\
+             \"re_tx\":3,\"rtt_50_us\":4,\"rtt_99_us\":null,\"rpc_50_us\":10,\
+             \"rpc_99_us\":20.5,\"rpc_999_us\":null,\"rate_gbps\":12.5}\n"
+        );
+    }
+
+    #[test]
+    fn test_csv_sink() {
+        let sink = CsvSink::new(Vec::new()).unwrap();
+        sink.record(&record()).unwrap();
+        let out = String::from_utf8(sink.w.into_inner().unwrap()).unwrap();
+        assert_eq!(
+            out,
+            "thread_id,timestamp_ms,rx_gbps,tx_gbps,re_tx,rtt_50_us,rtt_99_us,\
+             rpc_50_us,rpc_99_us,rpc_999_us,rate_gbps\n\
+             2,1234,1.5,0.25,3,4,NaN,10,20.5,inf,12.5\n"
+        );
+    }
+}
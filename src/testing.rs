@@ -0,0 +1,312 @@
+// Copyright (c) 2023, IOMesh Inc. All rights reserved.
+
+//! Unit testing for service handlers, enabled by the `loopback` feature.
+//!
+//! A [`ServiceTester`] plays the server's poll thread for a [`Service`]: it
+//! hands a request to the method's handler and captures what it responds,
+//! without an [`Environment`](crate::prelude::Environment) or any eRPC
+//! memory.
+//!
+//! ```ignore
+//! #[tokio::test]
+//! async fn say_hello() {
+//!     let mut tester = ServiceTester::new(create_greeter::<GreeterService>());
+//!     let req = HelloRequest { name: "world".into() };
+//!     let outcome = tester.call(&METHOD_GREETER_SAY_HELLO, &req).await.unwrap();
+//!     assert_eq!(outcome.unwrap().message, "Hello world");
+//! }
+//! ```
+
+use std::{
+    any::Any,
+    collections::HashMap,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use async_channel::unbounded;
+use erpc_sys::erpc::ReqHandle as RawReqHandle;
+
+use crate::{
+    buf::MsgBufferReader,
+    call::RpcCall,
+    error::{Error, Result},
+    method::Method,
+    nexus::Nexus,
+    pool::MsgBufferPool,
+    req_handle::ReqHandle,
+    rpc::Rpc,
+    server::{BoxHandler, Service},
+};
+
+/// The request buffer tried first, doubled up to eRPC's max message size
+/// while the request doesn't fit.
+const MIN_REQ_SIZE: usize = 4096;
+
+static NEXT_URI: AtomicUsize = AtomicUsize::new(0);
+
+/// How a handler finished.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome<T> {
+    /// The handler responded.
+    Response(T),
+    /// The handler returned without responding.
+    NoResponse,
+    /// The handler panicked, with the panic message.
+    Panicked(String),
+}
+
+impl<T> Outcome<T> {
+    /// Returns the response.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the handler didn't respond.
+    pub fn unwrap(self) -> T {
+        match self {
+            Outcome::Response(resp) => resp,
+            Outcome::NoResponse => panic!("the handler didn't respond"),
+            Outcome::Panicked(msg) => panic!("the handler panicked: {msg}"),
+        }
+    }
+
+    pub fn map<U, F: FnOnce(T) -> U>(self, f: F) -> Outcome<U> {
+        match self {
+            Outcome::Response(resp) => Outcome::Response(f(resp)),
+            Outcome::NoResponse => Outcome::NoResponse,
+            Outcome::Panicked(msg) => Outcome::Panicked(msg),
+        }
+    }
+}
+
+/// Invokes the handlers of a [`Service`] one request at a time.
+///
+/// Buffer allocations and responses are served like on a poll thread.
+/// Outbound calls made by a handler fail, which usually shows up as
+/// [`Outcome::Panicked`].
+pub struct ServiceTester {
+    registry: HashMap<u8, BoxHandler>,
+    pool: MsgBufferPool,
+    // Dropped before the nexus.
+    rpc: Rpc,
+    _nexus: Nexus,
+}
+
+impl ServiceTester {
+    pub fn new(service: Service) -> ServiceTester {
+        let uri = format!("testing:{}", NEXT_URI.fetch_add(1, Ordering::Relaxed));
+        let nexus = Nexus::new(&uri, 0);
+        let rpc = Rpc::new(&nexus, None, 0, None, 0);
+        ServiceTester {
+            registry: service.handlers,
+            pool: MsgBufferPool::default(),
+            rpc,
+            _nexus: nexus,
+        }
+    }
+
+    /// Call `method` with `req` and decode the response.
+    ///
+    /// Must be called within a Tokio runtime, e.g. from `#[tokio::test]`.
+    pub async fn call<Req, Resp>(
+        &mut self,
+        method: &Method<Req, Resp>,
+        req: &Req,
+    ) -> Result<Outcome<Resp>> {
+        let mut size = MIN_REQ_SIZE;
+        let mut req_msgbuf = self.pool.alloc(&mut self.rpc, size)?;
+        while let Err(e) = (method.req_ser())(req, &mut req_msgbuf) {
+            let max_size = self.rpc.get_max_msg_size();
+            if !matches!(e, Error::Codec(_)) || size >= max_size {
+                return Err(e);
+            }
+            size = (size * 2).min(max_size);
+            req_msgbuf = self.pool.alloc(&mut self.rpc, size)?;
+        }
+        let req = req_msgbuf.as_slice().to_vec();
+        drop(req_msgbuf);
+        match self.call_raw(method.id, &req).await? {
+            Outcome::Response(resp) => {
+                let mut resp_msgbuf = self.pool.alloc(&mut self.rpc, resp.len())?;
+                resp_msgbuf.as_mut_slice().copy_from_slice(&resp);
+                let reader = unsafe { MsgBufferReader::new(resp_msgbuf.as_inner()) };
+                (method.resp_de())(reader).map(Outcome::Response)
+            }
+            Outcome::NoResponse => Ok(Outcome::NoResponse),
+            Outcome::Panicked(msg) => Ok(Outcome::Panicked(msg)),
+        }
+    }
+
+    /// Call the handler of `method_id` with the serialized request `req`,
+    /// returns the serialized response.
+    ///
+    /// Must be called within a Tokio runtime, e.g. from `#[tokio::test]`.
+    pub async fn call_raw(&mut self, method_id: u8, req: &[u8]) -> Result<Outcome<Vec<u8>>> {
+        let handler = self
+            .registry
+            .get_mut(&method_id)
+            .ok_or_else(|| Error::Internal(format!("no handler for method {method_id}")))?;
+        let raw = Box::into_raw(Box::new(RawReqHandle::detached(req)));
+        let (tx, rx) = unbounded();
+        let task = tokio::spawn(handler.handle(ReqHandle::from_inner_raw(raw), tx));
+
+        // The channel closes once the handler and everything it spawned
+        // have dropped their senders.
+        let mut resp = None;
+        while let Ok(call) = rx.recv().await {
+            match call {
+                RpcCall::Alloc(req) => req.resolve(&mut self.rpc, &mut self.pool),
+                RpcCall::CallTag(mut tag) if resp.is_none() => {
+                    let data = match tag.resp_msgbuf.as_ref() {
+                        Some(buf) => buf.as_slice().to_vec(),
+                        None => tag.req_handle.get_dyn_resp_msgbuf().as_slice().to_vec(),
+                    };
+                    tag.resolve(&mut self.rpc);
+                    resp = Some(data);
+                }
+                // A request is only responded to once, and there's no
+                // session to call out or sample.
+                RpcCall::CallTag(_)
+                | RpcCall::Call(_)
+                | RpcCall::Stats(_)
+                | RpcCall::Latency(_)
+                | RpcCall::Settle(_) => {}
+            }
+        }
+        let res = task.await;
+        if resp.is_none() {
+            // Release the request, the handler is done with it.
+            let mut req_handle = ReqHandle::from_inner_raw(raw);
+            let mut pre_resp_msgbuf = req_handle.get_pre_resp_msgbuf();
+            self.rpc
+                .enqueue_response(&mut req_handle, &mut pre_resp_msgbuf);
+        }
+        Ok(match (res, resp) {
+            (Err(e), _) if e.is_panic() => Outcome::Panicked(panic_message(e.into_panic())),
+            (_, Some(resp)) => Outcome::Response(resp),
+            (_, None) => Outcome::NoResponse,
+        })
+    }
+}
+
+impl Drop for ServiceTester {
+    fn drop(&mut self) {
+        self.pool.release(&mut self.rpc);
+    }
+}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(msg) => *msg,
+        Err(payload) => match payload.downcast::<&'static str>() {
+            Ok(msg) => msg.to_string(),
+            Err(_) => "Box<dyn Any>".to_owned(),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_channel::Sender;
+    use erpc_sys::c_void;
+
+    use super::*;
+    use crate::{
+        call::{CallTag, Codec},
+        codec::{
+            pr_codec::{de as pr_de, ser as pr_ser},
+            Marshaller,
+        },
+        pool::MsgBufferAllocator,
+        server::ServiceBuilder,
+    };
+
+    const METHOD_ECHO: Method<String, String> = Method {
+        id: 1,
+        name: "/test.Echo/Echo",
+        req_mar: Marshaller {
+            ser: pr_ser,
+            de: pr_de,
+            trace_trailer: true,
+        },
+        resp_mar: Marshaller {
+            ser: pr_ser,
+            de: pr_de,
+            trace_trailer: true,
+        },
+    };
+
+    async fn echo(mut req: ReqHandle, tx: Sender<RpcCall>, codec: Codec<String, String>) {
+        let msg = (codec.de)(unsafe { MsgBufferReader::new(req.get_req_msgbuf()) }).unwrap();
+        match msg.as_str() {
+            "" => {}
+            "panic" => panic!("asked to panic"),
+            _ => {
+                let mut resp_msgbuf = MsgBufferAllocator::new(tx.clone())
+                    .alloc(msg.len() + 16)
+                    .await
+                    .unwrap();
+                (codec.ser)(&msg, &mut resp_msgbuf).unwrap();
+                tx.send(RpcCall::CallTag(CallTag::with_resp_msgbuf(
+                    req,
+                    resp_msgbuf,
+                )))
+                .await
+                .unwrap();
+            }
+        }
+    }
+
+    /// Never called, the tester invokes the async handler directly.
+    extern "C" fn echo_wrapper(_req: *mut RawReqHandle, _ctx: *mut c_void) {
+        unreachable!()
+    }
+
+    fn tester() -> ServiceTester {
+        ServiceTester::new(
+            ServiceBuilder::new()
+                .add_unary_handler(
+                    &METHOD_ECHO,
+                    |req, tx, codec| Box::pin(echo(req, tx, codec)),
+                    echo_wrapper,
+                )
+                .build(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_response() {
+        let mut tester = tester();
+        let outcome = tester.call(&METHOD_ECHO, &"hello".to_owned()).await;
+        assert_eq!(outcome.unwrap(), Outcome::Response("hello".to_owned()));
+        // Larger than the first request buffer.
+        let large = "x".repeat(3 * MIN_REQ_SIZE);
+        let outcome = tester.call(&METHOD_ECHO, &large).await;
+        assert_eq!(outcome.unwrap(), Outcome::Response(large));
+    }
+
+    #[tokio::test]
+    async fn test_no_response() {
+        let mut tester = tester();
+        let outcome = tester.call(&METHOD_ECHO, &String::new()).await;
+        assert_eq!(outcome.unwrap(), Outcome::NoResponse);
+    }
+
+    #[tokio::test]
+    async fn test_panic() {
+        let mut tester = tester();
+        let outcome = tester.call(&METHOD_ECHO, &"panic".to_owned()).await;
+        assert_eq!(
+            outcome.unwrap(),
+            Outcome::Panicked("asked to panic".to_owned())
+        );
+        // The tester is usable after a panic.
+        let outcome = tester.call(&METHOD_ECHO, &"again".to_owned()).await;
+        assert_eq!(outcome.unwrap(), Outcome::Response("again".to_owned()));
+    }
+
+    #[tokio::test]
+    async fn test_unknown_method() {
+        let mut tester = tester();
+        assert!(tester.call_raw(2, &[]).await.is_err());
+    }
+}
@@ -3,10 +3,14 @@
 use std::pin::Pin;
 
 use erpc_sys::{
-    erpc::{wheel_record_t, TimingWheel as RawTimingWheel},
+    c_int,
+    erpc::{to_usec, wheel_record_t, TimingWheel as RawTimingWheel},
+    erpc_wrapper::{wheel_record_abs_tx_tsc, wheel_record_is_insert, wheel_record_pkt_num},
     CxxVector,
 };
 
+use crate::{histogram::Histogram, rpc::Rpc};
+
 pub struct TimingWheel {
     inner: *mut RawTimingWheel,
 }
@@ -17,9 +21,19 @@ impl TimingWheel {
         TimingWheel { inner: raw }
     }
 
+    /// Returns the records of the wheel, `None` if pacing is disabled.
     #[inline]
-    pub fn get_record_vec(&mut self) -> Pin<&mut CxxVector<wheel_record_t>> {
-        self.as_inner_mut().get_record_vec()
+    pub fn get_record_vec(&mut self) -> Option<Pin<&mut CxxVector<wheel_record_t>>> {
+        self.as_inner_mut().map(|wheel| wheel.get_record_vec())
+    }
+
+    /// Iterate over the actions recorded by the wheel, oldest first.
+    ///
+    /// eRPC only records them when built with `kWheelRecord`, otherwise and
+    /// when pacing is disabled the iterator is empty.
+    pub fn records(&mut self) -> WheelRecords<'_> {
+        let vec = self.get_record_vec().map(|v| v.into_ref().get_ref());
+        WheelRecords { vec, pos: 0 }
     }
 
     #[inline]
@@ -27,8 +41,157 @@ impl TimingWheel {
         self.inner.is_null()
     }
 
+    /// Returns the wheel, `None` if pacing is disabled.
     #[inline]
-    pub fn as_inner_mut(&mut self) -> Pin<&mut RawTimingWheel> {
-        unsafe { Pin::new_unchecked(&mut *self.inner) }
+    pub fn as_inner_mut(&mut self) -> Option<Pin<&mut RawTimingWheel>> {
+        unsafe { self.inner.as_mut().map(|wheel| Pin::new_unchecked(wheel)) }
+    }
+}
+
+/// What the wheel did with a packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WheelEvent {
+    /// The packet was inserted, to be sent at TSC `tx_tsc`.
+    Insert { tx_tsc: usize },
+    /// The packet was moved from the wheel to the ready queue.
+    Reap,
+}
+
+/// An action of the timing wheel, see [`TimingWheel::records`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WheelRecord {
+    pub pkt_num: usize,
+    pub event: WheelEvent,
+}
+
+impl From<&wheel_record_t> for WheelRecord {
+    fn from(record: &wheel_record_t) -> Self {
+        let event = if wheel_record_is_insert(record) {
+            WheelEvent::Insert {
+                tx_tsc: wheel_record_abs_tx_tsc(record),
+            }
+        } else {
+            WheelEvent::Reap
+        };
+        WheelRecord {
+            pkt_num: wheel_record_pkt_num(record),
+            event,
+        }
+    }
+}
+
+/// The iterator returned by [`TimingWheel::records`].
+pub struct WheelRecords<'a> {
+    vec: Option<&'a CxxVector<wheel_record_t>>,
+    pos: usize,
+}
+
+impl Iterator for WheelRecords<'_> {
+    type Item = WheelRecord;
+
+    fn next(&mut self) -> Option<WheelRecord> {
+        let record = self.vec?.get(self.pos)?;
+        self.pos += 1;
+        Some(record.into())
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let n = self.vec.map_or(0, |v| v.len() - self.pos);
+        (n, Some(n))
+    }
+}
+
+impl ExactSizeIterator for WheelRecords<'_> {}
+
+/// How Timely paces one session.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SessionPacing {
+    pub session_num: c_int,
+    pub rate_gbps: f64,
+    /// `rate_gbps` as a fraction of the link bandwidth.
+    pub link_share: f64,
+    pub re_tx: usize,
+}
+
+/// Pacing diagnostics of one [`Rpc`], to check that Timely rate limiting
+/// behaves as expected, e.g. that the rates of the sessions of an incast
+/// converge to fair shares of the link.
+///
+/// The wheel counters come from [`TimingWheel::records`]. eRPC doesn't
+/// record which session a packet belongs to, so they're totals over all
+/// sessions.
+#[derive(Debug, Clone)]
+pub struct PacingReport {
+    pub sessions: Vec<SessionPacing>,
+    /// Packets inserted into the wheel.
+    pub inserted: u64,
+    /// Packets moved from the wheel to the ready queue.
+    pub reaped: u64,
+    /// Packets in the wheel after the last record.
+    pub occupancy: u64,
+    pub max_occupancy: u64,
+    /// The gap in nanoseconds between the TX times of consecutively inserted
+    /// packets, i.e. how far apart pacing spreads them. Packets of different
+    /// sessions may be interleaved.
+    pub tx_gap: Histogram,
+}
+
+impl PacingReport {
+    /// Collect the report of `rpc` for `sessions`. Must be called on the
+    /// thread owning `rpc`. Sessions which aren't client sessions, e.g. have
+    /// been destroyed, are skipped.
+    pub fn collect(rpc: &mut Rpc, sessions: &[c_int]) -> PacingReport {
+        let bandwidth_gbps = rpc.get_bandwidth() as f64 * 8.0 / 1e9;
+        let sessions = sessions
+            .iter()
+            .filter_map(|&sid| {
+                if !rpc.is_client_session(sid) {
+                    return None;
+                }
+                let rate_gbps = rpc.get_timely(sid).get_rate_gbps();
+                Some(SessionPacing {
+                    session_num: sid,
+                    rate_gbps,
+                    link_share: if bandwidth_gbps > 0.0 {
+                        rate_gbps / bandwidth_gbps
+                    } else {
+                        0.0
+                    },
+                    re_tx: rpc.get_num_re_tx(sid),
+                })
+            })
+            .collect();
+
+        let freq_ghz = rpc.get_freq_ghz();
+        let mut report = PacingReport {
+            sessions,
+            inserted: 0,
+            reaped: 0,
+            occupancy: 0,
+            max_occupancy: 0,
+            tx_gap: Histogram::new(),
+        };
+        let mut last_tx_tsc = None;
+        for record in rpc.get_wheel().records() {
+            match record.event {
+                WheelEvent::Insert { tx_tsc } => {
+                    report.inserted += 1;
+                    report.occupancy += 1;
+                    report.max_occupancy = report.max_occupancy.max(report.occupancy);
+                    if let Some(last) = last_tx_tsc {
+                        let gap = usize::saturating_sub(tx_tsc, last);
+                        report
+                            .tx_gap
+                            .record((to_usec(gap, freq_ghz) * 1000.0) as u64);
+                    }
+                    last_tx_tsc = Some(tx_tsc);
+                }
+                WheelEvent::Reap => {
+                    report.reaped += 1;
+                    report.occupancy = report.occupancy.saturating_sub(1);
+                }
+            }
+        }
+        report
     }
 }
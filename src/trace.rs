@@ -0,0 +1,152 @@
+// Copyright (c) 2023, IOMesh Inc. All rights reserved.
+
+use std::{
+    cell::Cell,
+    collections::hash_map::RandomState,
+    future::Future,
+    hash::{BuildHasher, Hasher},
+    io::{self, Write},
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// The request trailer is an unknown protobuf field, which is skipped by
+/// servers that don't look for it: the key of field 2^29 - 1 with wire type
+/// 2, a length of 16, then the trace and span ids in little endian. It's
+/// only sent for methods whose request marshaller opts in with
+/// [`Marshaller::trace_trailer`](crate::prelude::Marshaller::trace_trailer).
+const TRAILER_PREFIX: [u8; 6] = [0xfa, 0xff, 0xff, 0xff, 0x0f, 0x10];
+pub(crate) const TRAILER_LEN: usize = TRAILER_PREFIX.len() + 16;
+
+thread_local! {
+    static CURRENT: Cell<Option<TraceContext>> = const { Cell::new(None) };
+}
+
+/// Identifies an RPC across hops, so the spans of the client and the server
+/// can be joined by `trace_id`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TraceContext {
+    pub trace_id: u64,
+    pub span_id: u64,
+}
+
+impl TraceContext {
+    /// Returns the context of the request handler running on this thread.
+    pub fn current() -> Option<TraceContext> {
+        CURRENT.with(Cell::get)
+    }
+
+    /// A new span in the current trace, or in a new trace if there's none.
+    pub(crate) fn child() -> TraceContext {
+        TraceContext {
+            trace_id: TraceContext::current().map_or_else(random_id, |c| c.trace_id),
+            span_id: random_id(),
+        }
+    }
+
+    /// A new span in the same trace as `self`.
+    pub(crate) fn child_of(&self) -> TraceContext {
+        TraceContext {
+            trace_id: self.trace_id,
+            span_id: random_id(),
+        }
+    }
+
+    /// Append the trailer parsed by [`TraceContext::from_trailer`].
+    pub(crate) fn write_trailer<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let mut trailer = [0u8; TRAILER_LEN];
+        trailer[..6].copy_from_slice(&TRAILER_PREFIX);
+        trailer[6..14].copy_from_slice(&self.trace_id.to_le_bytes());
+        trailer[14..].copy_from_slice(&self.span_id.to_le_bytes());
+        w.write_all(&trailer)
+    }
+
+    /// Parse the trailer written by [`TraceContext::write_trailer`] at the end
+    /// of a request, if there's one.
+    pub(crate) fn from_trailer(buf: &[u8]) -> Option<TraceContext> {
+        let trailer = buf.get(buf.len().checked_sub(TRAILER_LEN)?..)?;
+        if trailer[..6] != TRAILER_PREFIX {
+            return None;
+        }
+        Some(TraceContext {
+            trace_id: u64::from_le_bytes(trailer[6..14].try_into().unwrap()),
+            span_id: u64::from_le_bytes(trailer[14..].try_into().unwrap()),
+        })
+    }
+}
+
+fn random_id() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
+
+/// Makes `ctx` the current [`TraceContext`] while `inner` is polled.
+pub(crate) struct Scoped<F> {
+    inner: F,
+    ctx: Option<TraceContext>,
+}
+
+impl<F> Scoped<F> {
+    pub(crate) fn new(inner: F, ctx: Option<TraceContext>) -> Self {
+        Scoped { inner, ctx }
+    }
+}
+
+impl<F: Future> Future for Scoped<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+        let ctx = self.ctx;
+        let prev = CURRENT.with(|c| c.replace(ctx));
+        // `inner` is never moved out of `self`.
+        let res = unsafe { self.map_unchecked_mut(|s| &mut s.inner) }.poll(cx);
+        CURRENT.with(|c| c.set(prev));
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::pr_codec;
+
+    const CTX: TraceContext = TraceContext {
+        trace_id: 0x0123_4567_89ab_cdef,
+        span_id: u64::MAX,
+    };
+
+    #[test]
+    fn test_trailer() {
+        let msg = "hello erpc".to_string();
+        let mut buf = Vec::new();
+        pr_codec::ser(&msg, &mut buf).unwrap();
+        let len = buf.len();
+        CTX.write_trailer(&mut buf).unwrap();
+        assert_eq!(buf.len(), len + TRAILER_LEN);
+        assert_eq!(TraceContext::from_trailer(&buf), Some(CTX));
+        // Protobuf decoders skip it.
+        let decoded: String = pr_codec::de(buf.as_slice()).unwrap();
+        assert_eq!(decoded, msg);
+
+        // It's the whole request for an empty message.
+        let mut buf = Vec::new();
+        CTX.write_trailer(&mut buf).unwrap();
+        assert_eq!(TraceContext::from_trailer(&buf), Some(CTX));
+        assert_eq!(pr_codec::de::<String, _>(buf.as_slice()).unwrap(), "");
+    }
+
+    #[test]
+    fn test_no_trailer() {
+        assert_eq!(TraceContext::from_trailer(&[]), None);
+        assert_eq!(TraceContext::from_trailer(&TRAILER_PREFIX), None);
+
+        let mut buf = Vec::new();
+        pr_codec::ser(&"x".repeat(64), &mut buf).unwrap();
+        assert_eq!(TraceContext::from_trailer(&buf), None);
+
+        // A trailer that isn't at the end doesn't count.
+        let mut buf = Vec::new();
+        CTX.write_trailer(&mut buf).unwrap();
+        buf.push(0);
+        assert_eq!(TraceContext::from_trailer(&buf), None);
+    }
+}
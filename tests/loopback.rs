@@ -0,0 +1,520 @@
+// Copyright (c) 2023, IOMesh Inc. All rights reserved.
+
+//! End-to-end tests of clients and servers on the loopback backend.
+
+#[cfg(not(feature = "bench_stat"))]
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::{
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use async_channel::Sender;
+#[cfg(not(feature = "bench_stat"))]
+use erpc_rs::sim::{Fault, FaultKind};
+use erpc_rs::{prelude::*, sim::SimBuilder};
+use tokio::sync::{Mutex, MutexGuard};
+
+const PHY_PORT: u8 = 0;
+const MSG_SIZE: usize = 1024;
+
+const METHOD_ECHO: Method<String, String> = Method {
+    id: 1,
+    name: "/test.Echo/Echo",
+    req_mar: Marshaller {
+        ser: pr_ser,
+        de: pr_de,
+        trace_trailer: true,
+    },
+    resp_mar: Marshaller {
+        ser: pr_ser,
+        de: pr_de,
+        trace_trailer: true,
+    },
+};
+
+/// The simulation and the virtual clock are global, so the tests run one
+/// at a time.
+async fn serial() -> MutexGuard<'static, ()> {
+    static SERIAL: Mutex<()> = Mutex::const_new(());
+    SERIAL.lock().await
+}
+
+/// Like [`METHOD_ECHO`], for clients whose requests never carry a trace
+/// context.
+const METHOD_ECHO_UNTRACED: Method<String, String> = Method {
+    id: 1,
+    name: "/test.Echo/Echo",
+    req_mar: Marshaller {
+        ser: pr_ser,
+        de: pr_de,
+        trace_trailer: false,
+    },
+    resp_mar: Marshaller {
+        ser: pr_ser,
+        de: pr_de,
+        trace_trailer: false,
+    },
+};
+
+async fn echo(mut req: ReqHandle, tx: Sender<RpcCall>, codec: Codec<String, String>) {
+    let msg = (codec.de)(unsafe { MsgBufferReader::new(req.get_req_msgbuf()) }).unwrap();
+    // Tells whether the request carried a trace context.
+    let msg = match msg.as_str() {
+        "traced?" => TraceContext::current().is_some().to_string(),
+        _ => msg,
+    };
+    let mut resp_msgbuf = MsgBufferAllocator::new(tx.clone())
+        .alloc(MSG_SIZE)
+        .await
+        .unwrap();
+    (codec.ser)(&msg, &mut resp_msgbuf).unwrap();
+    tx.send(RpcCall::CallTag(CallTag::with_resp_msgbuf(
+        req,
+        resp_msgbuf,
+    )))
+    .await
+    .unwrap();
+}
+
+extern "C" fn echo_wrapper(req: *mut RawReqHandle, ctx: *mut c_void) {
+    let req = ReqHandle::from_inner_raw(req);
+    unsafe {
+        ServerRpcContext::run_handler(ctx, |ctx| {
+            let tx = ctx.tx.clone();
+            let f = ctx.get_handler(METHOD_ECHO.id).unwrap().handle(req, tx);
+            ctx.spawn(f);
+        });
+    }
+}
+
+fn create_echo() -> Service {
+    ServiceBuilder::new()
+        .add_unary_handler(
+            &METHOD_ECHO,
+            |req, tx, codec| Box::pin(echo(req, tx, codec)),
+            echo_wrapper,
+        )
+        .build()
+}
+
+/// Like [`echo`], taking a while on a background thread.
+async fn slow_echo(mut req: ReqHandle, tx: Sender<RpcCall>, codec: Codec<String, String>) {
+    thread::sleep(Duration::from_millis(20));
+    let msg = (codec.de)(unsafe { MsgBufferReader::new(req.get_req_msgbuf()) }).unwrap();
+    // The server may have shut down meanwhile.
+    let resp_msgbuf = match MsgBufferAllocator::new(tx.clone()).alloc(MSG_SIZE).await {
+        Ok(mut buf) => {
+            (codec.ser)(&msg, &mut buf).unwrap();
+            buf
+        }
+        Err(_) => return,
+    };
+    let _ = tx
+        .send(RpcCall::CallTag(CallTag::with_resp_msgbuf(
+            req,
+            resp_msgbuf,
+        )))
+        .await;
+}
+
+extern "C" fn slow_echo_wrapper(req: *mut RawReqHandle, ctx: *mut c_void) {
+    let req = ReqHandle::from_inner_raw(req);
+    unsafe {
+        ServerRpcContext::run_bg_handler(ctx, METHOD_ECHO.id, req);
+    }
+}
+
+async fn panicking_echo(_req: ReqHandle, _tx: Sender<RpcCall>, _codec: Codec<String, String>) {
+    panic!("asked to panic");
+}
+
+/// Passes the requests to a background handler which isn't registered.
+extern "C" fn missing_wrapper(req: *mut RawReqHandle, ctx: *mut c_void) {
+    let req = ReqHandle::from_inner_raw(req);
+    unsafe {
+        ServerRpcContext::run_bg_handler(ctx, METHOD_ECHO.id + 1, req);
+    }
+}
+
+extern "C" fn cont_func(_ctx: *mut c_void, tag: *mut c_void) {
+    let tag = unsafe { Box::from_raw(tag as *mut Tag) };
+    let reader = unsafe { MsgBufferReader::new(tag.resp_msgbuf()) };
+    tag.tx.send_blocking(reader).unwrap();
+}
+
+fn env(uri: &str) -> EnvBuilder {
+    EnvBuilder::new(uri).chan_count(1)
+}
+
+async fn start_server(env: EnvBuilder) -> Server {
+    ServerBuilder::new(Arc::new(env.build()), PHY_PORT, 0)
+        .register_service(create_echo())
+        .build_and_start()
+        .await
+        .unwrap()
+}
+
+async fn connect(env: EnvBuilder, server_uri: &str) -> Result<Channel> {
+    ChannelBuilder::new(Arc::new(env.build()), PHY_PORT)
+        .subchan_count(1)
+        .connect_timeout_ms(100)
+        .connect(server_uri)
+        .await
+}
+
+async fn call(client: &Client, msg: &str) -> Result<String> {
+    let req_msgbuf = SharedMsgBuffer::new(client.alloc_msg_buffer(MSG_SIZE).await?);
+    let resp_msgbuf = SharedMsgBuffer::new(client.alloc_msg_buffer(MSG_SIZE).await?);
+    call_with(client, msg, req_msgbuf, resp_msgbuf).await
+}
+
+async fn call_with(
+    client: &Client,
+    msg: &str,
+    req_msgbuf: SharedMsgBuffer,
+    resp_msgbuf: SharedMsgBuffer,
+) -> Result<String> {
+    call_method(client, &METHOD_ECHO, msg, req_msgbuf, resp_msgbuf).await
+}
+
+async fn call_method(
+    client: &Client,
+    method: &Method<String, String>,
+    msg: &str,
+    req_msgbuf: SharedMsgBuffer,
+    resp_msgbuf: SharedMsgBuffer,
+) -> Result<String> {
+    client
+        .unary_call(method, &msg.to_owned(), req_msgbuf, resp_msgbuf, cont_func)
+        .await
+}
+
+#[tokio::test]
+async fn test_unary_call() {
+    let _serial = serial().await;
+    let mut server = start_server(env("localhost:32100")).await;
+    let mut ch = connect(env("localhost:32101"), "localhost:32100")
+        .await
+        .unwrap();
+    let client = Client::new(ch.clone());
+
+    for msg in ["hello", "loopback", ""] {
+        assert_eq!(call(&client, msg).await.unwrap(), msg);
+    }
+    let stats = client.session_stats().await.unwrap();
+    assert!(stats.connected);
+    assert_eq!(stats.outstanding, 0);
+    assert_eq!(ch.latency().await.unwrap().all.count(), 3);
+    assert!(format!("{ch:?}").starts_with("Channel { subchans: [0], "));
+
+    ch.shutdown().await.unwrap();
+    server.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_shared_buffers() {
+    let _serial = serial().await;
+    let mut server = start_server(env("localhost:32160")).await;
+    let mut ch = connect(env("localhost:32161"), "localhost:32160")
+        .await
+        .unwrap();
+    let client = Client::new(ch.clone());
+    let req_msgbuf = SharedMsgBuffer::new(client.alloc_msg_buffer(MSG_SIZE).await.unwrap());
+    let resp_msgbuf = SharedMsgBuffer::new(client.alloc_msg_buffer(MSG_SIZE).await.unwrap());
+    let echo = |msg| call_with(&client, msg, req_msgbuf.clone(), resp_msgbuf.clone());
+
+    // The buffers are leased by one call at a time, then released.
+    let lease = resp_msgbuf.try_lease().unwrap();
+    assert!(resp_msgbuf.try_lease().is_none());
+    assert!(matches!(echo("busy").await, Err(Error::Internal(_))));
+    assert!(!req_msgbuf.is_leased());
+    drop(lease);
+    for msg in ["hello", "again"] {
+        assert_eq!(echo(msg).await.unwrap(), msg);
+        assert!(!req_msgbuf.is_leased() && !resp_msgbuf.is_leased());
+    }
+    let resp = resp_msgbuf.try_lease().unwrap();
+    assert_eq!(pr_de::<String, _>(resp.as_slice()).unwrap(), "again");
+    drop(resp);
+
+    ch.shutdown().await.unwrap();
+    server.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_trace_context() {
+    let _serial = serial().await;
+    let mut server = start_server(env("localhost:32162")).await;
+    let traced = |propagate, uri| async move {
+        let ch = ChannelBuilder::new(Arc::new(env(uri).build()), PHY_PORT)
+            .subchan_count(1)
+            .connect_timeout_ms(100)
+            .propagate_trace_context(propagate)
+            .connect("localhost:32162")
+            .await
+            .unwrap();
+        let client = Client::new(ch.clone());
+        let mut res = vec![];
+        for method in [&METHOD_ECHO, &METHOD_ECHO_UNTRACED] {
+            let req_msgbuf = SharedMsgBuffer::new(client.alloc_msg_buffer(MSG_SIZE).await.unwrap());
+            let resp_msgbuf =
+                SharedMsgBuffer::new(client.alloc_msg_buffer(MSG_SIZE).await.unwrap());
+            let resp = call_method(&client, method, "traced?", req_msgbuf, resp_msgbuf);
+            res.push(resp.await.unwrap());
+        }
+        (ch, client, res)
+    };
+
+    // Only methods which opt in carry the trailer.
+    let (mut ch, _, res) = traced(false, "localhost:32163").await;
+    assert_eq!(res, ["false", "false"]);
+    ch.shutdown().await.unwrap();
+    let (mut ch, client, res) = traced(true, "localhost:32164").await;
+    assert_eq!(res, ["true", "false"]);
+
+    // The trailer fits in a buffer filled up to the requested size, and is
+    // skipped by the decoder.
+    const TRAILER_LEN: usize = 22;
+    let msg = "x".repeat(MSG_SIZE - 3);
+    let req_msgbuf = client.alloc_msg_buffer(MSG_SIZE).await.unwrap();
+    assert_eq!(req_msgbuf.get_data_size(), MSG_SIZE);
+    assert!(req_msgbuf.get_max_data_size() >= MSG_SIZE + TRAILER_LEN);
+    let req_msgbuf = SharedMsgBuffer::new(req_msgbuf);
+    let resp_msgbuf = SharedMsgBuffer::new(client.alloc_msg_buffer(MSG_SIZE).await.unwrap());
+    assert_eq!(
+        call_with(&client, &msg, req_msgbuf.clone(), resp_msgbuf)
+            .await
+            .unwrap(),
+        msg
+    );
+    assert_eq!(
+        req_msgbuf.try_lease().unwrap().get_data_size(),
+        MSG_SIZE + TRAILER_LEN
+    );
+
+    ch.shutdown().await.unwrap();
+    server.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_connect_failure() {
+    let _serial = serial().await;
+    // Nothing listens on the URI.
+    let err = connect(env("localhost:32111"), "localhost:32110")
+        .await
+        .unwrap_err();
+    assert!(matches!(err, Error::InvalidUri(_)), "{err:?}");
+
+    // The poll thread is handed back after the failure and connects again.
+    let mut server = start_server(env("localhost:32110")).await;
+    let env = Arc::new(env("localhost:32112").build());
+    let builder = || ChannelBuilder::new(env.clone(), PHY_PORT).subchan_count(1);
+    assert!(builder().connect("localhost:32113").await.is_err());
+    let mut ch = builder().connect("localhost:32110").await.unwrap();
+
+    ch.shutdown().await.unwrap();
+    server.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_connect_timeout() {
+    let _serial = serial().await;
+    let sim = SimBuilder::new(1)
+        .auto_advance(Duration::from_micros(100))
+        .install();
+    let mut server = start_server(env("localhost:32120")).await;
+    sim.stall("localhost:32120", Duration::from_secs(1));
+
+    let err = connect(env("localhost:32121"), "localhost:32120")
+        .await
+        .unwrap_err();
+    assert!(matches!(err, Error::Timeout(_)), "{err:?}");
+    assert!(err.is_retryable());
+
+    server.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_server_reset() {
+    let _serial = serial().await;
+    let sim = SimBuilder::new(2)
+        .auto_advance(Duration::from_micros(100))
+        .install();
+    let mut server = start_server(env("localhost:32130")).await;
+    let mut ch = connect(env("localhost:32131"), "localhost:32130")
+        .await
+        .unwrap();
+    let client = Client::new(ch.clone());
+    assert_eq!(call(&client, "before").await.unwrap(), "before");
+
+    sim.reset_sessions("localhost:32130");
+    while client.session_stats().await.unwrap().connected {
+        tokio::task::yield_now().await;
+    }
+    let err = call(&client, "after").await.unwrap_err();
+    assert!(matches!(err, Error::Disconnected(_)), "{err:?}");
+
+    ch.shutdown().await.unwrap();
+    server.shutdown().await.unwrap();
+}
+
+/// Echo a few messages over a lossy link. The clock only moves to the next
+/// delivery once nothing is ready, so the faults don't depend on timing.
+#[cfg(not(feature = "bench_stat"))]
+async fn lossy_echo(seed: u64) -> (Vec<Fault>, usize) {
+    let sim = Arc::new(
+        SimBuilder::new(seed)
+            .latency(Duration::from_micros(5), Duration::from_micros(2))
+            .loss(0.3)
+            .rto(Duration::from_micros(50))
+            .install(),
+    );
+    let stop = Arc::new(AtomicBool::new(false));
+    let driver = {
+        let (sim, stop) = (sim.clone(), stop.clone());
+        thread::spawn(move || {
+            while !stop.load(Ordering::Acquire) {
+                sim.advance_to_next_event();
+                thread::sleep(Duration::from_micros(100));
+            }
+        })
+    };
+
+    // The URIs are part of the links' seeds, wait for the previous run's
+    // poll threads to release them.
+    let (stopped_tx, stopped_rx) = async_channel::unbounded();
+    let stopped = move || {
+        let _ = stopped_tx.send_blocking(());
+    };
+    let mut server = start_server(env("localhost:32140").before_stop(stopped.clone())).await;
+    let mut ch = connect(
+        env("localhost:32141").before_stop(stopped),
+        "localhost:32140",
+    )
+    .await
+    .unwrap();
+    let client = Client::new(ch.clone());
+    for i in 0..20 {
+        let msg = i.to_string();
+        assert_eq!(call(&client, &msg).await.unwrap(), msg);
+    }
+    let re_tx = client.session_stats().await.unwrap().re_tx;
+    let faults = sim.faults();
+    ch.shutdown().await.unwrap();
+    server.shutdown().await.unwrap();
+    drop((client, ch, server));
+    for _ in 0..2 {
+        stopped_rx.recv().await.unwrap();
+    }
+
+    stop.store(true, Ordering::Release);
+    driver.join().unwrap();
+    (faults, re_tx)
+}
+
+// With `bench_stat` the poll thread resets the retransmission counter every
+// stats interval, so the session's count doesn't add up to the losses.
+#[cfg(not(feature = "bench_stat"))]
+#[tokio::test]
+async fn test_seed_replay() {
+    let _serial = serial().await;
+    let (faults, re_tx) = lossy_echo(3).await;
+    assert!(!faults.is_empty());
+    // The first packet on each link is the handshake, whose retransmissions
+    // aren't the session's.
+    let lost: u32 = faults
+        .iter()
+        .map(|f| match f.kind {
+            FaultKind::Loss { re_tx } if f.index > 0 => re_tx,
+            _ => 0,
+        })
+        .sum();
+    assert_eq!(re_tx, lost as usize);
+
+    assert_eq!(lossy_echo(3).await, (faults, re_tx));
+}
+
+#[tokio::test]
+async fn test_bg_shutdown() {
+    let _serial = serial().await;
+    let service = ServiceBuilder::new()
+        .add_unary_bg_handler(
+            &METHOD_ECHO,
+            |req, tx, codec| Box::pin(slow_echo(req, tx, codec)),
+            slow_echo_wrapper,
+        )
+        .build();
+    let mut server = ServerBuilder::new(
+        Arc::new(env("localhost:32150").bg_thread_count(1).build()),
+        PHY_PORT,
+        0,
+    )
+    .register_service(service)
+    .build_and_start()
+    .await
+    .unwrap();
+    let mut ch = connect(env("localhost:32151"), "localhost:32150")
+        .await
+        .unwrap();
+    let client = Client::new(ch.clone());
+    assert_eq!(call(&client, "bg").await.unwrap(), "bg");
+
+    // Queue requests behind a running one, the shutdown drops them.
+    let calls: Vec<_> = (0..8)
+        .map(|_| {
+            let client = client.clone();
+            tokio::spawn(async move { call(&client, "queued").await })
+        })
+        .collect();
+    while client.session_stats().await.unwrap().outstanding < 8 {
+        tokio::task::yield_now().await;
+    }
+    let start = Instant::now();
+    server.shutdown().await.unwrap();
+    assert!(start.elapsed() < Duration::from_millis(8 * 20));
+
+    for call in calls {
+        call.abort();
+    }
+    ch.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_bg_failure() {
+    let _serial = serial().await;
+    // Failed requests get an empty response instead of timing out.
+    for (port, raw_handler) in [
+        (32165, slow_echo_wrapper as ReqHandler),
+        (32167, missing_wrapper),
+    ] {
+        let service = ServiceBuilder::new()
+            .add_unary_bg_handler(
+                &METHOD_ECHO,
+                |req, tx, codec| Box::pin(panicking_echo(req, tx, codec)),
+                raw_handler,
+            )
+            .build();
+        let server_uri = format!("localhost:{port}");
+        let mut server = ServerBuilder::new(
+            Arc::new(env(&server_uri).bg_thread_count(1).build()),
+            PHY_PORT,
+            0,
+        )
+        .register_service(service)
+        .build_and_start()
+        .await
+        .unwrap();
+        let mut ch = connect(env(&format!("localhost:{}", port + 1)), &server_uri)
+            .await
+            .unwrap();
+        let client = Client::new(ch.clone());
+        for _ in 0..2 {
+            assert_eq!(call(&client, "failed").await.unwrap(), "");
+        }
+
+        ch.shutdown().await.unwrap();
+        server.shutdown().await.unwrap();
+    }
+}